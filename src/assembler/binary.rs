@@ -0,0 +1,245 @@
+//! Cobble binary container format.
+//!
+//! All multi-byte header fields are little-endian.
+//!
+//! | Offset | Size | Field                                     |
+//! |--------|------|-------------------------------------------|
+//! | 0      | 4    | Magic, `b"CBL\0"`                         |
+//! | 4      | 2    | Format version (currently `2`)            |
+//! | 6      | 2    | Entry point (address of first instr)      |
+//! | 8      | 2    | Code section length `n`, in words         |
+//! | 10     | 2    | Data section length `m`, in bytes         |
+//! | 12     | 3*n  | Code section, 3-byte little-endian words  |
+//! | 12+3*n | m    | Data section, loaded at memory address 0  |
+//!
+//! Version `1` binaries lack the data section and its length field,
+//! and are still accepted.
+
+use thiserror::Error;
+
+use crate::{assembler::encoder::MachineCode, interpreter::state::MEMORY_SIZE};
+
+/// Magic bytes identifying a cobble binary
+pub const MAGIC: [u8; 4] = *b"CBL\0";
+
+/// Current container format version
+pub const VERSION: u16 = 2;
+
+/// Size of the fixed header, in bytes
+pub const HEADER_LEN: usize = 12;
+
+/// Size of the fixed header of version `1` binaries, in bytes
+const HEADER_LEN_V1: usize = 10;
+
+/// Size of a single encoded word, in bytes
+pub const WORD_LEN: usize = 3;
+
+/// Largest number of words addressable by the 12-bit PC
+pub const MAX_WORDS: usize = 0x1000;
+
+#[derive(Debug, Error)]
+pub enum BinaryError {
+    #[error("Missing or invalid magic header")]
+    BadMagic,
+
+    #[error("Unsupported format version: {0}")]
+    UnsupportedVersion(u16),
+
+    #[error("Truncated binary: expected {expected} bytes, found {found}")]
+    Truncated { expected: usize, found: usize },
+
+    #[error("Code section too large: {0} words")]
+    TooLarge(usize),
+
+    #[error("Data section too large: {0} bytes")]
+    DataTooLarge(usize),
+
+    #[error("Entry point {0:#05x} outside code section")]
+    InvalidEntry(u16),
+}
+
+/// An assembled program, ready to be written to disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binary {
+    /// Address of the first instruction to execute
+    pub entry: u16,
+    /// Encoded 24-bit instruction words
+    pub code: Vec<MachineCode>,
+    /// Initial contents of data memory, from address 0
+    pub data: Vec<u8>,
+}
+
+impl Binary {
+    /// Creates a binary with entry point 0 and no data.
+    pub fn new(code: Vec<MachineCode>) -> Self {
+        Self {
+            entry: 0,
+            code,
+            data: Vec::new(),
+        }
+    }
+
+    /// Serializes the binary into its on-disk representation.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, BinaryError> {
+        if self.code.len() > MAX_WORDS {
+            return Err(BinaryError::TooLarge(self.code.len()));
+        }
+        if !self.code.is_empty() && self.entry as usize >= self.code.len() {
+            return Err(BinaryError::InvalidEntry(self.entry));
+        }
+        if self.data.len() > MEMORY_SIZE {
+            return Err(BinaryError::DataTooLarge(self.data.len()));
+        }
+
+        let mut out = Vec::with_capacity(HEADER_LEN + self.code.len() * WORD_LEN + self.data.len());
+        out.extend_from_slice(&MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&self.entry.to_le_bytes());
+        out.extend_from_slice(&(self.code.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(self.data.len() as u16).to_le_bytes());
+        for word in &self.code {
+            out.extend_from_slice(&word.to_le_bytes()[..WORD_LEN]);
+        }
+        out.extend_from_slice(&self.data);
+
+        Ok(out)
+    }
+
+    /// Deserializes a binary from its on-disk representation.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BinaryError> {
+        if !is_binary(bytes) {
+            return Err(BinaryError::BadMagic);
+        }
+        let truncated = |expected: usize| {
+            if bytes.len() < expected {
+                return Err(BinaryError::Truncated {
+                    expected,
+                    found: bytes.len(),
+                });
+            }
+            Ok(())
+        };
+        truncated(HEADER_LEN_V1)?;
+
+        let field = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+        let version = field(4);
+        let header_len = match version {
+            1 => HEADER_LEN_V1,
+            VERSION => HEADER_LEN,
+            _ => return Err(BinaryError::UnsupportedVersion(version)),
+        };
+        truncated(header_len)?;
+
+        let entry = field(6);
+        let len = field(8) as usize;
+        if len > MAX_WORDS {
+            return Err(BinaryError::TooLarge(len));
+        }
+        let data_len = match version {
+            1 => 0,
+            _ => field(10) as usize,
+        };
+        if data_len > MEMORY_SIZE {
+            return Err(BinaryError::DataTooLarge(data_len));
+        }
+
+        let code_end = header_len + len * WORD_LEN;
+        truncated(code_end + data_len)?;
+
+        let code = bytes[header_len..code_end]
+            .chunks_exact(WORD_LEN)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], 0]))
+            .collect();
+        let data = bytes[code_end..code_end + data_len].to_vec();
+
+        Ok(Self { entry, code, data })
+    }
+}
+
+/// Checks whether the given bytes start with the binary magic header.
+#[inline]
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binary_roundtrip() {
+        let bin = Binary {
+            entry: 1,
+            code: vec![0x000001, 0xABCDEF, 0x000000],
+            data: b"hi\0".to_vec(),
+        };
+        let bytes = bin.to_bytes().unwrap();
+        assert_eq!(bytes.len(), HEADER_LEN + 3 * WORD_LEN + 3);
+        assert_eq!(&bytes[..4], b"CBL\0");
+        // Words are stored little-endian
+        assert_eq!(&bytes[15..18], &[0xEF, 0xCD, 0xAB]);
+        // Data follows the code
+        assert_eq!(&bytes[21..], b"hi\0");
+
+        assert_eq!(Binary::from_bytes(&bytes).unwrap(), bin);
+    }
+
+    #[test]
+    fn test_binary_v1() {
+        let mut bytes = b"CBL\0".to_vec();
+        bytes.extend_from_slice(&[1, 0, 0, 0, 1, 0, 0xEF, 0xCD, 0xAB]);
+
+        let bin = Binary::from_bytes(&bytes).unwrap();
+        assert_eq!(bin.code, vec![0xABCDEF]);
+        assert!(bin.data.is_empty());
+    }
+
+    #[test]
+    fn test_binary_errors() {
+        // Not a binary
+        assert!(matches!(
+            Binary::from_bytes(b"addi r1, r0, 1"),
+            Err(BinaryError::BadMagic)
+        ));
+
+        // Unknown version
+        let mut bytes = Binary::new(vec![0]).to_bytes().unwrap();
+        bytes[4] = 0xFF;
+        assert!(matches!(
+            Binary::from_bytes(&bytes),
+            Err(BinaryError::UnsupportedVersion(_))
+        ));
+
+        // Missing code words
+        let bytes = Binary::new(vec![0, 0]).to_bytes().unwrap();
+        assert!(matches!(
+            Binary::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(BinaryError::Truncated { .. })
+        ));
+
+        // Entry point past end of code
+        let bin = Binary {
+            entry: 2,
+            ..Binary::new(vec![0, 0])
+        };
+        assert!(bin.to_bytes().is_err());
+
+        // Data past end of memory
+        let bin = Binary {
+            data: vec![0; MEMORY_SIZE + 1],
+            ..Binary::new(vec![0])
+        };
+        assert!(matches!(bin.to_bytes(), Err(BinaryError::DataTooLarge(_))));
+
+        // Missing data bytes
+        let bin = Binary {
+            data: vec![1, 2],
+            ..Binary::new(vec![0])
+        };
+        let bytes = bin.to_bytes().unwrap();
+        assert!(matches!(
+            Binary::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(BinaryError::Truncated { .. })
+        ));
+    }
+}
@@ -0,0 +1,229 @@
+use thiserror::Error;
+
+use crate::{
+    assembler::{
+        encoder::MachineCode,
+        opcode::{Format, OPCODES},
+    },
+    compiler::ast::*,
+};
+
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("word {0:#08x} is wider than 24 bits")]
+    TooWide(MachineCode),
+
+    #[error("illegal opcode {opcode:#04x} in word {word:#08x}")]
+    IllegalOpcode { word: MachineCode, opcode: u8 },
+
+    #[error(
+        "illegal function (fun2 {fun2}, fun4 {fun4}) for opcode {opcode:#04x} in word {word:#08x}"
+    )]
+    IllegalFunction {
+        word: MachineCode,
+        opcode: u8,
+        fun2: u8,
+        fun4: u8,
+    },
+
+    #[error("reserved field {field} is not zero in word {word:#08x}")]
+    ReservedField {
+        word: MachineCode,
+        field: &'static str,
+    },
+}
+
+/// Extracts `len` bits of a word, starting at bit `shift`
+#[inline]
+fn field(word: MachineCode, shift: u8, len: u8) -> u32 {
+    (word >> shift) & ((1 << len) - 1)
+}
+
+/// Decodes a single 24-bit word back into an instruction.
+///
+/// Encodings shared by several instructions decode to the most specific
+/// form: `addi r0, r0, 0` becomes `nop`, any other `addi rd, rs1, 0`
+/// becomes `mv rd, rs1`, and `sub r0, rs1, rs2` becomes `cmp rs1, rs2`.
+pub fn decode(word: MachineCode) -> Result<Instr, DecodeError> {
+    if word > 0xFFFFFF {
+        return Err(DecodeError::TooWide(word));
+    }
+
+    let opcode = field(word, 0, 6) as u8;
+    let fun2 = field(word, 6, 2) as u8;
+    let fun4 = field(word, 20, 4) as u8;
+    let rd = field(word, 8, 4) as u8;
+    let rs1 = field(word, 12, 4) as u8;
+    let rs2 = field(word, 16, 4) as u8;
+
+    // Find opcode map entry
+    let mut candidates = OPCODES.iter().filter(|d| d.opcode == opcode).peekable();
+    if candidates.peek().is_none() {
+        return Err(DecodeError::IllegalOpcode { word, opcode });
+    }
+    let def = candidates
+        .find(|d| d.fun2 == fun2 && (!d.format.has_fun4() || d.fun4 == fun4))
+        .ok_or(DecodeError::IllegalFunction {
+            word,
+            opcode,
+            fun2,
+            fun4,
+        })?;
+
+    let reserved = |field| Err(DecodeError::ReservedField { word, field });
+    let ops = match def.format {
+        Format::N => {
+            if word >> 8 != 0 {
+                return reserved("operands");
+            }
+            vec![]
+        }
+        Format::R1 => {
+            if rs1 != 0 || rs2 != 0 {
+                return reserved("rs1/rs2");
+            }
+            vec![Op::Reg(rd)]
+        }
+        Format::R2 => {
+            if rs2 != 0 {
+                return reserved("rs2");
+            }
+            vec![Op::Reg(rd), Op::Reg(rs1)]
+        }
+        Format::R3 => vec![Op::Reg(rd), Op::Reg(rs1), Op::Reg(rs2)],
+        Format::I => vec![
+            Op::Reg(rd),
+            Op::Reg(rs1),
+            Op::Imm8(field(word, 16, 8) as u8),
+        ],
+        Format::J => {
+            if rd != 0 {
+                return reserved("rd");
+            }
+            vec![Op::Imm12(field(word, 12, 12) as u16)]
+        }
+    };
+
+    let instr = match (def.mnemonic, ops.as_slice()) {
+        ("addi", [Op::Reg(0), Op::Reg(0), Op::Imm8(0)]) => Instr::Nop,
+        ("addi", [rd, rs1, Op::Imm8(0)]) => Instr::Mv {
+            rd: rd.clone(),
+            rs1: rs1.clone(),
+        },
+        ("sub", [Op::Reg(0), rs1, rs2]) => Instr::Cmp {
+            rs1: rs1.clone(),
+            rs2: rs2.clone(),
+        },
+        _ => Instr::from_parts(def.mnemonic, ops)
+            .expect("opcode map entries should match instruction operands"),
+    };
+
+    Ok(instr)
+}
+
+/// Decodes a sequence of words into a program.
+pub fn decode_program(code: &[MachineCode]) -> Result<Program, DecodeError> {
+    code.iter().map(|w| decode(*w)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler::encoder::encode, compiler::parser::parse_program};
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(0x000000).unwrap(), Instr::Halt);
+        assert_eq!(decode(0x000001).unwrap(), Instr::Nop);
+        assert_eq!(
+            decode(0xABC049).unwrap(),
+            Instr::Bnz {
+                imm: Op::Imm12(0xABC)
+            }
+        );
+        assert_eq!(
+            decode(0x132104).unwrap(),
+            Instr::Or {
+                rd: Op::Reg(1),
+                rs1: Op::Reg(2),
+                rs2: Op::Reg(3),
+            }
+        );
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        // More than 24 bits
+        assert!(matches!(decode(0x1000000), Err(DecodeError::TooWide(_))));
+
+        // Unassigned opcode
+        assert!(matches!(
+            decode(0x00003F),
+            Err(DecodeError::IllegalOpcode { opcode: 0x3F, .. })
+        ));
+
+        // Unassigned fun4 under the logic opcode
+        assert!(matches!(
+            decode(0xF32104),
+            Err(DecodeError::IllegalFunction { fun4: 0xF, .. })
+        ));
+
+        // Unassigned fun2 under the stack opcode
+        assert!(matches!(
+            decode(0x0000C7),
+            Err(DecodeError::IllegalFunction { fun2: 3, .. })
+        ));
+
+        // Halt with stray operand bits
+        assert!(matches!(
+            decode(0x000100),
+            Err(DecodeError::ReservedField { .. })
+        ));
+
+        // Jump with a register set
+        assert!(matches!(
+            decode(0xABC108),
+            Err(DecodeError::ReservedField { field: "rd", .. })
+        ));
+    }
+
+    #[test]
+    fn test_encode_decode_roundtrip() {
+        let src = "halt\nnop\nmv r1, r2\nnot r15, r14\n\
+                   add r1, r2, r3\nsub r4, r5, r6\nand r7, r8, r9\n\
+                   or r10, r11, r12\nxor r13, r14, r15\ncmp r1, r2\n\
+                   shl r1, r2, r3\nshr r1, r2, r3\nsar r1, r2, r3\nrol r1, r2, r3\nror r1, r2, r3\n\
+                   shli r1, r2, 1\nshri r1, r2, 2\nsari r1, r2, 3\nroli r1, r2, 4\nrori r1, r2, 5\n\
+                   addi r1, r0, 255\nandi r2, r3, 0x0f\nori r4, r5, 1\nxori r6, r7, 0x80\n\
+                   lb r8, 0(r9)\nsb r10, 0xff(r11)\n\
+                   push r12\npop r13\ncall 0x123\nret\n\
+                   jmp 0\nbz 0xfff\nbnz 42\nbc 1\nbnc 2\nbn 3\nbv 4\nblt 5\nbge 6";
+        for instr in parse_program(src).unwrap() {
+            let word = encode(&instr).unwrap();
+            assert_eq!(decode(word).unwrap(), instr, "decoding {:#08x}", word);
+        }
+    }
+
+    #[test]
+    fn test_decode_encode_property() {
+        // Every word that decodes must re-encode to itself
+        let mut decoded = 0;
+        for word in (0..=0xFFFFFFu32).step_by(97) {
+            if let Ok(instr) = decode(word) {
+                assert_eq!(encode(&instr).unwrap(), word, "re-encoding {}", instr);
+                decoded += 1;
+            }
+        }
+        assert!(decoded > 0);
+
+        // Every register combination survives a round trip
+        for (rd, rs1, rs2) in (0..16).flat_map(|a| (0..16).map(move |b| (a, b, (a + b) % 16))) {
+            let instr = Instr::Xor {
+                rd: Op::Reg(rd),
+                rs1: Op::Reg(rs1),
+                rs2: Op::Reg(rs2),
+            };
+            assert_eq!(decode(encode(&instr).unwrap()).unwrap(), instr);
+        }
+    }
+}
@@ -1,18 +1,9 @@
-use std::panic;
-
 use thiserror::Error;
 
-use crate::compiler::ast::*;
-
-macro_rules! make_instr {
-    ($op:expr, $( $field:ident => $val:expr ),* ) => {{
-        let mut b = InstrBuilder::new().opcode($op);
-        $(
-            b = b.$field($val);
-        )*
-        b.finalize()
-    }};
-}
+use crate::{
+    assembler::opcode::{self, Format},
+    compiler::ast::*,
+};
 
 /// A 24‑bit instruction builder.
 ///
@@ -31,6 +22,12 @@ impl InstrBuilder {
         Self { word: 0 }
     }
 
+    /// Start from an already encoded word, to change some of its fields
+    #[inline]
+    pub fn from_word(word: MachineCode) -> Self {
+        Self { word }
+    }
+
     /// Set a 6‑bit opcode (bits 0‑5)
     #[inline]
     pub fn opcode(mut self, op: u8) -> Self {
@@ -48,7 +45,7 @@ impl InstrBuilder {
     /// Set a 4‑bit fun4 (bits 20‑23)
     #[inline]
     pub fn fun4(mut self, fun: u8) -> Self {
-        self.word = (self.word & !0xF0000) | (((fun as u32) & 0x0F) << 20);
+        self.word = (self.word & !0xF00000) | (((fun as u32) & 0x0F) << 20);
         self
     }
 
@@ -111,7 +108,7 @@ pub enum AsmError {
     #[error("invalid register: {0}")]
     InvalidRegister(String),
 
-    #[error("invalid operand: {0:?}")]
+    #[error("invalid operands: {0}")]
     InvalidOperand(String),
 
     #[error("overflow in immediate: {0}")]
@@ -127,60 +124,193 @@ pub fn encode_program(instrs: &[Instr]) -> Result<Vec<MachineCode>, AsmError> {
     Ok(out)
 }
 
-fn encode(instr: &Instr) -> Result<MachineCode, AsmError> {
-    match instr {
+/// Checks that a register address fits the 4-bit register fields
+#[inline]
+fn reg(r: u8) -> Result<u8, AsmError> {
+    match r {
+        0..=15 => Ok(r),
+        _ => Err(AsmError::InvalidRegister(format!("r{}", r))),
+    }
+}
+
+/// Encodes a single instruction into a 24-bit word, per the opcode map
+/// in [`opcode`].
+pub fn encode(instr: &Instr) -> Result<MachineCode, AsmError> {
+    // Aliases without opcodes of their own
+    let alias;
+    let instr = match instr {
         Instr::Label(_) => panic!("Cannot encode labels"),
-        Instr::Halt => Ok(0),
-        Instr::Addi { rd, rs1, imm } => match (rd, rs1, imm) {
-            (Op::Reg(rd), Op::Reg(rs1), Op::Imm8(imm)) => {
-                Ok(make_instr!(0b000001, rd => *rd, rs1 => *rs1, imm8 => *imm))
-            }
-            _ => Err(AsmError::InvalidOperand("".to_string())),
-        },
-        Instr::Mv { rd, rs1 } => match (rd, rs1) {
-            (Op::Reg(rd), Op::Reg(rs1)) => Ok(make_instr!(0b000001, rd => *rd, rs1 => *rs1)),
-            _ => Err(AsmError::InvalidOperand("".to_string())),
-        },
-        Instr::Nop => Ok(make_instr!(0b000001, rd => 0, rs1 => 0)),
-        Instr::Add { rd, rs1, rs2 } => match (rd, rs1, rs2) {
-            (Op::Reg(rd), Op::Reg(rs1), Op::Reg(rs2)) => {
-                Ok(make_instr!(0b000010, rd => *rd, rs1 => *rs1, rs2 => *rs2))
-            }
-            _ => Err(AsmError::InvalidOperand("".to_string())),
-        },
-        Instr::Sub { rd, rs1, rs2 } => match (rd, rs1, rs2) {
-            (Op::Reg(rd), Op::Reg(rs1), Op::Reg(rs2)) => {
-                Ok(make_instr!(0b000011, rd => *rd, rs1 => *rs1, rs2 => *rs2))
-            }
-            _ => Err(AsmError::InvalidOperand("".to_string())),
-        },
-        Instr::Jmp { imm: target } => {
-            match &target {
-                Op::Imm12(_) => {
-                    // Direct address
-                    Ok(0x0)
-                }
-                _ => Err(AsmError::InvalidOperand("".to_string())),
+        Instr::Directive(_) => panic!("Cannot encode directives"),
+        Instr::Nop => {
+            alias = Instr::Addi {
+                rd: Op::Reg(0),
+                rs1: Op::Reg(0),
+                imm: Op::Imm8(0),
+            };
+            &alias
+        }
+        Instr::Mv { rd, rs1 } => {
+            alias = Instr::Addi {
+                rd: rd.clone(),
+                rs1: rs1.clone(),
+                imm: Op::Imm8(0),
+            };
+            &alias
+        }
+        Instr::Cmp { rs1, rs2 } => {
+            alias = Instr::Sub {
+                rd: Op::Reg(0),
+                rs1: rs1.clone(),
+                rs2: rs2.clone(),
+            };
+            &alias
+        }
+        Instr::Bltu { imm } => {
+            alias = Instr::Bc { imm: imm.clone() };
+            &alias
+        }
+        Instr::Bgeu { imm } => {
+            alias = Instr::Bnc { imm: imm.clone() };
+            &alias
+        }
+        _ => instr,
+    };
+
+    let def = opcode::lookup(instr.mnemonic())
+        .ok_or_else(|| AsmError::UnknownInstruction(instr.clone()))?;
+    let b = InstrBuilder::new().opcode(def.opcode).fun2(def.fun2);
+
+    let b = match (def.format, instr.operands().as_slice()) {
+        (Format::N, []) => b,
+        (Format::R1, [Op::Reg(rd)]) => b.fun4(def.fun4).rd(reg(*rd)?),
+        (Format::R2, [Op::Reg(rd), Op::Reg(rs1)]) => b.fun4(def.fun4).rd(reg(*rd)?).rs1(reg(*rs1)?),
+        (Format::R3, [Op::Reg(rd), Op::Reg(rs1), Op::Reg(rs2)]) => b
+            .fun4(def.fun4)
+            .rd(reg(*rd)?)
+            .rs1(reg(*rs1)?)
+            .rs2(reg(*rs2)?),
+        (Format::I, [Op::Reg(rd), Op::Reg(rs1), Op::Imm8(imm)]) => {
+            b.rd(reg(*rd)?).rs1(reg(*rs1)?).imm8(*imm)
+        }
+        (Format::J, [Op::Imm12(imm)]) => {
+            if *imm > 0xFFF {
+                return Err(AsmError::ImmOverflow(*imm));
             }
+            b.imm12(*imm)
         }
-        _ => Err(AsmError::UnknownInstruction(instr.clone())),
-    }
+        (_, [.., Op::Label(l)]) => return Err(AsmError::UndefinedLabel(l.clone())),
+        _ => return Err(AsmError::InvalidOperand(instr.to_string())),
+    };
+
+    Ok(b.finalize())
 }
 
-#[test]
-fn test_encode() {
-    // Halt instruction (all 0's)
-    assert_eq!(encode(&Instr::Halt).unwrap(), 0);
-
-    // Basic addi r0, r0, 0
-    let code = encode(&Instr::Addi {
-        rd: Op::Reg(0),
-        rs1: Op::Reg(0),
-        imm: Op::Imm8(0),
-    })
-    .unwrap();
-    assert_eq!(code, 0b00000000_0000_0000_00_000001);
-
-    // Nop instruction (equal to addi r0, r0, 0)
-    assert_eq!(encode(&Instr::Nop).unwrap(), code)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parser::parse_program;
+
+    #[test]
+    fn test_encode() {
+        // Halt instruction (all 0's)
+        assert_eq!(encode(&Instr::Halt).unwrap(), 0);
+
+        // Basic addi r0, r0, 0
+        let code = encode(&Instr::Addi {
+            rd: Op::Reg(0),
+            rs1: Op::Reg(0),
+            imm: Op::Imm8(0),
+        })
+        .unwrap();
+        #[allow(clippy::unusual_byte_groupings)]
+        let expected = 0b00000000_0000_0000_00_000001;
+        assert_eq!(code, expected);
+
+        // Nop instruction (equal to addi r0, r0, 0)
+        assert_eq!(encode(&Instr::Nop).unwrap(), code)
+    }
+
+    #[test]
+    fn test_encode_all() {
+        // One entry per instruction, with fields laid out as
+        // fun4/imm | rs2 | rs1 | rd | fun2:opcode
+        let cases = [
+            ("halt", 0x000000),
+            ("nop", 0x000001),
+            ("mv r1, r2", 0x002101),
+            ("not r1, r2", 0x302104),
+            ("add r1, r2, r3", 0x032102),
+            ("sub r1, r2, r3", 0x032103),
+            ("and r1, r2, r3", 0x032104),
+            ("or r1, r2, r3", 0x132104),
+            ("xor r1, r2, r3", 0x232104),
+            ("shl r1, r2, r3", 0x03210B),
+            ("shr r1, r2, r3", 0x13210B),
+            ("sar r1, r2, r3", 0x23210B),
+            ("rol r1, r2, r3", 0x33210B),
+            ("ror r1, r2, r3", 0x43210B),
+            ("cmp r2, r3", 0x032003),
+            ("addi r1, r2, 0xab", 0xAB2101),
+            ("andi r1, r2, 0xab", 0xAB2105),
+            ("ori r1, r2, 0xab", 0xAB2145),
+            ("xori r1, r2, 0xab", 0xAB2185),
+            ("shli r1, r2, 3", 0x03210C),
+            ("shri r1, r2, 3", 0x03214C),
+            ("sari r1, r2, 3", 0x03218C),
+            ("roli r1, r2, 3", 0x0321CC),
+            ("rori r1, r2, 3", 0x03210D),
+            ("lb r1, 0xab(r2)", 0xAB2106),
+            ("sb r1, 0xab(r2)", 0xAB2146),
+            ("push r1", 0x000107),
+            ("pop r1", 0x000147),
+            ("ret", 0x000087),
+            ("jmp 0xabc", 0xABC008),
+            ("call 0xabc", 0xABC048),
+            ("bz 0xabc", 0xABC009),
+            ("bnz 0xabc", 0xABC049),
+            ("bc 0xabc", 0xABC089),
+            ("bnc 0xabc", 0xABC0C9),
+            ("bn 0xabc", 0xABC00A),
+            ("bv 0xabc", 0xABC04A),
+            ("blt 0xabc", 0xABC08A),
+            ("bge 0xabc", 0xABC0CA),
+            ("bltu 0xabc", 0xABC089),
+            ("bgeu 0xabc", 0xABC0C9),
+        ];
+
+        for (src, word) in cases {
+            let prg = parse_program(src).unwrap();
+            assert_eq!(encode(&prg[0]).unwrap(), word, "encoding {}", src);
+        }
+    }
+
+    #[test]
+    fn test_encode_errors() {
+        // Unresolved label
+        let instr = Instr::Jmp {
+            imm: Op::Label("loop".to_string()),
+        };
+        assert!(matches!(encode(&instr), Err(AsmError::UndefinedLabel(_))));
+
+        // Jump target out of 12-bit range
+        let instr = Instr::Bz {
+            imm: Op::Imm12(0x1000),
+        };
+        assert!(matches!(encode(&instr), Err(AsmError::ImmOverflow(_))));
+
+        // Register out of range
+        let instr = Instr::Not {
+            rd: Op::Reg(16),
+            rs1: Op::Reg(0),
+        };
+        assert!(matches!(encode(&instr), Err(AsmError::InvalidRegister(_))));
+
+        // Wrong operand kind
+        let instr = Instr::Add {
+            rd: Op::Reg(1),
+            rs1: Op::Reg(2),
+            rs2: Op::Imm8(3),
+        };
+        assert!(matches!(encode(&instr), Err(AsmError::InvalidOperand(_))));
+    }
 }
@@ -0,0 +1,101 @@
+//! Memory images of assembled code, for loading into program memory
+//! outside of cobble, i.e. FPGA soft cores or Logisim circuits.
+//!
+//! Images only hold code, starting at address 0.
+
+use crate::assembler::{binary::WORD_LEN, encoder::MachineCode};
+
+/// Number of data bytes in each Intel HEX record, a whole number of words
+const HEX_RECORD_LEN: usize = 8 * WORD_LEN;
+
+/// Number of words on each line of a Logisim image
+const LOGISIM_LINE_LEN: usize = 8;
+
+/// Raw image, with each word stored as 3 little-endian bytes
+pub fn raw(code: &[MachineCode]) -> Vec<u8> {
+    code.iter()
+        .flat_map(|word| word.to_le_bytes()[..WORD_LEN].to_vec())
+        .collect()
+}
+
+/// Intel HEX image of the raw image, addressed in bytes
+pub fn intel_hex(code: &[MachineCode]) -> String {
+    let record = |kind: u8, address: u16, data: &[u8]| {
+        let mut bytes = vec![data.len() as u8];
+        bytes.extend(address.to_be_bytes());
+        bytes.push(kind);
+        bytes.extend(data);
+        // Checksum makes the sum of all bytes zero
+        let sum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        bytes.push(sum.wrapping_neg());
+
+        let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        format!(":{}\n", hex)
+    };
+
+    let mut out = String::new();
+    for (i, chunk) in raw(code).chunks(HEX_RECORD_LEN).enumerate() {
+        out += &record(0x00, (i * HEX_RECORD_LEN) as u16, chunk);
+    }
+    out += &record(0x01, 0, &[]);
+    out
+}
+
+/// Image for Verilog's `$readmemh`, with one word per line
+pub fn readmemh(code: &[MachineCode]) -> String {
+    code.iter().map(|word| format!("{:06x}\n", word)).collect()
+}
+
+/// Logisim "v2.0 raw" image, where runs of 4 or more equal words
+/// are written as `count*word`, as Logisim does
+pub fn logisim(code: &[MachineCode]) -> String {
+    let mut items = Vec::new();
+    let mut rest = code;
+    while let Some(word) = rest.first() {
+        let run = rest.iter().take_while(|w| *w == word).count();
+        match run {
+            4.. => items.push(format!("{}*{:x}", run, word)),
+            _ => items.extend(rest[..run].iter().map(|w| format!("{:x}", w))),
+        }
+        rest = &rest[run..];
+    }
+
+    let mut out = "v2.0 raw\n".to_string();
+    for line in items.chunks(LOGISIM_LINE_LEN) {
+        out += &line.join(" ");
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_images() {
+        let code = [0x030101, 0xff1101, 0x001049, 0, 0, 0, 0, 0x000087];
+
+        assert_eq!(raw(&code[..2]), [0x01, 0x01, 0x03, 0x01, 0x11, 0xff]);
+
+        assert_eq!(
+            intel_hex(&code[..2]),
+            ":060000000101030111FFE4\n:00000001FF\n"
+        );
+        // Records hold 8 words each, addressed in bytes
+        let hex = intel_hex(&[0; 9]);
+        let lines: Vec<&str> = hex.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with(":18000000"));
+        assert_eq!(lines[1], ":03001800000000E5");
+
+        assert_eq!(readmemh(&code[..3]), "030101\nff1101\n001049\n");
+
+        assert_eq!(logisim(&code), "v2.0 raw\n30101 ff1101 1049 4*0 87\n");
+        assert_eq!(
+            logisim(&[1, 2, 3, 0, 0, 0, 4, 5, 6, 7, 8]),
+            "v2.0 raw\n1 2 3 0 0 0 4 5\n6 7 8\n"
+        );
+        assert_eq!(logisim(&[]), "v2.0 raw\n");
+    }
+}
@@ -0,0 +1,406 @@
+//! Memory layouts, describing the regions of both address spaces and
+//! which region each section is placed in. Layouts are shared by the
+//! linker, which places sections at the start of their regions, and by
+//! the interpreter, which only fetches code from ROM regions and only
+//! maps the other regions in the data space.
+//!
+//! A layout is written one entry per line, with `;` or `#` comments:
+//!
+//! ```text
+//! ; kind  name   start  size
+//! rom     flash  0x000  0x1000
+//! ram     ram    0x000  0x180
+//! mmio    io     0x180  0x40
+//! stack   stack  0x1c0  0x40
+//!
+//! ; section  region
+//! text       flash
+//! data       ram
+//! ```
+//!
+//! ROM regions lie in the code space, addressed in words by the PC. All
+//! other regions lie in the data space, addressed in bytes. The text
+//! section is placed in a ROM region and the data section in a RAM one,
+//! by default the first of each. There is at most one stack region,
+//! which the stack grows down from the top of.
+
+use thiserror::Error;
+
+use crate::compiler::symbol::Section;
+
+/// Layout used when none is given, matching the memory of the interpreter
+pub const DEFAULT_LAYOUT: &str = "\
+rom   rom    0x000  0x1000
+ram   ram    0x000  0x1c0
+stack stack  0x1c0  0x40
+";
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum LayoutError {
+    #[error("line {line}: {message}")]
+    Syntax { line: usize, message: String },
+
+    #[error("line {line}: region `{name}` is defined twice")]
+    DuplicateRegion { line: usize, name: String },
+
+    #[error("line {line}: region `{name}` ends at {end:#x}, past the {space} space of {size:#x}")]
+    OutOfSpace {
+        line: usize,
+        name: String,
+        end: usize,
+        space: &'static str,
+        size: usize,
+    },
+
+    #[error("line {line}: region `{name}` overlaps region `{other}`")]
+    Overlap {
+        line: usize,
+        name: String,
+        other: String,
+    },
+
+    #[error("line {line}: only one stack region may be defined")]
+    DuplicateStack { line: usize },
+
+    #[error("line {line}: no region named `{name}`")]
+    UnknownRegion { line: usize, name: String },
+
+    #[error("line {line}: the {section} section cannot be placed in {kind} region `{name}`")]
+    WrongRegion {
+        line: usize,
+        section: &'static str,
+        kind: &'static str,
+        name: String,
+    },
+
+    #[error("no {kind} region to place the {section} section in")]
+    Unplaced {
+        section: &'static str,
+        kind: &'static str,
+    },
+}
+
+/// Kinds of memory regions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Rom,
+    Ram,
+    Stack,
+    Mmio,
+}
+
+impl RegionKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Rom => "rom",
+            Self::Ram => "ram",
+            Self::Stack => "stack",
+            Self::Mmio => "mmio",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [Self::Rom, Self::Ram, Self::Stack, Self::Mmio]
+            .into_iter()
+            .find(|kind| kind.name() == name)
+    }
+
+    /// Section whose address space the region lies in
+    pub fn space(&self) -> Section {
+        match self {
+            Self::Rom => Section::Text,
+            _ => Section::Data,
+        }
+    }
+
+    /// Kind of region a given section is placed in
+    fn holding(section: Section) -> Self {
+        match section {
+            Section::Text => Self::Rom,
+            Section::Data => Self::Ram,
+        }
+    }
+}
+
+/// A named range of addresses in one of the address spaces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub name: String,
+    pub kind: RegionKind,
+    pub start: u16,
+    /// Size in words for ROM regions, in bytes otherwise
+    pub size: usize,
+}
+
+impl Region {
+    /// Address just past the end of the region
+    pub fn end(&self) -> usize {
+        self.start as usize + self.size
+    }
+
+    pub fn contains(&self, addr: u16) -> bool {
+        (self.start as usize..self.end()).contains(&(addr as usize))
+    }
+}
+
+/// Regions of memory, and where each section is placed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    pub regions: Vec<Region>,
+    /// Index of the region holding the text section
+    text: usize,
+    /// Index of the region holding the data section
+    data: usize,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self::parse(DEFAULT_LAYOUT).expect("default layout should be valid")
+    }
+}
+
+impl Layout {
+    /// Parses a layout from its textual description.
+    /// Returns the first error found, if any.
+    pub fn parse(src: &str) -> Result<Self, LayoutError> {
+        let mut regions: Vec<Region> = Vec::new();
+        let (mut text, mut data) = (None, None);
+
+        for (i, line) in src.lines().enumerate() {
+            let line_no = i + 1;
+            let syntax = |message: String| LayoutError::Syntax {
+                line: line_no,
+                message,
+            };
+            let words: Vec<&str> = line
+                .split([';', '#'])
+                .next()
+                .unwrap_or_default()
+                .split_whitespace()
+                .collect();
+            let Some((&keyword, args)) = words.split_first() else {
+                continue;
+            };
+
+            if let Some(kind) = RegionKind::from_name(keyword) {
+                let [name, start, size] = args else {
+                    return Err(syntax(format!(
+                        "expected `{} <name> <start> <size>`",
+                        keyword
+                    )));
+                };
+                let number = |s: &str| {
+                    parse_number(s).ok_or_else(|| syntax(format!("invalid number `{}`", s)))
+                };
+                let (start, size) = (number(start)?, number(size)?);
+
+                if regions.iter().any(|r| r.name == *name) {
+                    return Err(LayoutError::DuplicateRegion {
+                        line: line_no,
+                        name: name.to_string(),
+                    });
+                }
+                let space = kind.space();
+                if start.saturating_add(size) > space.size() {
+                    return Err(LayoutError::OutOfSpace {
+                        line: line_no,
+                        name: name.to_string(),
+                        end: start.saturating_add(size),
+                        space: space.name(),
+                        size: space.size(),
+                    });
+                }
+                let region = Region {
+                    name: name.to_string(),
+                    kind,
+                    start: start as u16,
+                    size,
+                };
+                if let Some(other) = regions.iter().find(|r| {
+                    r.kind.space() == space
+                        && (r.start as usize) < region.end()
+                        && (region.start as usize) < r.end()
+                }) {
+                    return Err(LayoutError::Overlap {
+                        line: line_no,
+                        name: region.name,
+                        other: other.name.clone(),
+                    });
+                }
+                if kind == RegionKind::Stack && regions.iter().any(|r| r.kind == kind) {
+                    return Err(LayoutError::DuplicateStack { line: line_no });
+                }
+                regions.push(region);
+            } else if let Some(section) = [Section::Text, Section::Data]
+                .into_iter()
+                .find(|s| s.name() == keyword)
+            {
+                let [name] = args else {
+                    return Err(syntax(format!("expected `{} <region>`", keyword)));
+                };
+                let Some(index) = regions.iter().position(|r| r.name == *name) else {
+                    return Err(LayoutError::UnknownRegion {
+                        line: line_no,
+                        name: name.to_string(),
+                    });
+                };
+                if regions[index].kind != RegionKind::holding(section) {
+                    return Err(LayoutError::WrongRegion {
+                        line: line_no,
+                        section: section.name(),
+                        kind: regions[index].kind.name(),
+                        name: name.to_string(),
+                    });
+                }
+                match section {
+                    Section::Text => text = Some(index),
+                    Section::Data => data = Some(index),
+                }
+            } else {
+                return Err(syntax(format!(
+                    "expected a region kind (rom, ram, stack, mmio) or a section (text, data), found `{}`",
+                    keyword
+                )));
+            }
+        }
+
+        // Sections not placed explicitly go in the first region that may hold them
+        let place = |section: Section, placed: Option<usize>| {
+            let kind = RegionKind::holding(section);
+            placed
+                .or_else(|| regions.iter().position(|r| r.kind == kind))
+                .ok_or(LayoutError::Unplaced {
+                    section: section.name(),
+                    kind: kind.name(),
+                })
+        };
+        let text = place(Section::Text, text)?;
+        let data = place(Section::Data, data)?;
+        Ok(Self {
+            regions,
+            text,
+            data,
+        })
+    }
+
+    /// Region a given section is placed in
+    pub fn region(&self, section: Section) -> &Region {
+        match section {
+            Section::Text => &self.regions[self.text],
+            Section::Data => &self.regions[self.data],
+        }
+    }
+
+    /// Region the stack grows down in, if any
+    pub fn stack(&self) -> Option<&Region> {
+        self.regions.iter().find(|r| r.kind == RegionKind::Stack)
+    }
+
+    /// Regions of the data space
+    pub fn data_regions(&self) -> impl Iterator<Item = &Region> {
+        self.regions
+            .iter()
+            .filter(|r| r.kind.space() == Section::Data)
+    }
+}
+
+/// Parses a hex (`0x`), binary (`0b`) or decimal number
+fn parse_number(s: &str) -> Option<usize> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        usize::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = s.strip_prefix("0b") {
+        usize::from_str_radix(bin, 2).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::state::{MEMORY_SIZE, STACK_LIMIT, STACK_TOP};
+
+    #[test]
+    fn test_layout() {
+        // The default layout matches the memory of the interpreter
+        let layout = Layout::default();
+        assert_eq!(layout.region(Section::Text).start, 0);
+        assert_eq!(layout.region(Section::Text).size, Section::Text.size());
+        assert_eq!(layout.region(Section::Data).end(), STACK_LIMIT as usize);
+        let stack = layout.stack().unwrap();
+        assert_eq!(
+            (stack.start, stack.end()),
+            (STACK_LIMIT, STACK_TOP as usize)
+        );
+        assert_eq!(
+            layout.data_regions().map(|r| r.size).sum::<usize>(),
+            MEMORY_SIZE
+        );
+
+        let layout = Layout::parse(
+            "\
+; kind  name   start  size
+rom     boot   0x000  0x10
+rom     flash  0x100  0xf00   # after a gap
+ram     ram    0      128
+mmio    io     0x80   0b1000000
+text    flash
+",
+        )
+        .unwrap();
+        assert_eq!(layout.region(Section::Text).name, "flash");
+        assert_eq!(layout.region(Section::Data).name, "ram");
+        assert_eq!(layout.stack(), None);
+        assert_eq!(
+            layout.data_regions().map(|r| r.end()).collect::<Vec<_>>(),
+            [0x80, 0xc0]
+        );
+        assert!(layout.regions[3].contains(0xbf));
+        assert!(!layout.regions[3].contains(0xc0));
+    }
+
+    #[test]
+    fn test_layout_errors() {
+        let error = |src: &str| Layout::parse(src).unwrap_err().to_string();
+        assert_eq!(
+            error("rom flash 0 0x1000\nram ram 0 0x100\nvram x 0 1\n"),
+            "line 3: expected a region kind (rom, ram, stack, mmio) or a section (text, data), found `vram`"
+        );
+        assert_eq!(
+            error("rom flash 0\n"),
+            "line 1: expected `rom <name> <start> <size>`"
+        );
+        assert_eq!(error("rom flash 0 0x1g\n"), "line 1: invalid number `0x1g`");
+        assert_eq!(
+            error("rom flash 0x800 0x801\n"),
+            "line 1: region `flash` ends at 0x1001, past the text space of 0x1000"
+        );
+        assert_eq!(
+            error("ram a 0 0x80\nmmio b 0x7f 1\n"),
+            "line 2: region `b` overlaps region `a`"
+        );
+        assert_eq!(
+            error("rom a 0 0x80\nram a 0 0x80\n"),
+            "line 2: region `a` is defined twice"
+        );
+        assert_eq!(
+            error("stack a 0 0x10\nstack b 0x10 0x10\n"),
+            "line 2: only one stack region may be defined"
+        );
+        assert_eq!(
+            error("rom flash 0 0x1000\nram ram 0 0x80\ndata sram\n"),
+            "line 3: no region named `sram`"
+        );
+        assert_eq!(
+            error("rom flash 0 0x1000\nmmio io 0 0x80\ndata io\n"),
+            "line 3: the data section cannot be placed in mmio region `io`"
+        );
+        assert_eq!(
+            error("rom flash 0 0x1000\n"),
+            "no ram region to place the data section in"
+        );
+
+        // Code and data lie in separate spaces, so do not overlap
+        assert!(Layout::parse("rom flash 0 0x1000\nram ram 0 0x100\n").is_ok());
+    }
+}
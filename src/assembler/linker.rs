@@ -0,0 +1,383 @@
+use std::{collections::HashMap, fmt};
+
+use thiserror::Error;
+
+use crate::{
+    assembler::{
+        binary::Binary,
+        encoder::InstrBuilder,
+        layout::Layout,
+        object::{Object, RelocKind, Target},
+    },
+    compiler::{
+        expr::fits,
+        symbol::{Section, SymbolTable},
+    },
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum LinkError {
+    #[error("symbol `{symbol}` is defined by both `{first}` and `{second}`")]
+    DuplicateSymbol {
+        symbol: String,
+        first: String,
+        second: String,
+    },
+
+    #[error("undefined symbol `{symbol}`, referenced by `{object}`")]
+    UndefinedSymbol { symbol: String, object: String },
+
+    #[error(
+        "relocation against {target} in `{object}` resolves to {value:#x}, which does not fit in {bits} bits"
+    )]
+    OutOfRange {
+        object: String,
+        target: Target,
+        value: u16,
+        bits: u32,
+    },
+
+    #[error("relocation at {section} offset {offset:#x} lies outside of `{object}`")]
+    BadRelocation {
+        object: String,
+        section: &'static str,
+        offset: u16,
+    },
+
+    #[error(
+        "linked {section} section takes {len:#x}, past the size of region `{region}` ({size:#x})"
+    )]
+    SectionOverflow {
+        section: &'static str,
+        len: usize,
+        region: String,
+        size: usize,
+    },
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Section(section) => write!(f, "the {} section", section.name()),
+            Self::Symbol(symbol) => write!(f, "`{}`", symbol),
+        }
+    }
+}
+
+/// A program linked from objects, along with the symbols they export
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Linked {
+    pub binary: Binary,
+    /// Address (or value) of every exported symbol
+    pub symbols: SymbolTable,
+}
+
+/// Links named objects into a single binary with the default layout.
+/// See [`link_with`].
+pub fn link(objects: &[(&str, Object)]) -> Result<Linked, Vec<LinkError>> {
+    link_with(objects, &Layout::default())
+}
+
+/// Links named objects into a single binary, placing the sections of each
+/// object after those of the objects before it, from the start of the
+/// region the layout places the section in. The binary starts at the
+/// first instruction of the first object.
+///
+/// Every error found along the way is returned.
+pub fn link_with(objects: &[(&str, Object)], layout: &Layout) -> Result<Linked, Vec<LinkError>> {
+    let mut errors = Vec::new();
+
+    // Start of the sections of each object
+    let (text_start, data_start) = (
+        layout.region(Section::Text).start as usize,
+        layout.region(Section::Data).start as usize,
+    );
+    let mut bases = Vec::with_capacity(objects.len());
+    let (mut text, mut data) = (text_start, data_start);
+    for (_, obj) in objects {
+        bases.push((text, data));
+        text += obj.code.len();
+        data += obj.data.len();
+    }
+    for (section, len) in [
+        (Section::Text, text - text_start),
+        (Section::Data, data - data_start),
+    ] {
+        let region = layout.region(section);
+        if len > region.size {
+            errors.push(LinkError::SectionOverflow {
+                section: section.name(),
+                len,
+                region: region.name.clone(),
+                size: region.size,
+            });
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let base = |i: usize, section: Section| match section {
+        Section::Text => bases[i].0 as u16,
+        Section::Data => bases[i].1 as u16,
+    };
+
+    // Exported symbols, at their linked addresses
+    let mut symbols = SymbolTable::new();
+    let mut owners: HashMap<&str, &str> = HashMap::new();
+    for (i, (name, obj)) in objects.iter().enumerate() {
+        for export in &obj.exports {
+            if let Some(first) = owners.get(export.name.as_str()) {
+                errors.push(LinkError::DuplicateSymbol {
+                    symbol: export.name.clone(),
+                    first: first.to_string(),
+                    second: name.to_string(),
+                });
+                continue;
+            }
+            owners.insert(&export.name, name);
+            let value = match export.section {
+                Some(section) => base(i, section).wrapping_add(export.value),
+                None => export.value,
+            };
+            symbols.insert(export.name.clone(), value);
+        }
+    }
+
+    // Sections are loaded from address 0, so are padded up to their regions
+    let mut code = vec![0; text_start];
+    let mut memory = vec![0; data_start];
+    for (i, (name, obj)) in objects.iter().enumerate() {
+        let undefined = |symbol: &str| LinkError::UndefinedSymbol {
+            symbol: symbol.to_string(),
+            object: name.to_string(),
+        };
+        for symbol in &obj.externs {
+            if !symbols.contains_key(symbol) {
+                errors.push(undefined(symbol));
+            }
+        }
+
+        let (mut c, mut d) = (obj.code.clone(), obj.data.clone());
+        for reloc in &obj.relocations {
+            let target = match &reloc.target {
+                Target::Section(section) => base(i, *section),
+                Target::Symbol(symbol) => match symbols.get(symbol) {
+                    Some(address) => *address,
+                    None => {
+                        // Undeclared symbols are not reported above
+                        if !obj.externs.contains(symbol) {
+                            errors.push(undefined(symbol));
+                        }
+                        continue;
+                    }
+                },
+            };
+            let value = target.wrapping_add(reloc.addend);
+            if !fits(value, reloc.kind.bits()) {
+                errors.push(LinkError::OutOfRange {
+                    object: name.to_string(),
+                    target: reloc.target.clone(),
+                    value,
+                    bits: reloc.kind.bits(),
+                });
+                continue;
+            }
+
+            let at = reloc.offset as usize;
+            let len = match reloc.kind {
+                RelocKind::Imm8 | RelocKind::Imm12 => c.len(),
+                RelocKind::Byte => d.len(),
+                RelocKind::Word => d.len().saturating_sub(1),
+            };
+            if at >= len {
+                errors.push(LinkError::BadRelocation {
+                    object: name.to_string(),
+                    section: reloc.kind.section().name(),
+                    offset: reloc.offset,
+                });
+                continue;
+            }
+            match reloc.kind {
+                RelocKind::Imm8 => {
+                    c[at] = InstrBuilder::from_word(c[at]).imm8(value as u8).finalize()
+                }
+                RelocKind::Imm12 => {
+                    c[at] = InstrBuilder::from_word(c[at])
+                        .imm12(value & 0xfff)
+                        .finalize()
+                }
+                RelocKind::Byte => d[at] = value as u8,
+                RelocKind::Word => d[at..at + 2].copy_from_slice(&value.to_le_bytes()),
+            }
+        }
+        code.append(&mut c);
+        memory.append(&mut d);
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(Linked {
+        binary: Binary {
+            entry: text_start as u16,
+            code,
+            data: memory,
+        },
+        symbols,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::object::{Export, Relocation};
+
+    #[test]
+    fn test_link() {
+        // call putc ; jmp 0 (relative to its own code)
+        let main = Object {
+            code: vec![0x000048, 0x000008],
+            data: vec![0, 0],
+            exports: vec![],
+            externs: vec!["putc".to_string()],
+            relocations: vec![
+                Relocation {
+                    kind: RelocKind::Imm12,
+                    offset: 0,
+                    target: Target::Symbol("putc".to_string()),
+                    addend: 0,
+                },
+                Relocation {
+                    kind: RelocKind::Imm12,
+                    offset: 1,
+                    target: Target::Section(Section::Text),
+                    addend: 0,
+                },
+                Relocation {
+                    kind: RelocKind::Word,
+                    offset: 0,
+                    target: Target::Symbol("msg".to_string()),
+                    addend: 1,
+                },
+            ],
+        };
+        // putc: ret
+        let lib = Object {
+            code: vec![0x000087],
+            data: b"hi\0".to_vec(),
+            exports: vec![
+                Export {
+                    name: "putc".to_string(),
+                    section: Some(Section::Text),
+                    value: 0,
+                },
+                Export {
+                    name: "msg".to_string(),
+                    section: Some(Section::Data),
+                    value: 0,
+                },
+            ],
+            externs: vec![],
+            relocations: vec![],
+        };
+
+        let linked = link(&[("main.o", main.clone()), ("lib.o", lib.clone())]).unwrap();
+        assert_eq!(linked.binary.code, vec![0x002048, 0x000008, 0x000087]);
+        assert_eq!(linked.binary.data, vec![0x03, 0x00, b'h', b'i', 0]);
+        assert_eq!(linked.symbols.get("putc"), Some(&2));
+        assert_eq!(linked.symbols.get("msg"), Some(&2));
+
+        // In the other order, the sections of `main` move
+        let linked = link(&[("lib.o", lib.clone()), ("main.o", main.clone())]).unwrap();
+        assert_eq!(linked.binary.code[1..], [0x000048, 0x001008]);
+        assert_eq!(linked.binary.data[3..], [0x01, 0x00]);
+
+        // Sections start at their regions, with the entry point at the start of code
+        let layout =
+            Layout::parse("rom boot 0 0x10\nrom flash 0x10 0x20\nram ram 0x40 0x40\ntext flash\n")
+                .unwrap();
+        let linked =
+            link_with(&[("main.o", main.clone()), ("lib.o", lib.clone())], &layout).unwrap();
+        assert_eq!(linked.binary.entry, 0x10);
+        assert_eq!(linked.binary.code[0x10..], [0x012048, 0x010008, 0x000087]);
+        assert_eq!(linked.binary.data[..0x40], [0; 0x40]);
+        assert_eq!(linked.binary.data[0x40..], [0x43, 0x00, b'h', b'i', 0]);
+        assert_eq!(linked.symbols.get("putc"), Some(&0x12));
+
+        // Missing and duplicate symbols are all reported
+        let errors = link(&[("main.o", main), ("a.o", lib.clone()), ("b.o", lib)]).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0].to_string(),
+            "symbol `putc` is defined by both `a.o` and `b.o`"
+        );
+        let errors = link(&[(
+            "main.o",
+            Object {
+                externs: vec!["putc".to_string()],
+                ..Object::default()
+            },
+        )])
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            [LinkError::UndefinedSymbol {
+                symbol: "putc".to_string(),
+                object: "main.o".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_link_errors() {
+        // addi r1, r0, buf, with `buf` past what 8 bits can hold once linked
+        let obj = |data: usize| Object {
+            code: vec![0x000101],
+            data: vec![0; data],
+            relocations: vec![Relocation {
+                kind: RelocKind::Imm8,
+                offset: 0,
+                target: Target::Section(Section::Data),
+                addend: 0,
+            }],
+            ..Object::default()
+        };
+        let layout = Layout::parse("rom rom 0 0x1000\nram ram 0 0x100\n").unwrap();
+        let objects = [("a.o", obj(0x80)), ("b.o", obj(0x80)), ("c.o", obj(0))];
+        let errors = link_with(&objects, &layout).unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "relocation against the data section in `c.o` resolves to 0x100, which does not fit in 8 bits"
+        );
+
+        let errors = link_with(&[("a.o", obj(0xff)), ("b.o", obj(2))], &layout).unwrap_err();
+        assert!(matches!(
+            errors[0],
+            LinkError::SectionOverflow {
+                section: "data",
+                ..
+            }
+        ));
+        // Sections must fit their region, not just their address space
+        let errors = link(&[("a.o", obj(0xff)), ("b.o", obj(0xff))]).unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "linked data section takes 0x1fe, past the size of region `ram` (0x1c0)"
+        );
+
+        let bad = Object {
+            relocations: vec![Relocation {
+                kind: RelocKind::Word,
+                offset: 0,
+                target: Target::Section(Section::Data),
+                addend: 0,
+            }],
+            data: vec![0],
+            ..Object::default()
+        };
+        assert!(matches!(
+            link(&[("bad.o", bad)]).unwrap_err()[0],
+            LinkError::BadRelocation { .. }
+        ));
+    }
+}
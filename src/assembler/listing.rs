@@ -0,0 +1,132 @@
+//! Listings and symbol maps of assembled programs, for reading alongside their source
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    assembler::encoder::MachineCode,
+    compiler::{
+        span::SourceMap,
+        symbol::{Section, SymbolTable},
+    },
+};
+
+/// Renders a listing of a given program, interleaving every line of its
+/// source files with the address and encoded word of each instruction
+/// assembled from it, i.e.
+///
+/// ```text
+/// ; prog.asm
+///                1  ; Count down from 3
+/// 000  030101    2  main: li r1, 3
+/// 001  ff1101    3  loop: dec r1
+/// 002  001049    4    bnz loop
+/// ```
+///
+/// Lines expanding into several instructions are followed by the rest of
+/// them, and instructions without a source line (such as padding) are left out.
+pub fn listing(code: &[MachineCode], source_map: &SourceMap) -> String {
+    // Addresses assembled from each line of each file
+    let mut addresses: HashMap<(&str, usize), Vec<usize>> = HashMap::new();
+    for pc in 0..code.len() {
+        if let Some(span) = source_map.get(pc as u16) {
+            addresses
+                .entry((span.file.as_str(), span.line))
+                .or_default()
+                .push(pc);
+        }
+    }
+
+    let mut out = Vec::new();
+    for file in source_map.files() {
+        if !out.is_empty() {
+            out.push(String::new());
+        }
+        out.push(format!("; {}", file));
+        let src = source_map.source(file).unwrap_or_default();
+        for (i, text) in src.lines().enumerate() {
+            let pcs = addresses
+                .get(&(file, i + 1))
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            let word = |pc: usize| format!("{:03x}  {:06x}", pc, code[pc]);
+            let first = pcs.first().map_or(" ".repeat(11), |pc| word(*pc));
+            out.push(format!("{}  {:>3}  {}", first, i + 1, text));
+            out.extend(pcs.iter().skip(1).map(|pc| word(*pc)));
+        }
+    }
+
+    let mut out = out
+        .iter()
+        .map(|line| line.trim_end())
+        .collect::<Vec<_>>()
+        .join("\n");
+    out.push('\n');
+    out
+}
+
+/// Renders a map of every symbol of a program sorted by address, with the
+/// section each label was defined in. Symbols in no section are constants.
+pub fn symbol_map(symbols: &SymbolTable, labels: &HashMap<String, Section>) -> String {
+    let sorted: BTreeMap<(u16, &str), Option<Section>> = symbols
+        .iter()
+        .map(|(name, address)| ((*address, name.as_str()), labels.get(name).copied()))
+        .collect();
+
+    let mut out = format!("{:<8} {:<8} {}\n", "address", "section", "symbol");
+    for ((address, name), section) in sorted {
+        let section = section.map_or("const", |s| s.name());
+        out += &format!("{:#06x}   {:<8} {}\n", address, section, name);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler::encoder::encode_program, compiler::compile};
+
+    #[test]
+    fn test_listing() {
+        let src = "\
+; Count down from 3
+main: li r1, 3
+loop: dec r1
+  bnz loop
+  beq r1, r0, done
+done: halt
+.data
+msg: .string \"hi\"
+.equ LEN, 3
+";
+        let compiled = compile("prog.asm", src).unwrap();
+        let code = encode_program(&compiled.program).unwrap();
+        assert_eq!(
+            listing(&code, &compiled.source_map),
+            "\
+; prog.asm
+               1  ; Count down from 3
+000  030101    2  main: li r1, 3
+001  ff1101    3  loop: dec r1
+002  001049    4    bnz loop
+003  001003    5    beq r1, r0, done
+004  005009
+005  000000    6  done: halt
+               7  .data
+               8  msg: .string \"hi\"
+               9  .equ LEN, 3
+"
+        );
+
+        assert_eq!(
+            symbol_map(&compiled.symbols, &compiled.labels),
+            "\
+address  section  symbol
+0x0000   text     main
+0x0000   data     msg
+0x0001   text     loop
+0x0003   const    LEN
+0x0005   text     done
+"
+        );
+    }
+}
@@ -1 +1,9 @@
+pub mod binary;
+pub mod decoder;
 pub mod encoder;
+pub mod image;
+pub mod layout;
+pub mod linker;
+pub mod listing;
+pub mod object;
+pub mod opcode;
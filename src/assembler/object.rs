@@ -0,0 +1,461 @@
+//! Cobble relocatable object format, produced by `cobble build -c`
+//! and combined into binaries by the linker.
+//!
+//! All multi-byte fields are little-endian, and names are stored as a
+//! length byte followed by that many bytes of UTF-8.
+//!
+//! | Size   | Field                                           |
+//! |--------|-------------------------------------------------|
+//! | 4      | Magic, `b"CBO\0"`                               |
+//! | 2      | Format version (currently `1`)                  |
+//! | 2      | Code section length `n`, in words               |
+//! | 2      | Data section length `m`, in bytes               |
+//! | 2      | Number of exported symbols                      |
+//! | 2      | Number of external symbols                      |
+//! | 2      | Number of relocations                           |
+//! | 3*n    | Code section, 3-byte little-endian words        |
+//! | m      | Data section                                    |
+//! | ...    | Exports: name, section (see below), value (2)   |
+//! | ...    | Externals: name                                 |
+//! | ...    | Relocations: kind (1), offset (2), target, addend (2) |
+//!
+//! Sections are stored as `0` for constants, `1` for code and `2` for data.
+//! Relocation kinds are `0` to `3`, in the order of [`RelocKind`].
+//! Targets are a section, or `3` followed by the name of an external symbol.
+
+use thiserror::Error;
+
+use crate::{
+    assembler::{
+        binary::{MAX_WORDS, WORD_LEN},
+        encoder::MachineCode,
+    },
+    compiler::symbol::Section,
+    interpreter::state::MEMORY_SIZE,
+};
+
+/// Magic bytes identifying a cobble object
+pub const MAGIC: [u8; 4] = *b"CBO\0";
+
+/// Current object format version
+pub const VERSION: u16 = 1;
+
+/// Size of the fixed header, in bytes
+const HEADER_LEN: usize = 16;
+
+#[derive(Debug, Error)]
+pub enum ObjectError {
+    #[error("Missing or invalid magic header")]
+    BadMagic,
+
+    #[error("Unsupported format version: {0}")]
+    UnsupportedVersion(u16),
+
+    #[error("Truncated object: expected {expected} bytes, found {found}")]
+    Truncated { expected: usize, found: usize },
+
+    #[error("Code section too large: {0} words")]
+    TooLarge(usize),
+
+    #[error("Data section too large: {0} bytes")]
+    DataTooLarge(usize),
+
+    #[error("Symbol name too long: {0}")]
+    NameTooLong(String),
+
+    #[error("Invalid {what} at offset {offset}")]
+    Invalid { what: &'static str, offset: usize },
+}
+
+/// Fields patched by relocations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocKind {
+    /// 8-bit immediate of an instruction
+    Imm8,
+    /// 12-bit immediate of an instruction
+    Imm12,
+    /// Byte of data
+    Byte,
+    /// Little-endian 16-bit word of data
+    Word,
+}
+
+impl RelocKind {
+    /// Section the patched field lies in
+    pub fn section(&self) -> Section {
+        match self {
+            Self::Imm8 | Self::Imm12 => Section::Text,
+            Self::Byte | Self::Word => Section::Data,
+        }
+    }
+
+    /// Width of the patched field, in bits
+    pub fn bits(&self) -> u32 {
+        match self {
+            Self::Imm8 | Self::Byte => 8,
+            Self::Imm12 => 12,
+            Self::Word => 16,
+        }
+    }
+}
+
+/// What a relocation is relative to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// Start of a section of the same object
+    Section(Section),
+    /// Address of a symbol defined by another object
+    Symbol(String),
+}
+
+/// A field whose value depends on where objects end up once linked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub kind: RelocKind,
+    /// Address of the patched field in its section: a word for
+    /// instructions, a byte for data
+    pub offset: u16,
+    pub target: Target,
+    /// Value of the field relative to its target
+    pub addend: u16,
+}
+
+/// A symbol exported by an object with `.global`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export {
+    pub name: String,
+    /// Section of the symbol, `None` for constants
+    pub section: Option<Section>,
+    /// Address of the symbol in its section, or value of the constant
+    pub value: u16,
+}
+
+/// A compiled program, yet to be linked with others into a binary
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Object {
+    /// Encoded 24-bit instruction words, with relocated fields
+    /// holding their value relative to their target
+    pub code: Vec<MachineCode>,
+    /// Initial contents of data memory, relative to the object's data
+    pub data: Vec<u8>,
+    pub exports: Vec<Export>,
+    /// Symbols defined by other objects
+    pub externs: Vec<String>,
+    pub relocations: Vec<Relocation>,
+}
+
+fn section_tag(section: Option<Section>) -> u8 {
+    match section {
+        None => 0,
+        Some(Section::Text) => 1,
+        Some(Section::Data) => 2,
+    }
+}
+
+fn reloc_tag(kind: RelocKind) -> u8 {
+    match kind {
+        RelocKind::Imm8 => 0,
+        RelocKind::Imm12 => 1,
+        RelocKind::Byte => 2,
+        RelocKind::Word => 3,
+    }
+}
+
+impl Object {
+    /// Serializes the object into its on-disk representation.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ObjectError> {
+        if self.code.len() > MAX_WORDS {
+            return Err(ObjectError::TooLarge(self.code.len()));
+        }
+        if self.data.len() > MEMORY_SIZE {
+            return Err(ObjectError::DataTooLarge(self.data.len()));
+        }
+
+        let mut out = Vec::new();
+        let name = |out: &mut Vec<u8>, name: &str| {
+            let len =
+                u8::try_from(name.len()).map_err(|_| ObjectError::NameTooLong(name.to_string()))?;
+            out.push(len);
+            out.extend_from_slice(name.as_bytes());
+            Ok(())
+        };
+
+        out.extend_from_slice(&MAGIC);
+        for field in [
+            VERSION,
+            self.code.len() as u16,
+            self.data.len() as u16,
+            self.exports.len() as u16,
+            self.externs.len() as u16,
+            self.relocations.len() as u16,
+        ] {
+            out.extend_from_slice(&field.to_le_bytes());
+        }
+        for word in &self.code {
+            out.extend_from_slice(&word.to_le_bytes()[..WORD_LEN]);
+        }
+        out.extend_from_slice(&self.data);
+
+        for export in &self.exports {
+            name(&mut out, &export.name)?;
+            out.push(section_tag(export.section));
+            out.extend_from_slice(&export.value.to_le_bytes());
+        }
+        for symbol in &self.externs {
+            name(&mut out, symbol)?;
+        }
+        for reloc in &self.relocations {
+            out.push(reloc_tag(reloc.kind));
+            out.extend_from_slice(&reloc.offset.to_le_bytes());
+            match &reloc.target {
+                Target::Section(section) => out.push(section_tag(Some(*section))),
+                Target::Symbol(symbol) => {
+                    out.push(3);
+                    name(&mut out, symbol)?;
+                }
+            }
+            out.extend_from_slice(&reloc.addend.to_le_bytes());
+        }
+
+        Ok(out)
+    }
+
+    /// Deserializes an object from its on-disk representation.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ObjectError> {
+        if !is_object(bytes) {
+            return Err(ObjectError::BadMagic);
+        }
+        let mut r = Reader {
+            bytes,
+            at: MAGIC.len(),
+        };
+        r.expect(HEADER_LEN - MAGIC.len())?;
+
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(ObjectError::UnsupportedVersion(version));
+        }
+        let len = r.u16()? as usize;
+        if len > MAX_WORDS {
+            return Err(ObjectError::TooLarge(len));
+        }
+        let data_len = r.u16()? as usize;
+        if data_len > MEMORY_SIZE {
+            return Err(ObjectError::DataTooLarge(data_len));
+        }
+        let (exports, externs, relocations) = (r.u16()?, r.u16()?, r.u16()?);
+
+        let code = r
+            .take(len * WORD_LEN)?
+            .chunks_exact(WORD_LEN)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], 0]))
+            .collect();
+        let data = r.take(data_len)?.to_vec();
+
+        let exports = (0..exports)
+            .map(|_| {
+                let name = r.name()?;
+                let section = match r.u8()? {
+                    0 => None,
+                    tag => Some(r.section(tag)?),
+                };
+                let value = r.u16()?;
+                Ok(Export {
+                    name,
+                    section,
+                    value,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        let externs = (0..externs).map(|_| r.name()).collect::<Result<_, _>>()?;
+        let relocations = (0..relocations)
+            .map(|_| {
+                let kind = match r.u8()? {
+                    0 => RelocKind::Imm8,
+                    1 => RelocKind::Imm12,
+                    2 => RelocKind::Byte,
+                    3 => RelocKind::Word,
+                    _ => return Err(r.invalid("relocation kind")),
+                };
+                let offset = r.u16()?;
+                let target = match r.u8()? {
+                    3 => Target::Symbol(r.name()?),
+                    tag => Target::Section(r.section(tag)?),
+                };
+                let addend = r.u16()?;
+                Ok(Relocation {
+                    kind,
+                    offset,
+                    target,
+                    addend,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            code,
+            data,
+            exports,
+            externs,
+            relocations,
+        })
+    }
+}
+
+/// Cursor over the bytes of an object
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Checks that a given number of bytes is left past the cursor.
+    fn expect(&self, len: usize) -> Result<(), ObjectError> {
+        let expected = self.at + len;
+        if self.bytes.len() < expected {
+            return Err(ObjectError::Truncated {
+                expected,
+                found: self.bytes.len(),
+            });
+        }
+        Ok(())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ObjectError> {
+        self.expect(len)?;
+        self.at += len;
+        Ok(&self.bytes[self.at - len..self.at])
+    }
+
+    fn u8(&mut self) -> Result<u8, ObjectError> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ObjectError> {
+        self.take(2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn name(&mut self) -> Result<String, ObjectError> {
+        let len = self.u8()? as usize;
+        let at = self.at;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| ObjectError::Invalid {
+            what: "symbol name",
+            offset: at,
+        })
+    }
+
+    fn section(&self, tag: u8) -> Result<Section, ObjectError> {
+        match tag {
+            1 => Ok(Section::Text),
+            2 => Ok(Section::Data),
+            _ => Err(self.invalid("section")),
+        }
+    }
+
+    /// Error for an invalid field just read
+    fn invalid(&self, what: &'static str) -> ObjectError {
+        ObjectError::Invalid {
+            what,
+            offset: self.at - 1,
+        }
+    }
+}
+
+/// Checks whether the given bytes start with the object magic header.
+#[inline]
+pub fn is_object(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object_roundtrip() {
+        let obj = Object {
+            code: vec![0x000008, 0xABCDEF],
+            data: vec![0, 0, 7],
+            exports: vec![
+                Export {
+                    name: "main".to_string(),
+                    section: Some(Section::Text),
+                    value: 1,
+                },
+                Export {
+                    name: "LEN".to_string(),
+                    section: None,
+                    value: 3,
+                },
+            ],
+            externs: vec!["putc".to_string()],
+            relocations: vec![
+                Relocation {
+                    kind: RelocKind::Imm12,
+                    offset: 0,
+                    target: Target::Symbol("putc".to_string()),
+                    addend: 0,
+                },
+                Relocation {
+                    kind: RelocKind::Word,
+                    offset: 0,
+                    target: Target::Section(Section::Text),
+                    addend: 1,
+                },
+            ],
+        };
+        let bytes = obj.to_bytes().unwrap();
+        assert_eq!(&bytes[..4], b"CBO\0");
+        assert_eq!(&bytes[HEADER_LEN + 3..HEADER_LEN + 6], &[0xEF, 0xCD, 0xAB]);
+        assert_eq!(Object::from_bytes(&bytes).unwrap(), obj);
+    }
+
+    #[test]
+    fn test_object_errors() {
+        assert!(matches!(
+            Object::from_bytes(b"CBL\0"),
+            Err(ObjectError::BadMagic)
+        ));
+
+        let obj = Object {
+            externs: vec!["putc".to_string()],
+            ..Object::default()
+        };
+        let mut bytes = obj.to_bytes().unwrap();
+        assert!(matches!(
+            Object::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(ObjectError::Truncated { .. })
+        ));
+        bytes[4] = 2;
+        assert!(matches!(
+            Object::from_bytes(&bytes),
+            Err(ObjectError::UnsupportedVersion(2))
+        ));
+
+        let obj = Object {
+            externs: vec!["x".repeat(256)],
+            ..Object::default()
+        };
+        assert!(matches!(obj.to_bytes(), Err(ObjectError::NameTooLong(_))));
+
+        // Unknown section of an export
+        let obj = Object {
+            exports: vec![Export {
+                name: "a".to_string(),
+                section: Some(Section::Data),
+                value: 0,
+            }],
+            ..Object::default()
+        };
+        let mut bytes = obj.to_bytes().unwrap();
+        bytes[HEADER_LEN + 2] = 9;
+        assert!(matches!(
+            Object::from_bytes(&bytes),
+            Err(ObjectError::Invalid {
+                what: "section",
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,174 @@
+//! Opcode map of the cobble ISA.
+//!
+//! Every instruction is a 24-bit word. The opcode and `fun2` fields are
+//! present in every format, `fun4` only in register formats (where the
+//! upper nibble is not taken by an immediate).
+//!
+//! ```text
+//!  23    20 19    16 15    12 11     8 7  6 5        0
+//! +--------+--------+--------+--------+----+----------+
+//! |  fun4  |  rs2   |  rs1   |   rd   |fun2|  opcode  |  R1 / R2 / R3
+//! +--------+--------+--------+--------+----+----------+
+//! |      imm8       |  rs1   |   rd   |fun2|  opcode  |  I
+//! +-----------------+--------+--------+----+----------+
+//! |          imm12           |  ----  |fun2|  opcode  |  J
+//! +--------------------------+--------+----+----------+
+//! ```
+//!
+//! | Mnemonic | Format | Opcode | fun2 | fun4 |
+//! |----------|--------|--------|------|------|
+//! | `halt`   | N      | `0x00` | 0    | -    |
+//! | `addi`   | I      | `0x01` | 0    | -    |
+//! | `add`    | R3     | `0x02` | 0    | 0    |
+//! | `sub`    | R3     | `0x03` | 0    | 0    |
+//! | `and`    | R3     | `0x04` | 0    | 0    |
+//! | `or`     | R3     | `0x04` | 0    | 1    |
+//! | `xor`    | R3     | `0x04` | 0    | 2    |
+//! | `not`    | R2     | `0x04` | 0    | 3    |
+//! | `andi`   | I      | `0x05` | 0    | -    |
+//! | `ori`    | I      | `0x05` | 1    | -    |
+//! | `xori`   | I      | `0x05` | 2    | -    |
+//! | `shl`    | R3     | `0x0b` | 0    | 0    |
+//! | `shr`    | R3     | `0x0b` | 0    | 1    |
+//! | `sar`    | R3     | `0x0b` | 0    | 2    |
+//! | `rol`    | R3     | `0x0b` | 0    | 3    |
+//! | `ror`    | R3     | `0x0b` | 0    | 4    |
+//! | `shli`   | I      | `0x0c` | 0    | -    |
+//! | `shri`   | I      | `0x0c` | 1    | -    |
+//! | `sari`   | I      | `0x0c` | 2    | -    |
+//! | `roli`   | I      | `0x0c` | 3    | -    |
+//! | `rori`   | I      | `0x0d` | 0    | -    |
+//! | `lb`     | I      | `0x06` | 0    | -    |
+//! | `sb`     | I      | `0x06` | 1    | -    |
+//! | `push`   | R1     | `0x07` | 0    | 0    |
+//! | `pop`    | R1     | `0x07` | 1    | 0    |
+//! | `ret`    | N      | `0x07` | 2    | -    |
+//! | `jmp`    | J      | `0x08` | 0    | -    |
+//! | `call`   | J      | `0x08` | 1    | -    |
+//! | `bz`     | J      | `0x09` | 0    | -    |
+//! | `bnz`    | J      | `0x09` | 1    | -    |
+//! | `bc`     | J      | `0x09` | 2    | -    |
+//! | `bnc`    | J      | `0x09` | 3    | -    |
+//! | `bn`     | J      | `0x0a` | 0    | -    |
+//! | `bv`     | J      | `0x0a` | 1    | -    |
+//! | `blt`    | J      | `0x0a` | 2    | -    |
+//! | `bge`    | J      | `0x0a` | 3    | -    |
+//!
+//! `sb rs2, imm(rs1)` and `push rs` carry their source register in the
+//! `rd` field.
+//!
+//! `nop`, `mv rd, rs1`, `cmp rs1, rs2`, `bltu` and `bgeu` have no opcodes
+//! of their own, and are encoded as `addi r0, r0, 0`, `addi rd, rs1, 0`,
+//! `sub r0, rs1, rs2`, `bc` and `bnc` respectively.
+
+/// Instruction word layouts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// No operands
+    N,
+    /// One register: `rd`
+    R1,
+    /// Two registers: `rd, rs1`
+    R2,
+    /// Three registers: `rd, rs1, rs2`
+    R3,
+    /// Two registers and an 8-bit immediate: `rd, rs1, imm8`
+    I,
+    /// A 12-bit immediate: `imm12`
+    J,
+}
+
+impl Format {
+    /// Whether the format carries a `fun4` field
+    #[inline]
+    pub fn has_fun4(&self) -> bool {
+        matches!(self, Self::R1 | Self::R2 | Self::R3)
+    }
+}
+
+/// An entry in the opcode map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodeDef {
+    pub mnemonic: &'static str,
+    pub format: Format,
+    pub opcode: u8,
+    pub fun2: u8,
+    pub fun4: u8,
+}
+
+const fn def(mnemonic: &'static str, format: Format, opcode: u8, fun2: u8, fun4: u8) -> OpcodeDef {
+    OpcodeDef {
+        mnemonic,
+        format,
+        opcode,
+        fun2,
+        fun4,
+    }
+}
+
+/// The opcode map, shared by the encoder and decoder
+pub const OPCODES: &[OpcodeDef] = &[
+    def("halt", Format::N, 0x00, 0, 0),
+    def("addi", Format::I, 0x01, 0, 0),
+    def("add", Format::R3, 0x02, 0, 0),
+    def("sub", Format::R3, 0x03, 0, 0),
+    def("and", Format::R3, 0x04, 0, 0),
+    def("or", Format::R3, 0x04, 0, 1),
+    def("xor", Format::R3, 0x04, 0, 2),
+    def("not", Format::R2, 0x04, 0, 3),
+    def("andi", Format::I, 0x05, 0, 0),
+    def("ori", Format::I, 0x05, 1, 0),
+    def("xori", Format::I, 0x05, 2, 0),
+    def("shl", Format::R3, 0x0B, 0, 0),
+    def("shr", Format::R3, 0x0B, 0, 1),
+    def("sar", Format::R3, 0x0B, 0, 2),
+    def("rol", Format::R3, 0x0B, 0, 3),
+    def("ror", Format::R3, 0x0B, 0, 4),
+    def("shli", Format::I, 0x0C, 0, 0),
+    def("shri", Format::I, 0x0C, 1, 0),
+    def("sari", Format::I, 0x0C, 2, 0),
+    def("roli", Format::I, 0x0C, 3, 0),
+    def("rori", Format::I, 0x0D, 0, 0),
+    def("lb", Format::I, 0x06, 0, 0),
+    def("sb", Format::I, 0x06, 1, 0),
+    def("push", Format::R1, 0x07, 0, 0),
+    def("pop", Format::R1, 0x07, 1, 0),
+    def("ret", Format::N, 0x07, 2, 0),
+    def("jmp", Format::J, 0x08, 0, 0),
+    def("call", Format::J, 0x08, 1, 0),
+    def("bz", Format::J, 0x09, 0, 0),
+    def("bnz", Format::J, 0x09, 1, 0),
+    def("bc", Format::J, 0x09, 2, 0),
+    def("bnc", Format::J, 0x09, 3, 0),
+    def("bn", Format::J, 0x0A, 0, 0),
+    def("bv", Format::J, 0x0A, 1, 0),
+    def("blt", Format::J, 0x0A, 2, 0),
+    def("bge", Format::J, 0x0A, 3, 0),
+];
+
+/// Looks up the opcode map entry for a given mnemonic.
+pub fn lookup(mnemonic: &str) -> Option<&'static OpcodeDef> {
+    OPCODES.iter().find(|d| d.mnemonic == mnemonic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opcode_map_unique() {
+        // No two entries may share both mnemonic and encoding
+        for (i, a) in OPCODES.iter().enumerate() {
+            for b in &OPCODES[i + 1..] {
+                assert_ne!(a.mnemonic, b.mnemonic);
+                let same_fun4 = !a.format.has_fun4() || !b.format.has_fun4() || a.fun4 == b.fun4;
+                assert!(
+                    !(a.opcode == b.opcode && a.fun2 == b.fun2 && same_fun4),
+                    "{} and {} share an encoding",
+                    a.mnemonic,
+                    b.mnemonic
+                );
+            }
+        }
+    }
+}
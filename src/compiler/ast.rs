@@ -1,5 +1,7 @@
 use std::fmt::*;
 
+pub use crate::compiler::expr::Expr;
+
 /// Operand types
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Op {
@@ -14,6 +16,9 @@ pub enum Op {
 
     /// Address label
     Label(String),
+
+    /// Expression over numbers and symbols
+    Expr(Expr),
 }
 
 impl Display for Op {
@@ -23,6 +28,90 @@ impl Display for Op {
             Self::Imm8(v) => write!(f, "{}", v),
             Self::Imm12(v) => write!(f, "{}", v),
             Self::Label(l) => write!(f, "{}", l),
+            Self::Expr(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Assembler directives
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Directive {
+    /// Continue in the code section
+    Text,
+    /// Continue in the data section
+    Data,
+    /// Named constant
+    Equ(String, Expr),
+    /// Continue the current section at an address
+    Org(Expr),
+    /// Pad the current section to a multiple of a given size
+    Align(Expr),
+    /// Bytes of data
+    Byte(Vec<Expr>),
+    /// 16-bit words of data, stored little-endian
+    Word(Vec<Expr>),
+    /// NUL-terminated string
+    String(String),
+    /// Number of zero bytes
+    Zero(Expr),
+    /// Symbols exported to other objects
+    Global(Vec<String>),
+    /// Symbols defined by other objects
+    Extern(Vec<String>),
+}
+
+impl Directive {
+    /// Name of the directive, i.e. ".byte"
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Text => ".text",
+            Self::Data => ".data",
+            Self::Equ(..) => ".equ",
+            Self::Org(_) => ".org",
+            Self::Align(_) => ".align",
+            Self::Byte(_) => ".byte",
+            Self::Word(_) => ".word",
+            Self::String(_) => ".string",
+            Self::Zero(_) => ".zero",
+            Self::Global(_) => ".global",
+            Self::Extern(_) => ".extern",
+        }
+    }
+
+    /// Expressions taken by the directive, in assembly order
+    pub fn exprs(&self) -> Vec<&Expr> {
+        match self {
+            Self::Text | Self::Data | Self::String(_) | Self::Global(_) | Self::Extern(_) => vec![],
+            Self::Equ(_, e) | Self::Org(e) | Self::Align(e) | Self::Zero(e) => vec![e],
+            Self::Byte(v) | Self::Word(v) => v.iter().collect(),
+        }
+    }
+
+    /// Mutable variant of [`Directive::exprs`]
+    pub fn exprs_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Self::Text | Self::Data | Self::String(_) | Self::Global(_) | Self::Extern(_) => vec![],
+            Self::Equ(_, e) | Self::Org(e) | Self::Align(e) | Self::Zero(e) => vec![e],
+            Self::Byte(v) | Self::Word(v) => v.iter_mut().collect(),
+        }
+    }
+}
+
+impl Display for Directive {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let list = |v: &[Expr]| v.iter().map(Expr::to_string).collect::<Vec<_>>().join(", ");
+        match self {
+            Self::Text => write!(f, ".text"),
+            Self::Data => write!(f, ".data"),
+            Self::Equ(name, v) => write!(f, ".equ {}, {}", name, v),
+            Self::Org(a) => write!(f, ".org {}", a),
+            Self::Align(n) => write!(f, ".align {}", n),
+            Self::Byte(b) => write!(f, ".byte {}", list(b)),
+            Self::Word(w) => write!(f, ".word {}", list(w)),
+            Self::String(s) => write!(f, ".string \"{}\"", s.escape_default()),
+            Self::Zero(n) => write!(f, ".zero {}", n),
+            Self::Global(names) => write!(f, ".global {}", names.join(", ")),
+            Self::Extern(names) => write!(f, ".extern {}", names.join(", ")),
         }
     }
 }
@@ -33,6 +122,8 @@ pub enum Instr {
     // Meta operations
     /// Symbol placeholder (to be stripped)
     Label(String),
+    /// Assembler directive (to be stripped)
+    Directive(Directive),
     /// Terminate program
     Halt,
     /// No operation
@@ -53,6 +144,18 @@ pub enum Instr {
     Or { rd: Op, rs1: Op, rs2: Op },
     /// Bitwise XOR (rd = rs1 ^ rs2)
     Xor { rd: Op, rs1: Op, rs2: Op },
+    /// Shift left (rd = rs1 << rs2)
+    Shl { rd: Op, rs1: Op, rs2: Op },
+    /// Logical shift right (rd = rs1 >> rs2)
+    Shr { rd: Op, rs1: Op, rs2: Op },
+    /// Arithmetic shift right (rd = rs1 >> rs2, sign-filling)
+    Sar { rd: Op, rs1: Op, rs2: Op },
+    /// Rotate left (rd = rs1 rotated left by rs2)
+    Rol { rd: Op, rs1: Op, rs2: Op },
+    /// Rotate right (rd = rs1 rotated right by rs2)
+    Ror { rd: Op, rs1: Op, rs2: Op },
+    /// Compare (flags of rs1 - rs2, result discarded)
+    Cmp { rs1: Op, rs2: Op },
     // Immediate operations
     /// Immediate addition (rd = rs1 + imm)
     Addi { rd: Op, rs1: Op, imm: Op },
@@ -62,6 +165,30 @@ pub enum Instr {
     Ori { rd: Op, rs1: Op, imm: Op },
     /// Immediate bitwise XOR (rd = rs1 ^ imm)
     Xori { rd: Op, rs1: Op, imm: Op },
+    /// Immediate shift left (rd = rs1 << imm)
+    Shli { rd: Op, rs1: Op, imm: Op },
+    /// Immediate logical shift right (rd = rs1 >> imm)
+    Shri { rd: Op, rs1: Op, imm: Op },
+    /// Immediate arithmetic shift right (rd = rs1 >> imm, sign-filling)
+    Sari { rd: Op, rs1: Op, imm: Op },
+    /// Immediate rotate left (rd = rs1 rotated left by imm)
+    Roli { rd: Op, rs1: Op, imm: Op },
+    /// Immediate rotate right (rd = rs1 rotated right by imm)
+    Rori { rd: Op, rs1: Op, imm: Op },
+    // Memory operations
+    /// Load byte (rd = mem[rs1 + imm])
+    Lb { rd: Op, rs1: Op, imm: Op },
+    /// Store byte (mem[rs1 + imm] = rs2)
+    Sb { rs2: Op, rs1: Op, imm: Op },
+    // Stack operations
+    /// Push register onto stack (mem[--sp] = rs)
+    Push { rs: Op },
+    /// Pop register off stack (rd = mem[sp++])
+    Pop { rd: Op },
+    /// Call subroutine (push pc + 1, pc = imm)
+    Call { imm: Op },
+    /// Return from subroutine (pop pc)
+    Ret,
     // Branching operations
     /// Jump to address (pc = imm)
     Jmp { imm: Op },
@@ -69,12 +196,29 @@ pub enum Instr {
     Bz { imm: Op },
     /// Jump to address (pc = imm) if not flag zero
     Bnz { imm: Op },
+    /// Jump to address (pc = imm) if flag carry
+    Bc { imm: Op },
+    /// Jump to address (pc = imm) if not flag carry
+    Bnc { imm: Op },
+    /// Jump to address (pc = imm) if flag negative
+    Bn { imm: Op },
+    /// Jump to address (pc = imm) if flag overflow
+    Bv { imm: Op },
+    /// Jump to address (pc = imm) if signed less than (negative != overflow)
+    Blt { imm: Op },
+    /// Jump to address (pc = imm) if signed greater or equal (negative == overflow)
+    Bge { imm: Op },
+    /// Jump to address (pc = imm) if unsigned less than (alias of `bc`)
+    Bltu { imm: Op },
+    /// Jump to address (pc = imm) if unsigned greater or equal (alias of `bnc`)
+    Bgeu { imm: Op },
 }
 
 impl Display for Instr {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         match self {
             Self::Label(l) => write!(f, "{}:", l),
+            Self::Directive(d) => write!(f, "{}", d),
             Self::Nop => write!(f, "nop"),
             Self::Halt => write!(f, "halt"),
 
@@ -86,15 +230,310 @@ impl Display for Instr {
             Self::And { rd, rs1, rs2 } => write!(f, "and {}, {}, {}", rd, rs1, rs2),
             Self::Or { rd, rs1, rs2 } => write!(f, "or {}, {}, {}", rd, rs1, rs2),
             Self::Xor { rd, rs1, rs2 } => write!(f, "xor {}, {}, {}", rd, rs1, rs2),
+            Self::Shl { rd, rs1, rs2 } => write!(f, "shl {}, {}, {}", rd, rs1, rs2),
+            Self::Shr { rd, rs1, rs2 } => write!(f, "shr {}, {}, {}", rd, rs1, rs2),
+            Self::Sar { rd, rs1, rs2 } => write!(f, "sar {}, {}, {}", rd, rs1, rs2),
+            Self::Rol { rd, rs1, rs2 } => write!(f, "rol {}, {}, {}", rd, rs1, rs2),
+            Self::Ror { rd, rs1, rs2 } => write!(f, "ror {}, {}, {}", rd, rs1, rs2),
+            Self::Cmp { rs1, rs2 } => write!(f, "cmp {}, {}", rs1, rs2),
 
             Self::Addi { rd, rs1, imm } => write!(f, "addi {}, {}, {}", rd, rs1, imm),
             Self::Andi { rd, rs1, imm } => write!(f, "andi {}, {}, {}", rd, rs1, imm),
             Self::Ori { rd, rs1, imm } => write!(f, "ori {}, {}, {}", rd, rs1, imm),
             Self::Xori { rd, rs1, imm } => write!(f, "xori {}, {}, {}", rd, rs1, imm),
+            Self::Shli { rd, rs1, imm } => write!(f, "shli {}, {}, {}", rd, rs1, imm),
+            Self::Shri { rd, rs1, imm } => write!(f, "shri {}, {}, {}", rd, rs1, imm),
+            Self::Sari { rd, rs1, imm } => write!(f, "sari {}, {}, {}", rd, rs1, imm),
+            Self::Roli { rd, rs1, imm } => write!(f, "roli {}, {}, {}", rd, rs1, imm),
+            Self::Rori { rd, rs1, imm } => write!(f, "rori {}, {}, {}", rd, rs1, imm),
+
+            Self::Lb { rd, rs1, imm } => write!(f, "lb {}, {}({})", rd, imm, rs1),
+            Self::Sb { rs2, rs1, imm } => write!(f, "sb {}, {}({})", rs2, imm, rs1),
+
+            Self::Push { rs } => write!(f, "push {}", rs),
+            Self::Pop { rd } => write!(f, "pop {}", rd),
+            Self::Call { imm } => write!(f, "call {}", imm),
+            Self::Ret => write!(f, "ret"),
 
             Self::Jmp { imm } => write!(f, "jmp {}", imm),
             Self::Bz { imm } => write!(f, "bz {}", imm),
             Self::Bnz { imm } => write!(f, "bnz {}", imm),
+            Self::Bc { imm } => write!(f, "bc {}", imm),
+            Self::Bnc { imm } => write!(f, "bnc {}", imm),
+            Self::Bn { imm } => write!(f, "bn {}", imm),
+            Self::Bv { imm } => write!(f, "bv {}", imm),
+            Self::Blt { imm } => write!(f, "blt {}", imm),
+            Self::Bge { imm } => write!(f, "bge {}", imm),
+            Self::Bltu { imm } => write!(f, "bltu {}", imm),
+            Self::Bgeu { imm } => write!(f, "bgeu {}", imm),
+        }
+    }
+}
+
+impl Instr {
+    /// Assembly mnemonic of the instruction (lowercase)
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Self::Label(_) | Self::Directive(_) => "",
+            Self::Halt => "halt",
+            Self::Nop => "nop",
+            Self::Mv { .. } => "mv",
+            Self::Not { .. } => "not",
+            Self::Add { .. } => "add",
+            Self::Sub { .. } => "sub",
+            Self::And { .. } => "and",
+            Self::Or { .. } => "or",
+            Self::Xor { .. } => "xor",
+            Self::Addi { .. } => "addi",
+            Self::Andi { .. } => "andi",
+            Self::Ori { .. } => "ori",
+            Self::Xori { .. } => "xori",
+            Self::Shl { .. } => "shl",
+            Self::Shr { .. } => "shr",
+            Self::Sar { .. } => "sar",
+            Self::Rol { .. } => "rol",
+            Self::Ror { .. } => "ror",
+            Self::Shli { .. } => "shli",
+            Self::Shri { .. } => "shri",
+            Self::Sari { .. } => "sari",
+            Self::Roli { .. } => "roli",
+            Self::Rori { .. } => "rori",
+            Self::Cmp { .. } => "cmp",
+            Self::Lb { .. } => "lb",
+            Self::Sb { .. } => "sb",
+            Self::Push { .. } => "push",
+            Self::Pop { .. } => "pop",
+            Self::Call { .. } => "call",
+            Self::Ret => "ret",
+            Self::Jmp { .. } => "jmp",
+            Self::Bz { .. } => "bz",
+            Self::Bnz { .. } => "bnz",
+            Self::Bc { .. } => "bc",
+            Self::Bnc { .. } => "bnc",
+            Self::Bn { .. } => "bn",
+            Self::Bv { .. } => "bv",
+            Self::Blt { .. } => "blt",
+            Self::Bge { .. } => "bge",
+            Self::Bltu { .. } => "bltu",
+            Self::Bgeu { .. } => "bgeu",
+        }
+    }
+
+    /// Builds an instruction from its mnemonic and operands (ordered as by [`Instr::operands`]).
+    /// Returns `None` if no such instruction, or the operand count is wrong
+    pub fn from_parts(mnemonic: &str, ops: Vec<Op>) -> Option<Self> {
+        let arity = ops.len();
+        let mut ops = ops.into_iter();
+        // Fields are evaluated in order, so operands are taken in assembly order
+        let mut op = || ops.next().unwrap();
+
+        let instr = match (mnemonic, arity) {
+            ("halt", 0) => Self::Halt,
+            ("nop", 0) => Self::Nop,
+            ("ret", 0) => Self::Ret,
+
+            ("push", 1) => Self::Push { rs: op() },
+            ("pop", 1) => Self::Pop { rd: op() },
+
+            ("mv", 2) => Self::Mv {
+                rd: op(),
+                rs1: op(),
+            },
+            ("not", 2) => Self::Not {
+                rd: op(),
+                rs1: op(),
+            },
+            ("cmp", 2) => Self::Cmp {
+                rs1: op(),
+                rs2: op(),
+            },
+
+            ("add", 3) => Self::Add {
+                rd: op(),
+                rs1: op(),
+                rs2: op(),
+            },
+            ("sub", 3) => Self::Sub {
+                rd: op(),
+                rs1: op(),
+                rs2: op(),
+            },
+            ("and", 3) => Self::And {
+                rd: op(),
+                rs1: op(),
+                rs2: op(),
+            },
+            ("or", 3) => Self::Or {
+                rd: op(),
+                rs1: op(),
+                rs2: op(),
+            },
+            ("xor", 3) => Self::Xor {
+                rd: op(),
+                rs1: op(),
+                rs2: op(),
+            },
+
+            ("shl", 3) => Self::Shl {
+                rd: op(),
+                rs1: op(),
+                rs2: op(),
+            },
+            ("shr", 3) => Self::Shr {
+                rd: op(),
+                rs1: op(),
+                rs2: op(),
+            },
+            ("sar", 3) => Self::Sar {
+                rd: op(),
+                rs1: op(),
+                rs2: op(),
+            },
+            ("rol", 3) => Self::Rol {
+                rd: op(),
+                rs1: op(),
+                rs2: op(),
+            },
+            ("ror", 3) => Self::Ror {
+                rd: op(),
+                rs1: op(),
+                rs2: op(),
+            },
+            ("shli", 3) => Self::Shli {
+                rd: op(),
+                rs1: op(),
+                imm: op(),
+            },
+            ("shri", 3) => Self::Shri {
+                rd: op(),
+                rs1: op(),
+                imm: op(),
+            },
+            ("sari", 3) => Self::Sari {
+                rd: op(),
+                rs1: op(),
+                imm: op(),
+            },
+            ("roli", 3) => Self::Roli {
+                rd: op(),
+                rs1: op(),
+                imm: op(),
+            },
+            ("rori", 3) => Self::Rori {
+                rd: op(),
+                rs1: op(),
+                imm: op(),
+            },
+            ("addi", 3) => Self::Addi {
+                rd: op(),
+                rs1: op(),
+                imm: op(),
+            },
+            ("andi", 3) => Self::Andi {
+                rd: op(),
+                rs1: op(),
+                imm: op(),
+            },
+            ("ori", 3) => Self::Ori {
+                rd: op(),
+                rs1: op(),
+                imm: op(),
+            },
+            ("xori", 3) => Self::Xori {
+                rd: op(),
+                rs1: op(),
+                imm: op(),
+            },
+
+            ("lb", 3) => Self::Lb {
+                rd: op(),
+                rs1: op(),
+                imm: op(),
+            },
+            ("sb", 3) => Self::Sb {
+                rs2: op(),
+                rs1: op(),
+                imm: op(),
+            },
+
+            ("call", 1) => Self::Call { imm: op() },
+            ("jmp", 1) => Self::Jmp { imm: op() },
+            ("bz", 1) => Self::Bz { imm: op() },
+            ("bnz", 1) => Self::Bnz { imm: op() },
+            ("bc", 1) => Self::Bc { imm: op() },
+            ("bnc", 1) => Self::Bnc { imm: op() },
+            ("bn", 1) => Self::Bn { imm: op() },
+            ("bv", 1) => Self::Bv { imm: op() },
+            ("blt", 1) => Self::Blt { imm: op() },
+            ("bge", 1) => Self::Bge { imm: op() },
+            ("bltu", 1) => Self::Bltu { imm: op() },
+            ("bgeu", 1) => Self::Bgeu { imm: op() },
+            _ => return None,
+        };
+        Some(instr)
+    }
+
+    /// Whether the immediate operand of the instruction (if any)
+    /// is 8 bits wide, rather than 12
+    pub fn has_imm8(&self) -> bool {
+        matches!(
+            self,
+            Self::Addi { .. }
+                | Self::Andi { .. }
+                | Self::Ori { .. }
+                | Self::Xori { .. }
+                | Self::Shli { .. }
+                | Self::Shri { .. }
+                | Self::Sari { .. }
+                | Self::Roli { .. }
+                | Self::Rori { .. }
+                | Self::Lb { .. }
+                | Self::Sb { .. }
+        )
+    }
+
+    /// Operands of the instruction, in assembly order
+    /// (except for memory operations, which are ordered as register, base, offset)
+    pub fn operands(&self) -> Vec<&Op> {
+        match self {
+            Self::Label(_) | Self::Directive(_) | Self::Halt | Self::Nop | Self::Ret => vec![],
+            Self::Push { rs } => vec![rs],
+            Self::Pop { rd } => vec![rd],
+            Self::Mv { rd, rs1 } | Self::Not { rd, rs1 } => vec![rd, rs1],
+            Self::Add { rd, rs1, rs2 }
+            | Self::Sub { rd, rs1, rs2 }
+            | Self::And { rd, rs1, rs2 }
+            | Self::Or { rd, rs1, rs2 }
+            | Self::Xor { rd, rs1, rs2 }
+            | Self::Shl { rd, rs1, rs2 }
+            | Self::Shr { rd, rs1, rs2 }
+            | Self::Sar { rd, rs1, rs2 }
+            | Self::Rol { rd, rs1, rs2 }
+            | Self::Ror { rd, rs1, rs2 } => vec![rd, rs1, rs2],
+            Self::Cmp { rs1, rs2 } => vec![rs1, rs2],
+            Self::Addi { rd, rs1, imm }
+            | Self::Andi { rd, rs1, imm }
+            | Self::Ori { rd, rs1, imm }
+            | Self::Xori { rd, rs1, imm }
+            | Self::Shli { rd, rs1, imm }
+            | Self::Shri { rd, rs1, imm }
+            | Self::Sari { rd, rs1, imm }
+            | Self::Roli { rd, rs1, imm }
+            | Self::Rori { rd, rs1, imm }
+            | Self::Lb { rd, rs1, imm } => vec![rd, rs1, imm],
+            Self::Sb { rs2, rs1, imm } => vec![rs2, rs1, imm],
+            Self::Call { imm }
+            | Self::Jmp { imm }
+            | Self::Bz { imm }
+            | Self::Bnz { imm }
+            | Self::Bc { imm }
+            | Self::Bnc { imm }
+            | Self::Bn { imm }
+            | Self::Bv { imm }
+            | Self::Blt { imm }
+            | Self::Bge { imm }
+            | Self::Bltu { imm }
+            | Self::Bgeu { imm } => vec![imm],
         }
     }
 }
@@ -0,0 +1,201 @@
+use std::fmt;
+
+use console::style;
+
+use crate::compiler::span::{SourceMap, Span};
+
+/// Kinds of errors reported by the compiler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// Malformed line
+    Syntax,
+    UnknownMnemonic,
+    /// Operand of the wrong kind, or wrong number of operands
+    InvalidOperand,
+    ImmediateTooLarge,
+    UndefinedLabel,
+    DuplicateLabel,
+    /// Program does not fit its sections
+    Layout,
+    /// Malformed macro definition or call
+    Macro,
+    /// Included file missing, unreadable or included cyclically
+    Include,
+}
+
+/// A compiler error, pointing at the source it was raised for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub message: String,
+    pub span: Span,
+    /// Text shown under the offending source
+    pub label: String,
+    /// Further locations related to the error
+    pub notes: Vec<(Span, String)>,
+    pub hint: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(kind: DiagnosticKind, span: Span, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            span,
+            label: String::new(),
+            notes: Vec::new(),
+            hint: None,
+        }
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = label.into();
+        self
+    }
+
+    pub fn with_note(mut self, span: Span, note: impl Into<String>) -> Self {
+        self.notes.push((span, note.into()));
+        self
+    }
+
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    /// Renders the diagnostic with snippets of the source it points at, i.e.
+    ///
+    /// ```text
+    /// error: unknown mnemonic `adi`
+    ///  --> prog.asm:2:3
+    ///   |
+    /// 2 |   adi r1, r0, 1
+    ///   |   ^^^ not an instruction
+    ///   = help: did you mean `addi`?
+    /// ```
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let mut out = format!(
+            "{}: {}",
+            style("error").red().bold(),
+            style(&self.message).bold()
+        );
+
+        for (span, label) in
+            std::iter::once((&self.span, &self.label)).chain(self.notes.iter().map(|(s, l)| (s, l)))
+        {
+            out.push('\n');
+            match sources.render(span, label) {
+                Some(snippet) => out.push_str(&snippet),
+                None => out.push_str(&format!(" --> {}", span)),
+            }
+        }
+
+        if let Some(hint) = &self.hint {
+            out.push_str(&format!("\n  = {}: {}", style("help").bold(), hint));
+        }
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.message)
+    }
+}
+
+/// Errors collected while compiling, along with the sources they point into
+#[derive(Debug, Clone)]
+pub struct Diagnostics {
+    pub errors: Vec<Diagnostic>,
+    pub sources: SourceMap,
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for e in &self.errors {
+            writeln!(f, "{}\n", e.render(&self.sources))?;
+        }
+        match self.errors.len() {
+            1 => write!(f, "Aborting due to previous error"),
+            n => write!(f, "Aborting due to {} previous errors", n),
+        }
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+/// Edit distance between two strings
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let sub = diag + (ca != *cb) as usize;
+            diag = row[j + 1];
+            row[j + 1] = sub.min(row[j] + 1).min(diag + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Finds the candidate closest to a given misspelled name, if any is close enough.
+pub fn suggest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    suggest_by(name, candidates, |_| ())
+}
+
+/// Like [`suggest`], but breaks ties between equally close candidates
+/// by a given rank, lowest first
+pub fn suggest_by<'a, R: Ord>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+    rank: impl Fn(&str) -> R,
+) -> Option<&'a str> {
+    let max = name.len().div_ceil(3);
+    candidates
+        .into_iter()
+        .map(|c| (levenshtein(name, c), rank(c), c))
+        .filter(|(d, _, _)| *d <= max)
+        .min()
+        .map(|(_, _, c)| c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+
+    #[test]
+    fn test_suggest() {
+        assert_eq!(levenshtein("addi", "adi"), 1);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+
+        let mnemonics = ["add", "addi", "and", "andi"];
+        assert_eq!(suggest("adi", mnemonics), Some("add"));
+        assert_eq!(suggest("andii", mnemonics), Some("andi"));
+        assert_eq!(suggest("xyz", mnemonics), None);
+
+        // Ties are broken by rank
+        let rank = |c: &str| c.ends_with('i');
+        assert_eq!(suggest_by("adi", mnemonics, |c| !rank(c)), Some("addi"));
+        assert_eq!(suggest_by("adi", mnemonics, rank), Some("add"));
+    }
+
+    #[test]
+    fn test_render() {
+        let src = "start:\n  adi r1, r0, 1\n";
+        let mut sources = SourceMap::new();
+        sources.add_source("prog.asm", src);
+
+        let diag = compile("prog.asm", src).unwrap_err().errors.remove(0);
+
+        assert_eq!(diag.to_string(), "prog.asm:2:3: unknown mnemonic `adi`");
+        assert_eq!(
+            diag.render(&sources),
+            "error: unknown mnemonic `adi`\n --> prog.asm:2:3\n  |\n2 |   adi r1, r0, 1\n  |   ^^^ not an instruction\n  = help: did you mean `addi`?"
+        );
+    }
+}
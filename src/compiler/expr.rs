@@ -0,0 +1,230 @@
+use std::fmt;
+
+use thiserror::Error;
+
+/// Binary operators, from tightest to loosest binding:
+/// `* / %`, `+ -`, `<< >>`, `&`, `^`, `|`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    And,
+    Xor,
+    Or,
+}
+
+impl BinOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Rem => "%",
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Shl => "<<",
+            Self::Shr => ">>",
+            Self::And => "&",
+            Self::Xor => "^",
+            Self::Or => "|",
+        }
+    }
+
+    /// Binding strength, higher binds tighter
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Self::Mul | Self::Div | Self::Rem => 5,
+            Self::Add | Self::Sub => 4,
+            Self::Shl | Self::Shr => 3,
+            Self::And => 2,
+            Self::Xor => 1,
+            Self::Or => 0,
+        }
+    }
+}
+
+/// Unary operators and functions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnOp {
+    /// Two's complement negation, `-x`
+    Neg,
+    /// Bitwise NOT, `~x`
+    Not,
+    /// Low byte, `lo(x)`
+    Lo,
+    /// High byte, `hi(x)`
+    Hi,
+}
+
+/// An assemble-time expression, evaluated in 16-bit two's complement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Num(u16),
+    Symbol(String),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ExprError {
+    #[error("symbol `{0}` is not defined at this point")]
+    Undefined(String),
+
+    #[error("division by zero")]
+    DivisionByZero,
+}
+
+impl Expr {
+    /// Evaluates the expression, looking up symbols with a given function.
+    pub fn eval(&self, lookup: &impl Fn(&str) -> Option<u16>) -> Result<u16, ExprError> {
+        Ok(match self {
+            Self::Num(v) => *v,
+            Self::Symbol(s) => lookup(s).ok_or_else(|| ExprError::Undefined(s.clone()))?,
+            Self::Unary(op, e) => {
+                let v = e.eval(lookup)?;
+                match op {
+                    UnOp::Neg => v.wrapping_neg(),
+                    UnOp::Not => !v,
+                    UnOp::Lo => v & 0xff,
+                    UnOp::Hi => v >> 8,
+                }
+            }
+            Self::Binary(op, a, b) => {
+                let (a, b) = (a.eval(lookup)?, b.eval(lookup)?);
+                match op {
+                    BinOp::Mul => a.wrapping_mul(b),
+                    BinOp::Div => a.checked_div(b).ok_or(ExprError::DivisionByZero)?,
+                    BinOp::Rem => a.checked_rem(b).ok_or(ExprError::DivisionByZero)?,
+                    BinOp::Add => a.wrapping_add(b),
+                    BinOp::Sub => a.wrapping_sub(b),
+                    BinOp::Shl => a.checked_shl(b as u32).unwrap_or(0),
+                    BinOp::Shr => a.checked_shr(b as u32).unwrap_or(0),
+                    BinOp::And => a & b,
+                    BinOp::Xor => a ^ b,
+                    BinOp::Or => a | b,
+                }
+            }
+        })
+    }
+
+    /// Symbols referenced by the expression, in order of appearance
+    pub fn symbols(&self) -> Vec<&String> {
+        match self {
+            Self::Num(_) => vec![],
+            Self::Symbol(s) => vec![s],
+            Self::Unary(_, e) => e.symbols(),
+            Self::Binary(_, a, b) => [a.symbols(), b.symbols()].concat(),
+        }
+    }
+
+    /// Renames every symbol referenced by the expression with a given function.
+    pub fn rename(&mut self, f: &impl Fn(&str) -> Option<String>) {
+        match self {
+            Self::Num(_) => {}
+            Self::Symbol(s) => {
+                if let Some(renamed) = f(s) {
+                    *s = renamed;
+                }
+            }
+            Self::Unary(_, e) => e.rename(f),
+            Self::Binary(_, a, b) => {
+                a.rename(f);
+                b.rename(f);
+            }
+        }
+    }
+}
+
+/// Whether a value fits in a given number of bits, either as an
+/// unsigned number or as a (sign-extended) negative one
+pub fn fits(value: u16, bits: u32) -> bool {
+    bits >= 16 || value < 1 << bits || value >= (1u32 << 16).wrapping_sub(1 << (bits - 1)) as u16
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Operands of binary operators are parenthesized
+        // if they bind looser than the operator
+        let operand = |f: &mut fmt::Formatter<'_>, e: &Expr, prec: u8| match e {
+            Self::Binary(op, ..) if op.precedence() < prec => write!(f, "({})", e),
+            _ => write!(f, "{}", e),
+        };
+        match self {
+            Self::Num(v) => write!(f, "{}", v),
+            Self::Symbol(s) => write!(f, "{}", s),
+            Self::Unary(UnOp::Neg, e) => {
+                write!(f, "-")?;
+                operand(f, e, u8::MAX)
+            }
+            Self::Unary(UnOp::Not, e) => {
+                write!(f, "~")?;
+                operand(f, e, u8::MAX)
+            }
+            Self::Unary(UnOp::Lo, e) => write!(f, "lo({})", e),
+            Self::Unary(UnOp::Hi, e) => write!(f, "hi({})", e),
+            Self::Binary(op, a, b) => {
+                operand(f, a, op.precedence())?;
+                write!(f, " {} ", op.symbol())?;
+                // Operators are left-associative
+                operand(f, b, op.precedence() + 1)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bin(op: BinOp, a: Expr, b: Expr) -> Expr {
+        Expr::Binary(op, Box::new(a), Box::new(b))
+    }
+
+    #[test]
+    fn test_eval() {
+        let sym = |s: &str| Expr::Symbol(s.to_string());
+        let lookup = |s: &str| (s == "LEN").then_some(0x1234);
+
+        let e = bin(BinOp::Sub, sym("LEN"), Expr::Num(1));
+        assert_eq!(e.eval(&lookup), Ok(0x1233));
+        assert_eq!(e.to_string(), "LEN - 1");
+
+        let e = Expr::Unary(UnOp::Hi, Box::new(sym("LEN")));
+        assert_eq!(e.eval(&lookup), Ok(0x12));
+        let e = Expr::Unary(UnOp::Neg, Box::new(Expr::Num(1)));
+        assert_eq!(e.eval(&lookup), Ok(0xffff));
+        assert_eq!(e.to_string(), "-1");
+
+        // Parentheses are kept where needed
+        let e = bin(
+            BinOp::Mul,
+            bin(BinOp::Add, Expr::Num(1), Expr::Num(2)),
+            bin(BinOp::Shl, Expr::Num(1), Expr::Num(3)),
+        );
+        assert_eq!(e.eval(&lookup), Ok(24));
+        assert_eq!(e.to_string(), "(1 + 2) * (1 << 3)");
+
+        assert_eq!(
+            bin(BinOp::Div, Expr::Num(1), Expr::Num(0)).eval(&lookup),
+            Err(ExprError::DivisionByZero)
+        );
+        assert_eq!(
+            sym("end").eval(&lookup),
+            Err(ExprError::Undefined("end".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_fits() {
+        assert!(fits(255, 8));
+        assert!(!fits(256, 8));
+        assert!(fits(0xff80, 8));
+        assert!(!fits(0xff7f, 8));
+        assert!(fits(0xf800, 12));
+        assert!(fits(0xffff, 16));
+    }
+}
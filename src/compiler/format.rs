@@ -0,0 +1,348 @@
+//! Canonical formatting of assembly sources, as in `examples/fib.asm`:
+//!
+//! - labels on lines of their own, in the first column
+//! - instructions and data indented by [`INDENT`], with lowercase
+//!   mnemonics padded to [`MNEMONIC_WIDTH`] and operands separated by `, `
+//! - one space around binary operators in operands, and none within
+//!   parentheses or before those of memory references and `lo`/`hi`
+//! - directives structuring the program (`.text`, `.macro`, ...) in the first column
+//! - trailing comments aligned within each run of lines between blank lines,
+//!   and full-line comments indented like the line they precede
+//!
+//! Blank lines are kept, and lines the formatter cannot make sense of
+//! (i.e. in the bodies of macros) are only reindented.
+
+use crate::compiler::{
+    diagnostic::Diagnostics,
+    include::Includes,
+    parser::{Parsed, canonical_name, parse_sources, split_line},
+    span::SourceMap,
+};
+
+/// Indentation of instructions
+pub const INDENT: usize = 2;
+
+/// Width mnemonics are padded to, before their operands
+pub const MNEMONIC_WIDTH: usize = 4;
+
+/// Column trailing comments are aligned to at the least, past which they
+/// are aligned to a multiple of [`COMMENT_STEP`]
+pub const COMMENT_COLUMN: usize = 20;
+
+const COMMENT_STEP: usize = 4;
+
+/// Directives written in the first column, as they structure the program
+const TOP_LEVEL: &[&str] = &[
+    ".text", ".data", ".equ", ".global", ".extern", ".include", ".macro", ".endm",
+];
+
+/// A line of formatted code, yet to have its trailing comment aligned
+struct Formatted {
+    code: String,
+    comment: Option<String>,
+}
+
+/// Formats a given source file, once checked to parse (along with any file
+/// it includes, found with given `includes`). Formatting is idempotent.
+pub fn format_source(file: &str, src: &str, includes: &Includes) -> Result<String, Diagnostics> {
+    let Parsed {
+        errors, included, ..
+    } = parse_sources(&[(file, src)], includes);
+    if !errors.is_empty() {
+        let mut sources = SourceMap::new();
+        sources.add_source(file, src);
+        for (file, src) in &included {
+            sources.add_source(file, src);
+        }
+        return Err(Diagnostics { errors, sources });
+    }
+
+    // Code lines, with `None` standing for blank lines
+    let mut lines: Vec<Option<Formatted>> = Vec::new();
+    // Full-line comments waiting for the indentation of the next line of code
+    let mut pending: Vec<(usize, String)> = Vec::new();
+
+    for text in src.lines() {
+        let Some(parts) = split_line(text) else {
+            flush(&mut lines, &mut pending, INDENT);
+            lines.push(Some(Formatted {
+                code: format!("{}{}", " ".repeat(INDENT), text.trim()),
+                comment: None,
+            }));
+            continue;
+        };
+
+        if parts.label.is_none() && parts.name.is_none() {
+            match parts.comment {
+                Some(comment) => {
+                    pending.push((lines.len(), comment.to_string()));
+                    lines.push(None);
+                }
+                None => lines.push(None),
+            }
+            continue;
+        }
+
+        let mut comment = parts.comment.map(str::to_string);
+        if let Some(label) = parts.label {
+            flush(&mut lines, &mut pending, 0);
+            lines.push(Some(Formatted {
+                code: format!("{}:", label),
+                // A trailing comment goes with the instruction, if any
+                comment: match parts.name {
+                    Some(_) => None,
+                    None => comment.take(),
+                },
+            }));
+        }
+        if let Some(name) = parts.name {
+            let canonical = canonical_name(name);
+            let indent = match canonical.is_some_and(|n| TOP_LEVEL.contains(&n)) {
+                true => 0,
+                false => INDENT,
+            };
+            flush(&mut lines, &mut pending, indent);
+
+            let name = canonical.unwrap_or(name);
+            let operands: Vec<String> = parts
+                .operands
+                .iter()
+                .map(|op| canonical_operand(op))
+                .collect();
+            let code = match operands.is_empty() {
+                true => name.to_string(),
+                false => format!(
+                    "{:<width$} {}",
+                    name,
+                    operands.join(", "),
+                    width = MNEMONIC_WIDTH
+                ),
+            };
+            lines.push(Some(Formatted {
+                code: format!("{}{}", " ".repeat(indent), code),
+                comment,
+            }));
+        }
+    }
+    flush(&mut lines, &mut pending, 0);
+
+    // Align trailing comments within each run of lines between blank lines
+    let mut out = Vec::with_capacity(lines.len());
+    for run in lines.split(|line| line.is_none()) {
+        let width = run
+            .iter()
+            .flatten()
+            .filter(|line| line.comment.is_some())
+            .map(|line| (line.code.len() + 1).next_multiple_of(COMMENT_STEP))
+            .max()
+            .unwrap_or_default()
+            .max(COMMENT_COLUMN);
+        for line in run.iter().flatten() {
+            out.push(match &line.comment {
+                Some(comment) => format!("{:<width$}{}", line.code, comment),
+                None => line.code.clone(),
+            });
+        }
+        out.push(String::new());
+    }
+
+    // Blank lines are kept, but not at the end
+    while out.last().is_some_and(String::is_empty) {
+        out.pop();
+    }
+    let mut out = out.join("\n");
+    out.push('\n');
+    Ok(out)
+}
+
+/// Indents given full-line comments, at given positions among given lines
+fn flush(lines: &mut [Option<Formatted>], pending: &mut Vec<(usize, String)>, indent: usize) {
+    for (i, comment) in pending.drain(..) {
+        lines[i] = Some(Formatted {
+            code: format!("{}{}", " ".repeat(indent), comment),
+            comment: None,
+        });
+    }
+}
+
+/// Token of an operand, as far as its spacing is concerned
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Spacing {
+    /// Number, symbol, register, string or character
+    Atom,
+    Open,
+    Close,
+    Binary,
+    Unary,
+}
+
+/// Respaces an operand canonically, keeping its tokens as written: one space
+/// around binary operators, and none within parentheses, after unary
+/// operators, or before the parentheses of a function call or memory reference
+fn canonical_operand(operand: &str) -> String {
+    let mut out = String::with_capacity(operand.len());
+    let mut prev = None;
+    let mut rest = operand.trim();
+
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        let len = match c {
+            '"' | '\'' => quoted_len(rest),
+            '<' | '>' if rest[1..].starts_with(c) => 2,
+            _ if is_word_char(c) => rest.find(|c| !is_word_char(c)).unwrap_or(rest.len()),
+            _ => c.len_utf8(),
+        };
+        let (token, after) = rest.split_at(len);
+        rest = after;
+
+        let kind = match c {
+            '(' => Spacing::Open,
+            ')' => Spacing::Close,
+            '~' => Spacing::Unary,
+            // Operators are unary where no operand comes before them
+            '-' if matches!(
+                prev,
+                None | Some(Spacing::Open | Spacing::Binary | Spacing::Unary)
+            ) =>
+            {
+                Spacing::Unary
+            }
+            '*' | '/' | '%' | '+' | '-' | '<' | '>' | '&' | '^' | '|' => Spacing::Binary,
+            _ => Spacing::Atom,
+        };
+        let space = match (prev, kind) {
+            (None, _) | (Some(Spacing::Open | Spacing::Unary), _) => false,
+            (_, Spacing::Binary) | (Some(Spacing::Binary), _) => true,
+            (Some(Spacing::Atom | Spacing::Close), Spacing::Atom | Spacing::Unary) => true,
+            _ => false,
+        };
+        if space {
+            out.push(' ');
+        }
+        out.push_str(token);
+        prev = Some(kind);
+    }
+    out
+}
+
+/// Whether a character may appear in numbers, symbols, registers and
+/// macro parameters
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '\\'
+}
+
+/// Length of the string or character literal starting a given text,
+/// up to its closing quote (or the end of the text)
+fn quoted_len(text: &str) -> usize {
+    let mut chars = text.char_indices();
+    let quote = chars.next().map(|(_, q)| q);
+    let mut escaped = false;
+    for (i, c) in chars {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if Some(c) == quote => return i + c.len_utf8(),
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(src: &str) -> String {
+        format_source("prog.asm", src, &Includes::default()).unwrap()
+    }
+
+    #[test]
+    fn test_format() {
+        // The example is already formatted
+        let fib = include_str!("../../examples/fib.asm");
+        assert_eq!(format(fib), fib);
+
+        let src = "\
+;   Header comment
+.DATA
+msg:   .string   \"a,  b\" ; text
+  vals:.byte 1,2 ,  'x'
+.TEXT
+     ; about main
+main: LI r1,3;count
+loop:   DEC   r1
+    BNZ loop  #   back
+    lb r2, msg + 1 ( r0 )   ; a very long line of code indeed
+ .macro twice reg ,  n
+addi \\reg, \\reg, \\n
+  .endm
+
+
+   halt
+
+
+";
+        let expected = "\
+;   Header comment
+.data
+msg:
+  .string \"a,  b\"       ; text
+vals:
+  .byte 1, 2, 'x'
+.text
+; about main
+main:
+  li   r1, 3            ;count
+loop:
+  dec  r1
+  bnz  loop             #   back
+  lb   r2, msg + 1(r0)  ; a very long line of code indeed
+.macro twice reg, n
+  addi \\reg, \\reg, \\n
+.endm
+
+
+  halt
+";
+        assert_eq!(format(src), expected);
+        assert_eq!(format(expected), expected);
+
+        // Nothing is formatted unless it parses
+        let errors = format_source("prog.asm", "  addi r1\n", &Includes::default()).unwrap_err();
+        assert_eq!(errors.errors.len(), 1);
+    }
+
+    #[test]
+    fn test_canonical_operand() {
+        assert_eq!(canonical_operand("lo( a  +\t1 )"), "lo(a + 1)");
+        assert_eq!(canonical_operand("msg+1 ( r0 )"), "msg + 1(r0)");
+        assert_eq!(canonical_operand("-1(r2)"), "-1(r2)");
+        assert_eq!(
+            canonical_operand("2*( A<<1 )- ~ 0xf"),
+            "2 * (A << 1) - ~0xf"
+        );
+        assert_eq!(canonical_operand("a - - 1"), "a - -1");
+        assert_eq!(canonical_operand("hi (x)"), "hi(x)");
+        assert_eq!(canonical_operand("\\reg"), "\\reg");
+        assert_eq!(canonical_operand("\"a  \\\"  b\""), "\"a  \\\"  b\"");
+        assert_eq!(canonical_operand("'  '"), "'  '");
+        assert_eq!(canonical_operand("' '+1"), "' ' + 1");
+    }
+
+    #[test]
+    fn test_spellings() {
+        // The same code formats the same, however it is spaced
+        let a =
+            format("  lb r2, msg + 1 ( r0 )\n  addi r1, r1, lo( msg+2 )\n.data\nmsg: .byte 1\n");
+        let b = format("  lb r2, msg+1(r0)\n  addi r1,r1,lo(msg + 2)\n.data\nmsg: .byte 1\n");
+        assert_eq!(a, b);
+        assert_eq!(
+            a,
+            "  lb   r2, msg + 1(r0)\n  addi r1, r1, lo(msg + 2)\n.data\nmsg:\n  .byte 1\n"
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+/// Reads the contents of a file
+type Reader = Box<dyn Fn(&Path) -> io::Result<String>>;
+
+/// Where files named by `.include` are looked up, and how they are read
+pub struct Includes {
+    /// Directories searched, in order, after that of the including file
+    pub paths: Vec<PathBuf>,
+    read: Reader,
+}
+
+impl Default for Includes {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl Includes {
+    /// Looks up included files in a given list of directories, reading them from disk.
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        Self {
+            paths,
+            read: Box::new(|path| std::fs::read_to_string(path)),
+        }
+    }
+
+    /// Reads included files with a given function instead of from disk.
+    pub fn with_reader(mut self, read: impl Fn(&Path) -> io::Result<String> + 'static) -> Self {
+        self.read = Box::new(read);
+        self
+    }
+
+    /// Paths a given file included from another one may be found at, in order of precedence
+    pub fn candidates(&self, name: &str, from: &str) -> Vec<PathBuf> {
+        let name = Path::new(name);
+        if name.is_absolute() {
+            return vec![normalize(name)];
+        }
+        let dir = Path::new(from).parent().unwrap_or(Path::new(""));
+        let mut candidates = Vec::new();
+        for dir in std::iter::once(dir).chain(self.paths.iter().map(PathBuf::as_path)) {
+            let path = normalize(&dir.join(name));
+            if !candidates.contains(&path) {
+                candidates.push(path);
+            }
+        }
+        candidates
+    }
+
+    /// Finds and reads a given file included from another one.
+    /// Returns the path it was found at along with its contents,
+    /// or `None` if it is found nowhere.
+    pub fn resolve(&self, name: &str, from: &str) -> io::Result<Option<(String, String)>> {
+        for path in self.candidates(name, from) {
+            match (self.read)(&path) {
+                Ok(src) => return Ok(Some((path.to_string_lossy().into_owned(), src))),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Removes `.` components and resolves `..` components of a given path
+/// where possible, so that a file is named the same however it is reached
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match out.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    out.pop();
+                }
+                Some(Component::RootDir) => {}
+                _ => out.push(".."),
+            },
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let includes =
+            Includes::new(vec!["lib".into(), "/usr/share/cobble".into()]).with_reader(|path| {
+                match path.to_str() {
+                    Some("src/util.asm") => Ok("; util".to_string()),
+                    Some("lib/std.asm") => Ok("; std".to_string()),
+                    Some("/usr/share/cobble/std.asm") => Ok("; shadowed".to_string()),
+                    Some("lib/secret.asm") => Err(io::ErrorKind::PermissionDenied.into()),
+                    _ => Err(io::ErrorKind::NotFound.into()),
+                }
+            });
+
+        assert_eq!(
+            includes.candidates("../std.asm", "src/main.asm"),
+            [
+                PathBuf::from("std.asm"),
+                PathBuf::from("/usr/share/std.asm")
+            ]
+        );
+        assert_eq!(
+            includes.candidates("/std.asm", "main.asm"),
+            [PathBuf::from("/std.asm")]
+        );
+
+        // The including file's directory comes first, then include paths in order
+        assert_eq!(
+            includes.resolve("./util.asm", "src/main.asm").unwrap(),
+            Some(("src/util.asm".to_string(), "; util".to_string()))
+        );
+        assert_eq!(
+            includes.resolve("std.asm", "src/main.asm").unwrap(),
+            Some(("lib/std.asm".to_string(), "; std".to_string()))
+        );
+        assert_eq!(includes.resolve("none.asm", "main.asm").unwrap(), None);
+        assert!(includes.resolve("secret.asm", "main.asm").is_err());
+    }
+}
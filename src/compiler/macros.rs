@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+
+use crate::compiler::{ast::*, parser::Statement, span::Span, symbol::is_numeric};
+
+/// A macro defined with `.macro name param, ...` and `.endm`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Macro {
+    pub name: String,
+    pub params: Vec<String>,
+    /// Lines between `.macro` and `.endm`, with their line numbers
+    pub body: Vec<(usize, String)>,
+    /// Span of the macro name in its definition
+    pub span: Span,
+}
+
+impl Macro {
+    /// Usage of the macro, i.e. "zero reg"
+    pub fn usage(&self) -> String {
+        match self.params.is_empty() {
+            true => format!("usage: `{}`", self.name),
+            false => format!("usage: `{} {}`", self.name, self.params.join(", ")),
+        }
+    }
+
+    /// Body lines of the macro with each `\param` replaced by its argument
+    pub fn expand(&self, args: &[&str]) -> Vec<(usize, String)> {
+        self.body
+            .iter()
+            .map(|(n, text)| (*n, substitute(text, &self.params, args)))
+            .collect()
+    }
+}
+
+/// Replaces each `\param` in a given line with its argument.
+/// Strings and characters are kept as written, so their escapes
+/// are never taken for parameters, nor are other backslashes.
+fn substitute(text: &str, params: &[String], args: &[&str]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut quote = None;
+    let mut escaped = false;
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        if let Some(q) = quote {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                _ if c == q => quote = None,
+                _ => {}
+            }
+            out.push(c);
+            continue;
+        }
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '\\' => {
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                if let Some(k) = params.iter().position(|p| *p == rest[..len]) {
+                    out.push_str(args[k]);
+                    rest = &rest[len..];
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Splits the arguments of a macro call at top-level commas, up to a comment.
+/// Commas within parentheses, strings or characters do not split arguments.
+pub fn split_args(text: &str) -> Vec<&str> {
+    split_comment(text).0
+}
+
+/// Like [`split_args`], but also returns the comment ending the text, if any.
+pub fn split_comment(text: &str) -> (Vec<&str>, Option<&str>) {
+    let mut args = Vec::new();
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut start = 0;
+    let mut end = text.len();
+
+    for (i, c) in text.char_indices() {
+        if let Some(q) = quote {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                _ if c == q => quote = None,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                args.push(text[start..i].trim());
+                start = i + 1;
+            }
+            ';' | '#' => {
+                end = i;
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    let last = text[start..end].trim();
+    if !args.is_empty() || !last.is_empty() {
+        args.push(last);
+    }
+    let comment = (end < text.len()).then(|| text[end..].trim_end());
+    (args, comment)
+}
+
+/// Makes the labels and constants defined by a macro expansion unique to it,
+/// so that expanding a macro more than once does not redefine them:
+/// `name` becomes `name@id`. Numeric labels are unique by nature, and left as is.
+pub fn rename_locals(statements: &mut [Statement], id: usize) {
+    let renamed: HashMap<String, String> = statements
+        .iter()
+        .filter_map(|s| match &s.instr {
+            Instr::Label(label) if !is_numeric(label) => Some(label),
+            Instr::Directive(Directive::Equ(name, _)) => Some(name),
+            _ => None,
+        })
+        .map(|name| (name.clone(), format!("{}@{}", name, id)))
+        .collect();
+    let rename = |name: &str| renamed.get(name).cloned();
+
+    for stmt in statements.iter_mut() {
+        stmt.instr = match &stmt.instr {
+            Instr::Label(label) => Instr::Label(rename(label).unwrap_or(label.clone())),
+            Instr::Directive(d) => {
+                let mut d = d.clone();
+                if let Directive::Equ(name, _) = &mut d
+                    && let Some(r) = rename(name)
+                {
+                    *name = r;
+                }
+                d.exprs_mut().into_iter().for_each(|e| e.rename(&rename));
+                Instr::Directive(d)
+            }
+            instr => {
+                let ops = instr
+                    .operands()
+                    .into_iter()
+                    .map(|op| match op {
+                        Op::Label(label) => Op::Label(rename(label).unwrap_or(label.clone())),
+                        Op::Expr(e) => {
+                            let mut e = e.clone();
+                            e.rename(&rename);
+                            Op::Expr(e)
+                        }
+                        _ => op.clone(),
+                    })
+                    .collect();
+                Instr::from_parts(instr.mnemonic(), ops)
+                    .expect("operands should be valid for their instruction")
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(split_args(""), Vec::<&str>::new());
+        assert_eq!(split_args("  ; comment"), Vec::<&str>::new());
+        assert_eq!(split_args("r1, r2 ; comment"), vec!["r1", "r2"]);
+        assert_eq!(
+            split_args("4(r2), lo(a, b), ',', \"x;y\" # comment"),
+            vec!["4(r2)", "lo(a, b)", "','", "\"x;y\""]
+        );
+        assert_eq!(split_args("r1, "), vec!["r1", ""]);
+        assert_eq!(
+            split_comment("r1, r2  ; comment  "),
+            (vec!["r1", "r2"], Some("; comment"))
+        );
+        assert_eq!(split_comment("\"#\""), (vec!["\"#\""], None));
+    }
+
+    #[test]
+    fn test_expand() {
+        let m = Macro {
+            name: "dbnz".to_string(),
+            params: vec!["reg".to_string(), "target".to_string()],
+            body: vec![
+                (2, "  addi \\reg, \\reg, -1".to_string()),
+                (3, "  bnz \\target ; \\regs".to_string()),
+                (4, "  .string \"\\n\"".to_string()),
+            ],
+            span: Span::new("a.asm", 1, 8, 4),
+        };
+        assert_eq!(m.usage(), "usage: `dbnz reg, target`");
+        assert_eq!(
+            m.expand(&["r3", "loop"]),
+            vec![
+                (2, "  addi r3, r3, -1".to_string()),
+                (3, "  bnz loop ; \\regs".to_string()),
+                (4, "  .string \"\\n\"".to_string()),
+            ]
+        );
+
+        // Parameters named like escapes are not replaced within quotes
+        let m = Macro {
+            name: "say".to_string(),
+            params: vec!["n".to_string()],
+            body: vec![
+                (2, "  .string \"a\\n\"".to_string()),
+                (3, "  .byte '\\n', '\\'', \\n".to_string()),
+            ],
+            span: Span::new("a.asm", 1, 8, 3),
+        };
+        assert_eq!(
+            m.expand(&["3"]),
+            vec![
+                (2, "  .string \"a\\n\"".to_string()),
+                (3, "  .byte '\\n', '\\'', 3".to_string()),
+            ]
+        );
+    }
+}
@@ -1,26 +1,586 @@
 pub mod ast;
+pub mod diagnostic;
+pub mod expr;
+pub mod format;
+pub mod include;
+pub mod macros;
 pub mod parser;
+pub mod pseudo;
+pub mod span;
 pub mod symbol;
 
-use ast::Program;
-use parser::parse_program;
-use symbol::{replace_symbols, strip_symbols};
+use std::collections::HashMap;
+
+use crate::assembler::object::{Export, RelocKind, Relocation, Target};
+use ast::{Directive, Expr, Instr, Op, Program};
+use diagnostic::{Diagnostic, DiagnosticKind, Diagnostics};
+use expr::{BinOp, ExprError};
+use include::Includes;
+use parser::{Parsed, Statement, parse_sources};
+use span::{SourceMap, Span};
+use symbol::{
+    Section, Site, Stripped, SymbolError, SymbolTable, check_symbols, replace_symbols,
+    scope_local_labels, strip_symbols, with_externs,
+};
+
+/// A compiled program, along with what is known about its source
+#[derive(Debug, Clone)]
+pub struct Compiled {
+    pub program: Program,
+    /// Initial contents of data memory
+    pub data: Vec<u8>,
+    pub symbols: SymbolTable,
+    /// Section each label was defined in, constants excluded
+    pub labels: HashMap<String, Section>,
+    pub source_map: SourceMap,
+}
 
 /// Compiles a given program from a string into AST,
 /// with symbols stripped and replaced.
-pub fn compile_program(src: &str) -> Result<Program, String> {
-    // Parse program into AST
-    let prg = parse_program(src).map_err(|e| format!("Parse error: {}", e))?;
+pub fn compile_program(src: &str) -> Result<Program, Diagnostics> {
+    compile("<input>", src).map(|c| c.program)
+}
+
+/// Like [`compile_program`], but also returns the symbol table and
+/// source map of the program, attributing its source to a given file.
+/// Files it includes are looked up next to it.
+pub fn compile(file: &str, src: &str) -> Result<Compiled, Diagnostics> {
+    compile_sources(&[(file, src)], &Includes::default())
+}
+
+/// Like [`compile`], but for a program given as several files, one after
+/// the other, looking up the files they include with given `includes`.
+pub fn compile_sources(
+    sources: &[(&str, &str)],
+    includes: &Includes,
+) -> Result<Compiled, Diagnostics> {
+    let (statements, source_map) = parse_checked(sources, includes)?;
+    let (stripped, program) = lay_out(&statements, &source_map)?;
+
+    // Without a linker, external symbols must be defined by one of the sources
+    let errors: Vec<Diagnostic> = stripped
+        .externs
+        .iter()
+        .map(|name| {
+            let (stmt, span) = declaration(name, &statements);
+            stmt.note_expansion(
+                Diagnostic::new(
+                    DiagnosticKind::UndefinedLabel,
+                    span.clone(),
+                    format!("`{}` is declared `.extern` but never defined", name),
+                )
+                .with_label("not defined by any source")
+                .with_hint("build an object with `-c`, then link it with one defining it"),
+            )
+        })
+        .collect();
+    if !errors.is_empty() {
+        return Err(Diagnostics {
+            errors,
+            sources: source_map,
+        });
+    }
+
+    Ok(finish(statements, stripped, program, source_map))
+}
+
+/// A compiled program yet to be linked with others, see [`compile_object`]
+#[derive(Debug, Clone)]
+pub struct CompiledObject {
+    /// Program whose relocated operands and data hold
+    /// their value relative to their target
+    pub compiled: Compiled,
+    pub exports: Vec<Export>,
+    /// Symbols declared `.extern` and left for other objects to define
+    pub externs: Vec<String>,
+    pub relocations: Vec<Relocation>,
+}
+
+/// Like [`compile_sources`], but compiles the program into an object,
+/// whose references to addresses are relocated once linked.
+/// Symbols declared `.extern` may be left undefined, and those
+/// declared `.global` are exported.
+pub fn compile_object(
+    sources: &[(&str, &str)],
+    includes: &Includes,
+) -> Result<CompiledObject, Diagnostics> {
+    let (statements, source_map) = parse_checked(sources, includes)?;
+    let (stripped, program) = lay_out(&statements, &source_map)?;
+
+    // Addresses are only known relative to their section or symbol
+    let target = |name: &str| match stripped.externs.iter().any(|e| e == name) {
+        true => Some(Target::Symbol(name.to_string())),
+        false => stripped.labels.get(name).map(|s| Target::Section(*s)),
+    };
+    let table = with_externs(&stripped.symbols, &stripped.externs);
+    let mut errors = Vec::new();
+    let mut relocations = Vec::new();
+    let mut relocate = |e: &Expr, kind: RelocKind, offset: u16, stmt: &Statement, operand| {
+        let error = |message: String| {
+            let span = stmt.operands.get(operand).unwrap_or(&stmt.span).clone();
+            stmt.note_expansion(
+                Diagnostic::new(DiagnosticKind::InvalidOperand, span, message)
+                    .with_label("address only known once linked")
+                    .with_hint("addresses may only be used as `label`, `label + n` or `label - n`"),
+            )
+        };
+        match relocation_target(e, &target) {
+            Ok(None) => {}
+            Ok(Some(target)) => relocations.push(Relocation {
+                kind,
+                offset,
+                target,
+                addend: e
+                    .eval(&|s| table.get(s).copied())
+                    .expect("laid out expressions should evaluate"),
+            }),
+            Err(()) => errors.push(error(format!("`{}` cannot be relocated", e))),
+        }
+    };
 
-    // Strip symbols
-    let (stripped, symbols) = strip_symbols(&prg).map_err(|e| format!("Symbol error: {}", e))?;
+    for (pc, instr) in stripped.code.iter().enumerate() {
+        let index = stripped
+            .addresses
+            .iter()
+            .position(|a| *a == Some(pc as u16));
+        // Padding takes no operands
+        let Some(index) = index else { continue };
+        let kind = match instr.has_imm8() {
+            true => RelocKind::Imm8,
+            false => RelocKind::Imm12,
+        };
+        for (operand, op) in instr.operands().into_iter().enumerate() {
+            let e = match op {
+                Op::Label(symbol) => Expr::Symbol(symbol.clone()),
+                Op::Expr(e) => e.clone(),
+                _ => continue,
+            };
+            relocate(&e, kind, pc as u16, &statements[index], operand);
+        }
+    }
+    for value in &stripped.values {
+        let stmt = &statements[value.index];
+        let Instr::Directive(d) = &stmt.instr else {
+            unreachable!("data values should come from directives");
+        };
+        let kind = match value.width {
+            1 => RelocKind::Byte,
+            _ => RelocKind::Word,
+        };
+        relocate(
+            d.exprs()[value.operand],
+            kind,
+            value.offset,
+            stmt,
+            value.operand,
+        );
+    }
 
-    // Replace symbol references
-    let replaced =
-        replace_symbols(&stripped, &symbols).map_err(|e| format!("Symbol error: {}", e))?;
+    // Constants are not relocated, so may not depend on addresses
+    for stmt in &statements {
+        if let Instr::Directive(Directive::Equ(name, e)) = &stmt.instr
+            && let Some(symbol) = e.symbols().into_iter().find(|s| target(s).is_some())
+        {
+            errors.push(
+                stmt.note_expansion(
+                    Diagnostic::new(
+                        DiagnosticKind::InvalidOperand,
+                        stmt.operands[0].clone(),
+                        format!(
+                            "constant `{}` cannot depend on the address `{}`",
+                            name, symbol
+                        ),
+                    )
+                    .with_label("address only known once linked"),
+                ),
+            );
+        }
+    }
+
+    let mut exports: Vec<Export> = Vec::new();
+    for stmt in &statements {
+        let Instr::Directive(Directive::Global(names)) = &stmt.instr else {
+            continue;
+        };
+        for (name, span) in names.iter().zip(&stmt.operands) {
+            if exports.iter().any(|e| e.name == *name) {
+                continue;
+            }
+            match stripped.symbols.get(name) {
+                Some(value) => exports.push(Export {
+                    name: name.clone(),
+                    section: stripped.labels.get(name).copied(),
+                    value: *value,
+                }),
+                None => errors.push(
+                    stmt.note_expansion(
+                        Diagnostic::new(
+                            DiagnosticKind::UndefinedLabel,
+                            span.clone(),
+                            format!("`{}` is declared `.extern`, so cannot be exported", name),
+                        )
+                        .with_label("not defined by this object"),
+                    ),
+                ),
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(Diagnostics {
+            errors,
+            sources: source_map,
+        });
+    }
+    let externs = stripped.externs.clone();
+    Ok(CompiledObject {
+        compiled: finish(statements, stripped, program, source_map),
+        exports,
+        externs,
+        relocations,
+    })
+}
 
-    Ok(replaced)
+/// Target of the relocation a given expression needs, if any, given the
+/// target of each symbol. Only a single address, plus or minus a value
+/// independent of addresses, can be relocated.
+fn relocation_target(
+    e: &Expr,
+    target: &impl Fn(&str) -> Option<Target>,
+) -> Result<Option<Target>, ()> {
+    let relocatable = |e: &Expr| e.symbols().into_iter().any(|s| target(s).is_some());
+    if !relocatable(e) {
+        return Ok(None);
+    }
+    match e {
+        Expr::Symbol(s) => Ok(target(s)),
+        Expr::Binary(BinOp::Add, a, b) if !relocatable(b) => relocation_target(a, target),
+        Expr::Binary(BinOp::Add, a, b) if !relocatable(a) => relocation_target(b, target),
+        Expr::Binary(BinOp::Sub, a, b) if !relocatable(b) => relocation_target(a, target),
+        _ => Err(()),
+    }
+}
+
+/// Parses a program from given sources, then checks its symbols,
+/// collecting all errors along the way.
+fn parse_checked(
+    sources: &[(&str, &str)],
+    includes: &Includes,
+) -> Result<(Vec<Statement>, SourceMap), Diagnostics> {
+    let Parsed {
+        mut statements,
+        mut errors,
+        included,
+    } = parse_sources(sources, includes);
+
+    let mut source_map = SourceMap::new();
+    for (file, src) in sources {
+        source_map.add_source(file, src);
+    }
+    for (file, src) in &included {
+        source_map.add_source(file, src);
+    }
+
+    scope_local_labels(&mut statements);
+    errors.extend(check_symbols(&statements));
+    if !errors.is_empty() {
+        return Err(Diagnostics {
+            errors,
+            sources: source_map,
+        });
+    }
+    Ok((statements, source_map))
+}
+
+/// Lays out checked statements, then replaces symbol references,
+/// with external symbols left undefined at address 0.
+fn lay_out(
+    statements: &[Statement],
+    source_map: &SourceMap,
+) -> Result<(Stripped, Program), Diagnostics> {
+    let prg: Program = statements.iter().map(|s| s.instr.clone()).collect();
+    let fail = |e, stripped: &Stripped| Diagnostics {
+        errors: vec![layout_error(e, statements, stripped)],
+        sources: source_map.clone(),
+    };
+    let stripped = strip_symbols(&prg).map_err(|e| fail(e, &Stripped::default()))?;
+    let table = with_externs(&stripped.symbols, &stripped.externs);
+    let replaced = replace_symbols(&stripped.code, &table).map_err(|e| fail(e, &stripped))?;
+    Ok((stripped, replaced))
+}
+
+/// Collects a laid out program, with the span of each of its instructions.
+fn finish(
+    statements: Vec<Statement>,
+    stripped: Stripped,
+    program: Program,
+    mut source_map: SourceMap,
+) -> Compiled {
+    // Padding and directives take up no instructions, so neither do their spans
+    for (stmt, address) in statements.into_iter().zip(&stripped.addresses) {
+        if let Some(pc) = address {
+            source_map.insert(*pc, stmt.span);
+        }
+    }
+
+    Compiled {
+        program,
+        data: stripped.data,
+        symbols: stripped.symbols,
+        labels: stripped.labels,
+        source_map,
+    }
+}
+
+/// Statement declaring a given symbol `.extern`, and the span of the symbol in it
+fn declaration<'a>(name: &str, statements: &'a [Statement]) -> (&'a Statement, &'a Span) {
+    statements
+        .iter()
+        .find_map(|stmt| match &stmt.instr {
+            Instr::Directive(Directive::Extern(names)) => {
+                let i = names.iter().position(|n| n == name)?;
+                Some((stmt, &stmt.operands[i]))
+            }
+            _ => None,
+        })
+        .expect("external symbols should be declared")
+}
+
+/// Points an error raised while laying out a program at the statement it was raised for.
+fn layout_error(e: SymbolError, statements: &[Statement], stripped: &Stripped) -> Diagnostic {
+    let message = e.to_string();
+    let (index, diag) = match e {
+        SymbolError::OrgBackwards { index, .. }
+        | SymbolError::WrongSection { index, .. }
+        | SymbolError::SectionOverflow { index, .. } => (
+            index,
+            Diagnostic::new(
+                DiagnosticKind::Layout,
+                statements[index].span.clone(),
+                message,
+            ),
+        ),
+        SymbolError::OutOfRange { site, operand, .. } | SymbolError::Eval { site, operand, .. } => {
+            let index = match site {
+                Site::Statement(index) => index,
+                Site::Instr(pc) => stripped
+                    .addresses
+                    .iter()
+                    .position(|a| *a == Some(pc))
+                    .expect("instruction should have been laid out"),
+            };
+            let stmt = &statements[index];
+            let span = stmt.operands.get(operand).unwrap_or(&stmt.span).clone();
+            let diag = match e {
+                SymbolError::OutOfRange { .. } => {
+                    Diagnostic::new(DiagnosticKind::ImmediateTooLarge, span, message)
+                        .with_label("value out of range")
+                }
+                SymbolError::Eval {
+                    error: ExprError::Undefined(_),
+                    ..
+                } => Diagnostic::new(DiagnosticKind::UndefinedLabel, span, message)
+                    .with_label("must be defined before this point"),
+                _ => Diagnostic::new(DiagnosticKind::InvalidOperand, span, message),
+            };
+            (index, diag)
+        }
+        e => unreachable!("symbols should be checked: {}", e),
+    };
+    statements[index].note_expansion(diag)
 }
 
 #[test]
-fn test_compiler() {}
+fn test_compiler() {
+    let compiled = compile("prog.asm", "start:\n  addi r1, r0, 1\n\n  jmp start\n").unwrap();
+    assert_eq!(compiled.program.len(), 2);
+    assert_eq!(compiled.symbols.get("start"), Some(&0));
+    assert_eq!(
+        compiled.source_map.get(1),
+        Some(&span::Span::new("prog.asm", 4, 3, 9))
+    );
+
+    // Local labels resolve within their scope
+    let compiled = compile("prog.asm", "a:\n.x: jmp .x\nb:\n.x: jmp 1f\n1: jmp .x\n").unwrap();
+    assert_eq!(
+        compiled.program[2],
+        ast::Instr::Jmp {
+            imm: ast::Op::Imm12(1)
+        }
+    );
+
+    // Parse and symbol errors are reported together
+    let errors = compile("prog.asm", "adi r1, r0, 1\njmp end\n").unwrap_err();
+    assert_eq!(errors.errors.len(), 2);
+    assert!(
+        errors
+            .to_string()
+            .ends_with("Aborting due to 2 previous errors")
+    );
+
+    // Data is laid out apart from the code
+    let compiled = compile(
+        "prog.asm",
+        ".data\nmsg: .string \"hi\"\n.text\n.org 2\n  lb r1, msg(r0)\n",
+    )
+    .unwrap();
+    assert_eq!(compiled.data, b"hi\0");
+    assert_eq!(compiled.program.len(), 3);
+    assert!(compiled.source_map.get(0).is_none());
+    assert_eq!(compiled.source_map.get(2).unwrap().line, 5);
+
+    // Layout errors point at their statement
+    let errors = compile("prog.asm", ".equ BIG, 0x100\n  addi r1, r0, BIG\n").unwrap_err();
+    assert_eq!(errors.errors[0].kind, DiagnosticKind::ImmediateTooLarge);
+    assert_eq!(errors.errors[0].span, span::Span::new("prog.asm", 2, 16, 3));
+    let errors = compile("prog.asm", "nop\n.byte 1\n").unwrap_err();
+    assert_eq!(errors.errors[0].kind, DiagnosticKind::Layout);
+    assert_eq!(errors.errors[0].span.line, 2);
+
+    // Expressions are evaluated once symbols are known
+    let src = "\
+.equ BUF_LEN, 4 * 2
+.equ MINUS, -1
+.data
+buf: .zero BUF_LEN
+ptrs: .word end, buf + BUF_LEN
+.byte hi(end), lo(end)
+.text
+  addi r1, r0, BUF_LEN - 1
+  addi r2, r0, MINUS
+  sb r1, buf + 1(r0)
+.org 0x120
+end: jmp end
+";
+    let compiled = compile("prog.asm", src).unwrap();
+    assert_eq!(compiled.data[8..], [0x20, 0x01, 0x08, 0x00, 0x01, 0x20]);
+    let imms: Vec<_> = compiled.program[..3]
+        .iter()
+        .map(|i| i.operands()[2].clone())
+        .collect();
+    assert_eq!(
+        imms,
+        [ast::Op::Imm8(7), ast::Op::Imm8(0xff), ast::Op::Imm8(1)]
+    );
+
+    // Out of range values point at their operand
+    let errors = compile("prog.asm", ".org 0x100\nend: addi r1, r0, end + 1\n").unwrap_err();
+    assert_eq!(errors.errors[0].kind, DiagnosticKind::ImmediateTooLarge);
+    assert_eq!(errors.errors[0].span, span::Span::new("prog.asm", 2, 19, 7));
+    let errors = compile("prog.asm", ".data\n.byte 1, end\n.text\n.org 0x100\nend:\n").unwrap_err();
+    assert_eq!(errors.errors[0].span, span::Span::new("prog.asm", 2, 10, 3));
+
+    // Labels after a pseudo-instruction account for all of its expansion
+    let compiled = compile("prog.asm", "  beq r1, r2, end\n  inc r1\nend: halt\n").unwrap();
+    assert_eq!(compiled.symbols.get("end"), Some(&3));
+    assert_eq!(
+        compiled.program[1],
+        ast::Instr::Bz {
+            imm: ast::Op::Imm12(3)
+        }
+    );
+    assert_eq!(compiled.source_map.get(1).unwrap().line, 1);
+
+    // Programs span several files, and render errors from any of them
+    let includes = include::Includes::default().with_reader(|path| match path.to_str() {
+        Some("lib.asm") => Ok("double: add r1, r1, r1\n  ret\n  jmp nowhere\n".to_string()),
+        _ => Err(std::io::ErrorKind::NotFound.into()),
+    });
+    let sources = [
+        ("a.asm", ".include \"lib.asm\"\n"),
+        ("b.asm", "call double\n"),
+    ];
+    let errors = compile_sources(&sources, &includes).unwrap_err();
+    assert_eq!(errors.errors[0].span, span::Span::new("lib.asm", 3, 7, 7));
+    assert!(errors.to_string().contains("3 |   jmp nowhere"));
+    let sources = [
+        ("a.asm", ".include \"lib.asm\"\n"),
+        ("b.asm", "call double\nnowhere:\n"),
+    ];
+    let compiled = compile_sources(&sources, &includes).unwrap();
+    assert_eq!(compiled.symbols.get("nowhere"), Some(&4));
+    assert_eq!(compiled.source_map.get(3).unwrap().file, "b.asm");
+
+    // Macro expansions keep their labels to themselves
+    let src = ".macro spin\nloop: jmp loop\n.endm\n  spin\n  spin\n  jmp lop\n";
+    let errors = compile("prog.asm", src).unwrap_err();
+    assert_eq!(errors.errors.len(), 1);
+    assert_eq!(errors.errors[0].hint, None);
+    let errors = compile("prog.asm", ".macro j t\n  jmp \\t\n.endm\n  j end\n").unwrap_err();
+    assert_eq!(errors.errors[0].span, span::Span::new("prog.asm", 4, 3, 5));
+    assert_eq!(
+        errors.errors[0].notes[0].0,
+        span::Span::new("prog.asm", 2, 3, 6)
+    );
+
+    // Layout may only depend on symbols defined before
+    let errors = compile("prog.asm", ".org end\nend: halt\n").unwrap_err();
+    assert_eq!(errors.errors[0].kind, DiagnosticKind::UndefinedLabel);
+    assert_eq!(errors.errors[0].span, span::Span::new("prog.asm", 1, 6, 3));
+
+    // Objects leave addresses to relocations, and external symbols to the linker
+    let src = "\
+.extern putc
+.global main, LEN
+.equ LEN, 2
+main: call putc
+  jmp main + 1
+  addi r1, r0, LEN
+.data
+msg: .byte 1, msg
+";
+    let sources = [("prog.asm", src)];
+    let obj = compile_object(&sources, &Includes::default()).unwrap();
+    assert_eq!(obj.externs, ["putc"]);
+    assert_eq!(
+        obj.exports,
+        [
+            Export {
+                name: "main".to_string(),
+                section: Some(symbol::Section::Text),
+                value: 0
+            },
+            Export {
+                name: "LEN".to_string(),
+                section: None,
+                value: 2
+            }
+        ]
+    );
+    let targets: Vec<_> = obj
+        .relocations
+        .iter()
+        .map(|r| (r.kind, r.offset, r.target.clone(), r.addend))
+        .collect();
+    assert_eq!(
+        targets,
+        [
+            (RelocKind::Imm12, 0, Target::Symbol("putc".to_string()), 0),
+            (
+                RelocKind::Imm12,
+                1,
+                Target::Section(symbol::Section::Text),
+                1
+            ),
+            (
+                RelocKind::Byte,
+                1,
+                Target::Section(symbol::Section::Data),
+                0
+            ),
+        ]
+    );
+    let errors = compile_sources(&sources, &Includes::default()).unwrap_err();
+    assert_eq!(errors.errors[0].span, span::Span::new("prog.asm", 1, 9, 4));
+
+    let src = ".extern x\nmain: addi r1, r0, hi(x)\n.equ A, main\n";
+    let errors = compile_object(&[("prog.asm", src)], &Includes::default()).unwrap_err();
+    let messages: Vec<&str> = errors.errors.iter().map(|e| e.message.as_str()).collect();
+    assert_eq!(
+        messages,
+        [
+            "`hi(x)` cannot be relocated",
+            "constant `A` cannot depend on the address `main`"
+        ]
+    );
+}
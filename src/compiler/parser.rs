@@ -1,235 +1,2003 @@
-use crate::compiler::ast::*;
+// Diagnostics are only built on the error path, so their size is of no concern
+#![allow(clippy::result_large_err)]
+
+use std::{collections::HashMap, path::Path};
+
+use crate::compiler::{
+    ast::*,
+    diagnostic::{Diagnostic, DiagnosticKind, suggest, suggest_by},
+    expr::{BinOp, UnOp, fits},
+    include::{Includes, normalize},
+    macros::{Macro, rename_locals, split_args, split_comment},
+    pseudo,
+    span::Span,
+};
 use nom::{
     IResult, Parser,
     branch::alt,
+    bytes::complete::escaped_transform,
     bytes::complete::tag,
-    character::complete::{alpha1, char, digit1, hex_digit1, multispace0},
-    combinator::{map, map_res},
-    sequence::{preceded, terminated},
+    bytes::complete::{take_while, take_while1},
+    character::complete::{char, digit1, hex_digit1, multispace0, none_of, one_of, satisfy},
+    combinator::{map, map_res, not, opt, peek, recognize, value},
+    sequence::{delimited, pair, preceded, terminated},
 };
-use std::str::FromStr;
-use thiserror::Error;
 
-#[derive(Debug, Error)]
-pub enum ParserError {
-    #[error("Parse error on line {0}: {1}")]
-    Error(usize, String),
+/// Operands taken by each mnemonic, in assembly order
+const SIGNATURES: &[(&str, &str)] = &[
+    ("halt", ""),
+    ("nop", ""),
+    ("ret", ""),
+    ("push", "rs"),
+    ("pop", "rd"),
+    ("mv", "rd, rs1"),
+    ("not", "rd, rs1"),
+    ("cmp", "rs1, rs2"),
+    ("add", "rd, rs1, rs2"),
+    ("sub", "rd, rs1, rs2"),
+    ("and", "rd, rs1, rs2"),
+    ("or", "rd, rs1, rs2"),
+    ("xor", "rd, rs1, rs2"),
+    ("shl", "rd, rs1, rs2"),
+    ("shr", "rd, rs1, rs2"),
+    ("sar", "rd, rs1, rs2"),
+    ("rol", "rd, rs1, rs2"),
+    ("ror", "rd, rs1, rs2"),
+    ("addi", "rd, rs1, imm8"),
+    ("andi", "rd, rs1, imm8"),
+    ("ori", "rd, rs1, imm8"),
+    ("xori", "rd, rs1, imm8"),
+    ("shli", "rd, rs1, imm8"),
+    ("shri", "rd, rs1, imm8"),
+    ("sari", "rd, rs1, imm8"),
+    ("roli", "rd, rs1, imm8"),
+    ("rori", "rd, rs1, imm8"),
+    ("lb", "rd, imm8(rs1)"),
+    ("sb", "rs2, imm8(rs1)"),
+    ("jmp", "target"),
+    ("call", "target"),
+    ("bz", "target"),
+    ("bnz", "target"),
+    ("bc", "target"),
+    ("bnc", "target"),
+    ("bn", "target"),
+    ("bv", "target"),
+    ("blt", "target"),
+    ("bge", "target"),
+    ("bltu", "target"),
+    ("bgeu", "target"),
+    // Pseudo-instructions, see [`pseudo`]
+    ("li", "rd, imm8"),
+    ("inc", "rd"),
+    ("dec", "rd"),
+    ("neg", "rd, rs1"),
+    ("clr", "rd"),
+    ("beq", "rs1, rs2, target"),
+    ("bne", "rs1, rs2, target"),
+];
+
+/// Operands taken by each directive
+const DIRECTIVES: &[(&str, &str)] = &[
+    (".text", ""),
+    (".data", ""),
+    (".equ", "name, value"),
+    (".org", "address"),
+    (".align", "size"),
+    (".byte", "value, ..."),
+    (".word", "value, ..."),
+    (".string", "\"text\""),
+    (".zero", "count"),
+    (".global", "name, ..."),
+    (".extern", "name, ..."),
+    (".include", "\"file\""),
+];
+
+/// Kinds of operands, as named in [`SIGNATURES`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Reg,
+    Imm8,
+    /// 12-bit address or label
+    Target,
+    /// Base register with an 8-bit offset
+    MemRef,
+}
+
+impl Kind {
+    fn from_name(name: &str) -> Self {
+        match name {
+            "imm8" => Self::Imm8,
+            "target" => Self::Target,
+            "imm8(rs1)" => Self::MemRef,
+            _ => Self::Reg,
+        }
+    }
+
+    /// Kinds of the operands of a given signature, in order
+    fn of_signature(signature: &str) -> Vec<Self> {
+        signature
+            .split(", ")
+            .filter(|s| !s.is_empty())
+            .map(Self::from_name)
+            .collect()
+    }
+
+    /// Whether an operand of this kind may be written as a given token
+    fn accepts(&self, token: &Token) -> bool {
+        matches!(
+            (self, token),
+            (Self::Reg, Token::Reg(_))
+                | (Self::Imm8 | Self::Target, Token::Expr(_))
+                | (Self::MemRef, Token::Mem(..))
+        )
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Self::Reg => "register",
+            Self::Imm8 => "immediate",
+            Self::Target => "address or label",
+            Self::MemRef => "memory reference",
+        }
+    }
+}
+
+/// An operand as written, before being checked against its instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Reg(u32),
+    Expr(Expr),
+    Str(String),
+    /// Offset and base register
+    Mem(Expr, u32),
+}
+
+impl Token {
+    fn describe(&self) -> &'static str {
+        match self {
+            Self::Reg(_) => "register",
+            Self::Expr(Expr::Num(_)) => "immediate",
+            Self::Expr(Expr::Symbol(_)) => "symbol",
+            Self::Expr(_) => "expression",
+            Self::Str(_) => "string",
+            Self::Mem(..) => "memory reference",
+        }
+    }
 }
 
-/// Parse a u16 numerical value, hex or decimal
-fn parse_u16(input: &str) -> IResult<&str, u16> {
+/// A parsed instruction (or label), along with the spans of
+/// itself and its operands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub instr: Instr,
+    pub span: Span,
+    /// Spans of the operands, ordered as [`Instr::operands`]
+    pub operands: Vec<Span>,
+    /// Span of the macro line the statement was expanded from, if any
+    pub macro_span: Option<Span>,
+}
+
+impl Statement {
+    /// Adds a note pointing at the macro line the statement was expanded from, if any.
+    pub fn note_expansion(&self, diag: Diagnostic) -> Diagnostic {
+        match &self.macro_span {
+            Some(span) => diag.with_note(span.clone(), "expanded from this macro line"),
+            None => diag,
+        }
+    }
+}
+
+/// Parse a numerical value, hex, binary or decimal
+fn parse_num(input: &str) -> IResult<&str, u32> {
     alt((
         map_res(preceded(tag("0x"), hex_digit1), |hex| {
-            u16::from_str_radix(hex, 16)
+            u32::from_str_radix(hex, 16)
         }),
-        map_res(digit1, u16::from_str),
+        map_res(
+            preceded(tag("0b"), take_while1(|c| c == '0' || c == '1')),
+            |bin| u32::from_str_radix(bin, 2),
+        ),
+        map_res(digit1, str::parse),
     ))
     .parse(input)
 }
 
-/// Parse a u8 numerical value, hex or decimal
-fn parse_u8(input: &str) -> IResult<&str, u8> {
+/// Parse an escape sequence (after the backslash) like "n" → '\n'
+fn parse_escape(input: &str) -> IResult<&str, char> {
     alt((
-        map_res(preceded(tag("0x"), hex_digit1), |hex| {
-            u8::from_str_radix(hex, 16)
+        value('\\', char('\\')),
+        value('"', char('"')),
+        value('\'', char('\'')),
+        value('\n', char('n')),
+        value('\t', char('t')),
+        value('\r', char('r')),
+        value('\0', char('0')),
+    ))
+    .parse(input)
+}
+
+/// Parse a character literal like 'A' or '\n'
+fn parse_char(input: &str) -> IResult<&str, char> {
+    delimited(
+        char('\''),
+        alt((preceded(char('\\'), parse_escape), none_of("\\'"))),
+        char('\''),
+    )
+    .parse(input)
+}
+
+/// Whether a character may appear in identifiers
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+/// Parse an identifier like "loop_1" or ".L0"
+fn parse_ident(input: &str) -> IResult<&str, &str> {
+    recognize(pair(
+        satisfy(|c| c.is_ascii_alphabetic() || c == '_' || c == '.'),
+        take_while(is_ident_char),
+    ))
+    .parse(input)
+}
+
+/// Parse a numeric local label reference like "1b" (backward) or "1f" (forward)
+fn parse_local_ref(input: &str) -> IResult<&str, &str> {
+    terminated(
+        recognize(pair(digit1, one_of("bf"))),
+        not(peek(satisfy(is_ident_char))),
+    )
+    .parse(input)
+}
+
+/// Parse a register name like "r2" → 2
+fn parse_reg(input: &str) -> IResult<&str, u32> {
+    terminated(
+        preceded(char('r'), parse_num),
+        not(peek(satisfy(is_ident_char))),
+    )
+    .parse(input)
+}
+
+/// Parse a string literal like "hi\n", with escapes
+fn parse_string(input: &str) -> IResult<&str, String> {
+    delimited(
+        char('"'),
+        map(
+            opt(escaped_transform(none_of("\\\""), '\\', parse_escape)),
+            Option::unwrap_or_default,
+        ),
+        char('"'),
+    )
+    .parse(input)
+}
+
+/// Parse an operand of an expression: a number, character, symbol,
+/// parenthesized expression, or one of those under a unary operator
+fn parse_primary(input: &str) -> IResult<&str, Expr> {
+    let unary = |op: UnOp| move |e: Expr| Expr::Unary(op, Box::new(e));
+    let parens = || {
+        delimited(
+            (char('('), multispace0),
+            parse_expr,
+            (multispace0, char(')')),
+        )
+    };
+    alt((
+        map(
+            preceded((char('-'), multispace0), parse_primary),
+            unary(UnOp::Neg),
+        ),
+        map(
+            preceded((char('~'), multispace0), parse_primary),
+            unary(UnOp::Not),
+        ),
+        map(
+            preceded((tag("lo"), multispace0), parens()),
+            unary(UnOp::Lo),
+        ),
+        map(
+            preceded((tag("hi"), multispace0), parens()),
+            unary(UnOp::Hi),
+        ),
+        parens(),
+        map(parse_char, |c| Expr::Num(c as u16)),
+        map(parse_local_ref, |l| Expr::Symbol(l.to_string())),
+        map_res(parse_num, |v| u16::try_from(v).map(Expr::Num)),
+        // Register names are never symbols
+        map(preceded(not(parse_reg), parse_ident), |s| {
+            Expr::Symbol(s.to_string())
         }),
-        map_res(digit1, u8::from_str),
     ))
     .parse(input)
 }
 
-/// Parse a register name like "r2" → Operand::Reg(2)
-fn parse_reg(input: &str) -> IResult<&str, Op> {
-    map(preceded(char('r'), parse_u8), Op::Reg).parse(input)
+/// Parse a binary operator
+fn parse_binop(input: &str) -> IResult<&str, BinOp> {
+    alt((
+        value(BinOp::Shl, tag("<<")),
+        value(BinOp::Shr, tag(">>")),
+        map(one_of("*/%+-&^|"), |c| match c {
+            '*' => BinOp::Mul,
+            '/' => BinOp::Div,
+            '%' => BinOp::Rem,
+            '+' => BinOp::Add,
+            '-' => BinOp::Sub,
+            '&' => BinOp::And,
+            '^' => BinOp::Xor,
+            _ => BinOp::Or,
+        }),
+    ))
+    .parse(input)
 }
 
-/// Parse an 8-bit immediate like "42" → Operand::Imm8(42)
-fn parse_imm8(input: &str) -> IResult<&str, Op> {
-    map(parse_u8, Op::Imm8).parse(input)
+/// Parse an expression whose operators bind at least as tight as a given precedence
+fn parse_binary(input: &str, min: u8) -> IResult<&str, Expr> {
+    let (mut input, mut lhs) = parse_primary(input)?;
+    // An operator without an operand is left for the caller to report
+    while let Ok((rest, op)) = preceded(multispace0, parse_binop).parse(input)
+        && op.precedence() >= min
+        && let Ok((rest, rhs)) =
+            preceded(multispace0, |i| parse_binary(i, op.precedence() + 1)).parse(rest)
+    {
+        lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        input = rest;
+    }
+    Ok((input, lhs))
 }
 
-/// Parse a 12-bit immediate like "42" → Operand::Imm12(42)
-fn parse_imm12(input: &str) -> IResult<&str, Op> {
-    map_res(parse_u16, |val| {
-        // Ensure is within 12 bits
-        if val <= 0xFFF {
-            Ok(Op::Imm12(val))
-        } else {
-            Err(nom::error::Error::new(
-                input,
-                nom::error::ErrorKind::TooLarge,
-            ))
-        }
-    })
+/// Parse an expression like "BUF_LEN - 1" or "hi(label)"
+fn parse_expr(input: &str) -> IResult<&str, Expr> {
+    parse_binary(input, 0)
+}
+
+/// Parse a memory reference like "4(r2)" → (4, 2), where the offset
+/// may be any expression (i.e. "buf + 1(r2)") or omitted (i.e. "(r2)")
+fn parse_mem_ref(input: &str) -> IResult<&str, (Expr, u32)> {
+    map(
+        (
+            opt(terminated(parse_expr, multispace0)),
+            delimited(
+                (char('('), multispace0),
+                parse_reg,
+                (multispace0, char(')')),
+            ),
+        ),
+        |(offset, reg)| (offset.unwrap_or(Expr::Num(0)), reg),
+    )
     .parse(input)
 }
 
-/// Parse a label reference like "loop"
-fn parse_label_ref(input: &str) -> IResult<&str, Op> {
-    map(alpha1, |s: &str| Op::Label(s.to_string())).parse(input)
+/// Parse a single operand
+fn parse_token(input: &str) -> IResult<&str, Token> {
+    alt((
+        map(parse_mem_ref, |(offset, reg)| Token::Mem(offset, reg)),
+        map(parse_string, Token::Str),
+        map(parse_reg, Token::Reg),
+        map(parse_expr, Token::Expr),
+    ))
+    .parse(input)
 }
 
-/// Parse a label statement, i.e. "start:"
+/// Parse a label statement, i.e. "start:", ".loop:" or "1:"
 fn parse_label(input: &str) -> IResult<&str, &str> {
-    let (rest, label) = terminated(alpha1, char(':')).parse(input)?;
+    let (rest, label) = terminated(alt((parse_ident, digit1)), char(':')).parse(input)?;
     Ok((rest, label))
 }
 
-/// Parse a single instruction line into AST.
-fn parse_line(input: &str) -> IResult<&str, Vec<Instr>> {
-    // Consume leading whitespace
-    let (input, _) = multispace0(input)?;
+/// Hint on how to use a given mnemonic, per its signature
+fn usage_hint(mnemonic: &str, signature: &str) -> String {
+    match signature {
+        "" => format!("usage: `{}`", mnemonic),
+        _ => format!("usage: `{} {}`", mnemonic, signature),
+    }
+}
+
+/// Whether the rest of a line is a comment
+fn is_comment(input: &str) -> bool {
+    input.starts_with(';') || input.starts_with('#')
+}
+
+/// A line of source code
+struct Line<'a> {
+    file: &'a str,
+    /// Line number, starting at 1
+    n: usize,
+    text: &'a str,
+}
 
-    // Try to parse a label first (label lines end with ':')
+impl Line<'_> {
+    /// Span of a given length, starting at a given suffix of the line
+    fn span(&self, rest: &str, len: usize) -> Span {
+        Span::new(self.file, self.n, self.text.len() - rest.len() + 1, len)
+    }
+}
+
+/// Parse a single line into statements, stopping at its first error.
+///
+/// Lines consist of an optional label, an optional instruction
+/// and an optional comment, in that order:
+///
+/// ```text
+/// loop: addi r1, r1, 1 ; comment
+/// ```
+fn parse_line(line: &Line) -> Result<Vec<Statement>, Diagnostic> {
+    let mut statements = Vec::new();
+    let mut input = line.text.trim_start();
+
+    // Label
     if let Ok((rest, label)) = parse_label(input) {
-        return Ok((rest, vec![Instr::Label(label.to_string())]));
-    }
-
-    // Otherwise we have an opcode + operands.
-    let (input, opcode) = terminated(alpha1, multispace0).parse(input)?;
-
-    match opcode.to_uppercase().as_str() {
-        "HALT" => Ok((input, vec![Instr::Halt])),
-        "NOP" => Ok((input, vec![Instr::Nop])),
-        // Unary ops
-        op @ ("MV" | "NOT") => {
-            let (input, (rd, rs1)) =
-                (parse_reg, preceded((char(','), multispace0), parse_reg)).parse(input)?;
-            Ok((
-                input,
-                vec![match op {
-                    "MV" => Instr::Mv { rd, rs1 },
-                    "NOT" => Instr::Not { rd, rs1 },
-                    _ => unreachable!(),
-                }],
-            ))
+        statements.push(Statement {
+            instr: Instr::Label(label.to_string()),
+            span: line.span(input, label.len()),
+            operands: vec![],
+            macro_span: None,
+        });
+        input = rest.trim_start();
+    }
+
+    // Instruction
+    let mut usage = None;
+    if !input.is_empty() && !is_comment(input) {
+        let (parsed, rest) = match input.starts_with('.') {
+            true => parse_directive(line, input).map(|(stmt, rest)| (vec![stmt], rest))?,
+            false => parse_instr(line, input)?,
+        };
+        // Named as written, as pseudo-instructions expand to other instructions
+        let name = parse_ident(input)
+            .map(|(_, name)| name.to_lowercase())
+            .unwrap_or_default();
+        usage = SIGNATURES
+            .iter()
+            .chain(DIRECTIVES)
+            .find(|(m, _)| *m == name)
+            .map(|(m, signature)| usage_hint(m, signature));
+        statements.extend(parsed);
+        input = rest.trim_start();
+    }
+
+    // Comment, or nothing at all
+    if !input.is_empty() && !is_comment(input) {
+        let garbage = input
+            .split([';', '#'])
+            .next()
+            .unwrap_or_default()
+            .trim_end();
+        let diag = Diagnostic::new(
+            DiagnosticKind::Syntax,
+            line.span(input, garbage.len()),
+            format!("unexpected `{}`", garbage),
+        )
+        .with_label("expected comment or end of line");
+        return Err(match usage {
+            Some(usage) => diag.with_hint(usage),
+            None => diag,
+        });
+    }
+
+    Ok(statements)
+}
+
+/// Parts of a line as written, see [`split_line`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LineParts<'a> {
+    pub label: Option<&'a str>,
+    /// Mnemonic, directive or macro name
+    pub name: Option<&'a str>,
+    /// Operands, split at top-level commas
+    pub operands: Vec<&'a str>,
+    /// Comment, starting with its `;` or `#`
+    pub comment: Option<&'a str>,
+}
+
+/// Splits a line into its label, instruction and comment as written,
+/// without checking them. Returns `None` if the line does not have that
+/// shape, i.e. if something other than a comment follows a label.
+pub fn split_line(text: &str) -> Option<LineParts<'_>> {
+    let mut parts = LineParts::default();
+    let mut input = text.trim_start();
+
+    if let Ok((rest, label)) = parse_label(input) {
+        parts.label = Some(label);
+        input = rest.trim_start();
+    }
+    if input.is_empty() || is_comment(input) {
+        parts.comment = Some(input.trim_end()).filter(|c| !c.is_empty());
+        return Some(parts);
+    }
+
+    let (rest, name) = parse_ident(input).ok()?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) && !is_comment(rest) {
+        return None;
+    }
+    parts.name = Some(name);
+    (parts.operands, parts.comment) = split_comment(rest);
+    Some(parts)
+}
+
+/// Canonical name of a given mnemonic or directive, or `None` if it is neither
+pub fn canonical_name(name: &str) -> Option<&'static str> {
+    let lower = name.to_lowercase();
+    SIGNATURES
+        .iter()
+        .chain(DIRECTIVES)
+        .chain(&[(".macro", ""), (".endm", "")])
+        .find(|(m, _)| *m == lower)
+        .map(|(m, _)| *m)
+}
+
+/// Checks an operand expression at a given span, evaluating it right away
+/// if it is constant, in which case it must fit in a given number of bits
+fn operand_expr(e: &Expr, bits: u32, span: &Span) -> Result<Expr, Diagnostic> {
+    if !e.symbols().is_empty() {
+        return Ok(e.clone());
+    }
+    let v = e.eval(&|_| None).map_err(|err| {
+        Diagnostic::new(
+            DiagnosticKind::InvalidOperand,
+            span.clone(),
+            format!("cannot evaluate `{}`: {}", e, err),
+        )
+    })?;
+    if !fits(v, bits) {
+        return Err(Diagnostic::new(
+            DiagnosticKind::ImmediateTooLarge,
+            span.clone(),
+            format!("immediate `{}` does not fit in {} bits", e, bits),
+        )
+        .with_label(format!(
+            "must be between -{} and {}",
+            1u32 << (bits - 1),
+            (1u32 << bits) - 1
+        )));
+    }
+    Ok(e.clone())
+}
+
+/// Parse an instruction and its operands, returning what is left of the line.
+/// Pseudo-instructions are expanded to the instructions they stand for.
+fn parse_instr<'a>(line: &Line, input: &'a str) -> Result<(Vec<Statement>, &'a str), Diagnostic> {
+    let span = |rest: &str, len: usize| line.span(rest, len);
+
+    let start = input;
+    let Ok((mut input, mnemonic)) = parse_ident(input) else {
+        return Err(Diagnostic::new(
+            DiagnosticKind::Syntax,
+            span(input, 1),
+            "expected instruction",
+        ));
+    };
+    let mnemonic_span = span(start, mnemonic.len());
+
+    let lower = mnemonic.to_lowercase();
+    let Some((mnemonic, signature)) = SIGNATURES.iter().find(|(m, _)| *m == lower) else {
+        let diag = Diagnostic::new(
+            DiagnosticKind::UnknownMnemonic,
+            mnemonic_span,
+            format!("unknown mnemonic `{}`", mnemonic),
+        )
+        .with_label("not an instruction");
+        // Of equally close mnemonics, prefer those taking the operands as written
+        let (written, _) = split_comment(input);
+        let written: Vec<Option<Token>> = written
+            .iter()
+            .map(|op| match parse_token(op) {
+                Ok(("", token)) => Some(token),
+                _ => None,
+            })
+            .collect();
+        let takes_written = |m: &str| {
+            let signature = SIGNATURES.iter().find(|(s, _)| *s == m).map(|(_, s)| *s);
+            let kinds = Kind::of_signature(signature.unwrap_or_default());
+            kinds.len() == written.len()
+                && kinds
+                    .iter()
+                    .zip(&written)
+                    .all(|(k, t)| t.as_ref().is_some_and(|t| k.accepts(t)))
+        };
+        let names = SIGNATURES.iter().map(|(m, _)| *m);
+        return Err(match suggest_by(&lower, names, |m| !takes_written(m)) {
+            Some(s) => diag.with_hint(format!("did you mean `{}`?", s)),
+            None => diag,
+        });
+    };
+
+    let kinds = Kind::of_signature(signature);
+    let usage = usage_hint(mnemonic, signature);
+    let missing = |found: usize| {
+        Diagnostic::new(
+            DiagnosticKind::InvalidOperand,
+            mnemonic_span.clone(),
+            format!(
+                "`{}` takes {} operands, found {}",
+                mnemonic,
+                kinds.len(),
+                found
+            ),
+        )
+        .with_label("missing operands")
+        .with_hint(usage.clone())
+    };
+
+    let mut ops = Vec::new();
+    let mut operands = Vec::new();
+    for (i, kind) in kinds.iter().enumerate() {
+        // Operands are separated by commas
+        let mut rest = input.trim_start();
+        if i > 0 {
+            rest = match rest.strip_prefix(',') {
+                Some(r) => r.trim_start(),
+                None => return Err(missing(i)),
+            };
         }
-        // Binary ops
-        op @ ("ADD" | "SUB" | "AND" | "OR" | "XOR") => {
-            let (input, (rd, rs1, rs2)) = (
-                parse_reg,
-                preceded((char(','), multispace0), parse_reg),
-                preceded((char(','), multispace0), parse_reg),
+
+        let Ok((after, token)) = parse_token(rest) else {
+            // Nothing but a comment (or nothing at all) left
+            if rest.is_empty() || is_comment(rest) {
+                return Err(missing(i));
+            }
+            return Err(
+                Diagnostic::new(DiagnosticKind::Syntax, span(rest, 1), "expected operand")
+                    .with_hint(usage),
+            );
+        };
+        let token_span = span(rest, rest.len() - after.len());
+        input = after;
+
+        // Check operand against what the instruction takes
+        let reg = |r: u32| match r {
+            0..=15 => Ok(Op::Reg(r as u8)),
+            _ => Err(Diagnostic::new(
+                DiagnosticKind::InvalidOperand,
+                token_span.clone(),
+                format!("no such register `r{}`", r),
             )
-                .parse(input)?;
-            Ok((
-                input,
-                vec![match op {
-                    "ADD" => Instr::Add { rd, rs1, rs2 },
-                    "SUB" => Instr::Sub { rd, rs1, rs2 },
-                    "AND" => Instr::And { rd, rs1, rs2 },
-                    "OR" => Instr::Or { rd, rs1, rs2 },
-                    "XOR" => Instr::Xor { rd, rs1, rs2 },
-                    _ => unreachable!(),
-                }],
-            ))
+            .with_label("registers are r0 to r15")),
+        };
+        let imm = |e: &Expr, bits: u32| {
+            operand_expr(e, bits, &token_span).map(|e| match e {
+                Expr::Symbol(s) => Op::Label(s),
+                e if !e.symbols().is_empty() => Op::Expr(e),
+                // Constants are folded right away
+                e => {
+                    let v = e.eval(&|_| None).expect("constants should evaluate");
+                    match bits {
+                        8 => Op::Imm8(v as u8),
+                        _ => Op::Imm12(v & 0xfff),
+                    }
+                }
+            })
+        };
+        match (kind, &token) {
+            (Kind::Reg, Token::Reg(r)) => ops.push(reg(*r)?),
+            (Kind::Imm8, Token::Expr(e)) => ops.push(imm(e, 8)?),
+            (Kind::Target, Token::Expr(e)) => ops.push(imm(e, 12)?),
+            (Kind::MemRef, Token::Mem(offset, base)) => {
+                // Ordered as base, offset
+                ops.push(reg(*base)?);
+                ops.push(imm(offset, 8)?);
+                operands.push(token_span.clone());
+            }
+            _ => {
+                return Err(Diagnostic::new(
+                    DiagnosticKind::InvalidOperand,
+                    token_span,
+                    format!("expected {}, found {}", kind.describe(), token.describe()),
+                )
+                .with_label(format!("not a {}", kind.describe()))
+                .with_hint(usage));
+            }
         }
-        // Immediate ops
-        op @ ("ADDI" | "ANDI" | "ORI" | "XORI") => {
-            let (input, (rd, rs1, imm)) = (
-                parse_reg,
-                preceded((char(','), multispace0), parse_reg),
-                preceded((char(','), multispace0), parse_imm8),
+        operands.push(token_span);
+    }
+
+    let stmt_span = span(start, start.len() - input.len());
+    if let Some(statements) = pseudo::expand(mnemonic, &ops, &operands, &stmt_span) {
+        return Ok((statements, input));
+    }
+
+    let instr = Instr::from_parts(mnemonic, ops).expect("operands should match signature");
+    let stmt = Statement {
+        instr,
+        span: stmt_span,
+        operands,
+        macro_span: None,
+    };
+    Ok((vec![stmt], input))
+}
+
+/// Parse a directive and its operands, returning what is left of the line.
+fn parse_directive<'a>(line: &Line, input: &'a str) -> Result<(Statement, &'a str), Diagnostic> {
+    let span = |rest: &str, len: usize| line.span(rest, len);
+
+    let start = input;
+    let (mut input, name) = parse_ident(input).expect("directives should start with a dot");
+    let name_span = span(start, name.len());
+
+    let lower = name.to_lowercase();
+    let Some((name, signature)) = DIRECTIVES.iter().find(|(d, _)| *d == lower) else {
+        let diag = Diagnostic::new(
+            DiagnosticKind::UnknownMnemonic,
+            name_span,
+            format!("unknown directive `{}`", name),
+        )
+        .with_label("not a directive");
+        return Err(match suggest(&lower, DIRECTIVES.iter().map(|(d, _)| *d)) {
+            Some(s) => diag.with_hint(format!("did you mean `{}`?", s)),
+            None => diag,
+        });
+    };
+    let usage = usage_hint(name, signature);
+
+    // Comma-separated operands, up to a comment or the end of the line
+    let mut args = Vec::new();
+    let rest = input.trim_start();
+    if !rest.is_empty() && !is_comment(rest) {
+        let mut rest = rest;
+        loop {
+            let Ok((after, token)) = parse_token(rest) else {
+                return Err(Diagnostic::new(
+                    DiagnosticKind::Syntax,
+                    span(rest, 1),
+                    "expected operand",
+                )
+                .with_hint(usage));
+            };
+            args.push((token, span(rest, rest.len() - after.len())));
+            input = after;
+
+            match after.trim_start().strip_prefix(',') {
+                Some(r) => rest = r.trim_start(),
+                None => break,
+            }
+        }
+    }
+
+    // Check operands against what the directive takes
+    let count = |n: usize| {
+        if args.len() == n {
+            Ok(())
+        } else {
+            Err(Diagnostic::new(
+                DiagnosticKind::InvalidOperand,
+                name_span.clone(),
+                format!("`{}` takes {} operands, found {}", name, n, args.len()),
             )
-                .parse(input)?;
-            Ok((
-                input,
-                vec![match op {
-                    "ADDI" => Instr::Addi { rd, rs1, imm },
-                    "ANDI" => Instr::Andi { rd, rs1, imm },
-                    "ORI" => Instr::Ori { rd, rs1, imm },
-                    "XORI" => Instr::Xori { rd, rs1, imm },
-                    _ => unreachable!(),
-                }],
-            ))
+            .with_hint(usage.clone()))
         }
-        // Jump ops
-        op @ ("JMP" | "BZ" | "BNZ") => {
-            let (input, imm) = alt((parse_label_ref, parse_imm12)).parse(input)?;
-            Ok((
-                input,
-                vec![match op {
-                    "JMP" => Instr::Jmp { imm },
-                    "BZ" => Instr::Bz { imm },
-                    "BNZ" => Instr::Bnz { imm },
-                    _ => unreachable!(),
-                }],
-            ))
+    };
+    let num = |(token, span): &(Token, Span), bits: u32| match token {
+        Token::Expr(e) => operand_expr(e, bits, span),
+        _ => Err(Diagnostic::new(
+            DiagnosticKind::InvalidOperand,
+            span.clone(),
+            format!("expected number, found {}", token.describe()),
+        )
+        .with_label("not a number")
+        .with_hint(usage.clone())),
+    };
+
+    let directive = match *name {
+        ".text" => count(0).map(|_| Directive::Text)?,
+        ".data" => count(0).map(|_| Directive::Data)?,
+        ".equ" => {
+            count(2)?;
+            let Token::Expr(Expr::Symbol(symbol)) = &args[0].0 else {
+                return Err(Diagnostic::new(
+                    DiagnosticKind::InvalidOperand,
+                    args[0].1.clone(),
+                    format!("expected name, found {}", args[0].0.describe()),
+                )
+                .with_label("not a name")
+                .with_hint(usage));
+            };
+            let symbol = symbol.clone();
+            // Only the value is an operand
+            args.remove(0);
+            Directive::Equ(symbol, num(&args[0], 16)?)
+        }
+        ".org" => count(1)
+            .and_then(|_| num(&args[0], 16))
+            .map(Directive::Org)?,
+        ".align" => count(1)
+            .and_then(|_| num(&args[0], 16))
+            .map(Directive::Align)?,
+        ".zero" => count(1)
+            .and_then(|_| num(&args[0], 16))
+            .map(Directive::Zero)?,
+        ".byte" | ".word" | ".global" | ".extern" if args.is_empty() => {
+            return Err(Diagnostic::new(
+                DiagnosticKind::InvalidOperand,
+                name_span,
+                format!("`{}` takes at least 1 operand", name),
+            )
+            .with_hint(usage));
+        }
+        ".byte" => Directive::Byte(args.iter().map(|a| num(a, 8)).collect::<Result<_, _>>()?),
+        ".word" => Directive::Word(args.iter().map(|a| num(a, 16)).collect::<Result<_, _>>()?),
+        ".global" | ".extern" => {
+            let names = args
+                .iter()
+                .map(|(token, span)| match token {
+                    Token::Expr(Expr::Symbol(symbol)) => Ok(symbol.clone()),
+                    _ => Err(Diagnostic::new(
+                        DiagnosticKind::InvalidOperand,
+                        span.clone(),
+                        format!("expected name, found {}", token.describe()),
+                    )
+                    .with_label("not a name")
+                    .with_hint(usage.clone())),
+                })
+                .collect::<Result<_, _>>()?;
+            match *name {
+                ".global" => Directive::Global(names),
+                _ => Directive::Extern(names),
+            }
+        }
+        ".string" => {
+            count(1)?;
+            let Token::Str(text) = &args[0].0 else {
+                return Err(Diagnostic::new(
+                    DiagnosticKind::InvalidOperand,
+                    args[0].1.clone(),
+                    format!("expected string, found {}", args[0].0.describe()),
+                )
+                .with_label("not a string")
+                .with_hint(usage));
+            };
+            Directive::String(text.clone())
+        }
+        // Included by `parse_file` when on a line of its own
+        ".include" => {
+            return Err(Diagnostic::new(
+                DiagnosticKind::Include,
+                name_span,
+                "`.include` must be on a line of its own, outside of macros",
+            ));
         }
-        _ => Err(nom::Err::Error(nom::error::Error::new(
-            opcode,
-            nom::error::ErrorKind::Tag,
-        ))),
+        _ => unreachable!("directive table and parser out of sync"),
+    };
+
+    let stmt = Statement {
+        span: span(start, start.len() - input.len()),
+        // Spans of the expressions taken, if any
+        operands: match directive {
+            Directive::String(_) => vec![],
+            _ => args.into_iter().map(|(_, span)| span).collect(),
+        },
+        macro_span: None,
+        instr: Instr::Directive(directive),
+    };
+    Ok((stmt, input))
+}
+
+/// Deepest nesting of macro expansions, to catch runaway recursion
+const MAX_EXPANSION_DEPTH: usize = 16;
+
+/// Name of the directive a line starts with, if any (i.e. ".macro")
+fn directive_name(text: &str) -> Option<String> {
+    let text = text.trim_start();
+    match text.starts_with('.') {
+        true => parse_ident(text).ok().map(|(_, name)| name.to_lowercase()),
+        false => None,
     }
 }
 
-/// Parse an entire program, given as a string with newlines.
-pub fn parse_program(src: &str) -> Result<Program, ParserError> {
-    let mut program = Vec::new();
+/// Expansion nested deeper than [`MAX_EXPANSION_DEPTH`],
+/// in the macro with the given name
+struct TooDeep(String);
+
+/// Parser state carried across lines: the macros defined so far
+#[derive(Default)]
+struct Macros {
+    defined: HashMap<String, Macro>,
+    /// Number of expansions so far, to make their labels unique
+    expansions: usize,
+}
+
+impl Macros {
+    /// Parse a macro definition, from its `.macro` line up to its `.endm`
+    /// line, consuming the lines of its body.
+    fn define<'a>(
+        &mut self,
+        line: &Line,
+        body: &mut impl Iterator<Item = Line<'a>>,
+    ) -> Result<(), Diagnostic> {
+        let input = line.text.trim_start();
+        let (rest, directive) = parse_ident(input).expect("definition should start with .macro");
+
+        // Body first, so that errors in the header do not spill into it
+        let mut lines = Vec::new();
+        let mut nested = None;
+        let mut terminated = false;
+        for body_line in body.by_ref() {
+            match directive_name(body_line.text).as_deref() {
+                Some(".endm") => {
+                    terminated = true;
+                    break;
+                }
+                Some(".macro") => {
+                    let text = body_line.text.trim_start();
+                    nested.get_or_insert(body_line.span(text, directive.len()));
+                }
+                _ => {}
+            }
+            lines.push((body_line.n, body_line.text.to_string()));
+        }
+        let header = line.span(input, directive.len());
+        if !terminated {
+            return Err(Diagnostic::new(
+                DiagnosticKind::Macro,
+                header,
+                "unterminated macro definition",
+            )
+            .with_label("missing `.endm`"));
+        }
+        if let Some(span) = nested {
+            return Err(Diagnostic::new(
+                DiagnosticKind::Macro,
+                span,
+                "macros may not be defined within macros",
+            )
+            .with_note(header, "within this definition"));
+        }
+
+        // Name, then parameters separated by commas or spaces
+        let input = rest.trim_start();
+        let Ok((rest, name)) = parse_ident(input) else {
+            return Err(Diagnostic::new(
+                DiagnosticKind::Macro,
+                line.span(input, 1),
+                "expected macro name",
+            )
+            .with_hint("usage: `.macro name param, ...`"));
+        };
+        let span = line.span(input, name.len());
+        let lower = name.to_lowercase();
+        if SIGNATURES.iter().any(|(m, _)| *m == lower) || lower.starts_with('.') {
+            return Err(Diagnostic::new(
+                DiagnosticKind::Macro,
+                span,
+                format!("macro `{}` would shadow an instruction", name),
+            )
+            .with_label("reserved name"));
+        }
+        if let Some(first) = self.defined.get(name) {
+            return Err(Diagnostic::new(
+                DiagnosticKind::Macro,
+                span,
+                format!("macro `{}` is defined multiple times", name),
+            )
+            .with_label("redefined here")
+            .with_note(first.span.clone(), "first defined here"));
+        }
+
+        let mut params: Vec<String> = Vec::new();
+        let mut rest = rest.trim_start();
+        while !rest.is_empty() && !is_comment(rest) {
+            let Ok((after, param)) = parse_ident(rest) else {
+                return Err(Diagnostic::new(
+                    DiagnosticKind::Macro,
+                    line.span(rest, 1),
+                    "expected parameter name",
+                )
+                .with_hint("usage: `.macro name param, ...`"));
+            };
+            if params.iter().any(|p| p == param) {
+                return Err(Diagnostic::new(
+                    DiagnosticKind::Macro,
+                    line.span(rest, param.len()),
+                    format!("parameter `{}` is declared multiple times", param),
+                ));
+            }
+            params.push(param.to_string());
+            rest = after.trim_start();
+            rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+        }
+
+        self.defined.insert(
+            name.to_string(),
+            Macro {
+                name: name.to_string(),
+                params,
+                body: lines,
+                span,
+            },
+        );
+        Ok(())
+    }
+
+    /// Parse a single line into statements, expanding it if it calls a macro.
+    /// Returns the statements along with the errors found on the way.
+    fn parse(
+        &mut self,
+        line: &Line,
+        depth: usize,
+    ) -> Result<(Vec<Statement>, Vec<Diagnostic>), TooDeep> {
+        // A label may precede the call
+        let mut input = line.text.trim_start();
+        let mut statements = Vec::new();
+        if let Ok((rest, label)) = parse_label(input) {
+            statements.push(Statement {
+                instr: Instr::Label(label.to_string()),
+                span: line.span(input, label.len()),
+                operands: vec![],
+                macro_span: None,
+            });
+            input = rest.trim_start();
+        }
+        let call = parse_ident(input)
+            .ok()
+            .and_then(|(rest, name)| Some((rest, self.defined.get(name)?.clone())));
+        let Some((rest, mac)) = call else {
+            return Ok(match parse_line(line) {
+                Ok(s) => (s, vec![]),
+                Err(e) => (vec![], vec![e]),
+            });
+        };
+
+        let args = split_args(rest);
+        let call = input
+            .split([';', '#'])
+            .next()
+            .unwrap_or_default()
+            .trim_end();
+        let call_span = line.span(input, call.len());
+        if args.len() != mac.params.len() {
+            let diag = Diagnostic::new(
+                DiagnosticKind::Macro,
+                call_span,
+                format!(
+                    "`{}` takes {} arguments, found {}",
+                    mac.name,
+                    mac.params.len(),
+                    args.len()
+                ),
+            )
+            .with_note(mac.span.clone(), "macro defined here")
+            .with_hint(mac.usage());
+            return Ok((statements, vec![diag]));
+        }
+        if depth >= MAX_EXPANSION_DEPTH {
+            return Err(TooDeep(mac.name));
+        }
 
-    for (n, line) in src.lines().enumerate() {
-        // Skip blank or comment lines
-        let trimmed = line.trim();
-        if trimmed.is_empty() || trimmed.starts_with(';') {
-            continue;
+        self.expansions += 1;
+        let id = self.expansions;
+        let mut expanded = Vec::new();
+        let mut errors = Vec::new();
+        for ((n, text), (_, original)) in mac.expand(&args).into_iter().zip(&mac.body) {
+            // The body may be in another file than the call
+            let body_line = Line {
+                file: &mac.span.file,
+                n,
+                text: &text,
+            };
+            let trimmed = original.trim();
+            let body_span = Line {
+                file: &mac.span.file,
+                n,
+                text: original,
+            }
+            .span(original.trim_start(), trimmed.len());
+
+            let (mut stmts, errs) = self.parse(&body_line, depth + 1)?;
+            // Expanded statements are attributed to the call
+            for stmt in &mut stmts {
+                stmt.span = call_span.clone();
+                stmt.operands.fill(call_span.clone());
+                stmt.macro_span.get_or_insert(body_span.clone());
+            }
+            expanded.append(&mut stmts);
+            errors.extend(errs.into_iter().map(|e| {
+                let mut notes = vec![(body_span.clone(), format!("expanded to `{}`", text.trim()))];
+                notes.extend(e.notes);
+                Diagnostic {
+                    span: call_span.clone(),
+                    label: format!("in this expansion of `{}`", mac.name),
+                    notes,
+                    ..e
+                }
+            }));
         }
+        rename_locals(&mut expanded, id);
+        statements.append(&mut expanded);
+        Ok((statements, errors))
+    }
+}
+
+/// Parser state carried across files: the macros defined so far,
+/// and the files being included
+struct Context<'a> {
+    macros: Macros,
+    includes: &'a Includes,
+    /// Files being parsed, outermost first
+    stack: Vec<String>,
+    /// Sources of the files included so far
+    sources: Vec<(String, String)>,
+}
+
+impl Context<'_> {
+    /// Parse an entire file into statements, expanding the macros it defines
+    /// and the files it includes.
+    /// Lines with errors are skipped, with a diagnostic each.
+    fn parse_file(&mut self, file: &str, src: &str) -> (Vec<Statement>, Vec<Diagnostic>) {
+        let mut statements = Vec::new();
+        let mut errors = Vec::new();
+
+        let mut lines = src.lines().enumerate().map(|(n, text)| Line {
+            file,
+            n: n + 1,
+            text,
+        });
+        while let Some(line) = lines.next() {
+            match directive_name(line.text).as_deref() {
+                Some(".macro") => {
+                    if let Err(e) = self.macros.define(&line, &mut lines) {
+                        errors.push(e);
+                    }
+                    continue;
+                }
+                Some(".endm") => {
+                    let text = line.text.trim_start();
+                    errors.push(Diagnostic::new(
+                        DiagnosticKind::Macro,
+                        line.span(text, ".endm".len()),
+                        "`.endm` without `.macro`",
+                    ));
+                    continue;
+                }
+                Some(".include") => {
+                    match self.include(&line) {
+                        Ok((mut s, mut e)) => {
+                            statements.append(&mut s);
+                            errors.append(&mut e);
+                        }
+                        Err(e) => errors.push(e),
+                    }
+                    continue;
+                }
+                _ => {}
+            }
 
-        match parse_line(line) {
-            Ok((_, mut instr)) => program.append(&mut instr),
+            match self.macros.parse(&line, 0) {
+                Ok((mut s, mut e)) => {
+                    statements.append(&mut s);
+                    errors.append(&mut e);
+                }
+                Err(TooDeep(name)) => {
+                    let text = line.text.trim();
+                    let diag = Diagnostic::new(
+                        DiagnosticKind::Macro,
+                        line.span(line.text.trim_start(), text.len()),
+                        format!(
+                            "macro expansion nested more than {} levels deep",
+                            MAX_EXPANSION_DEPTH
+                        ),
+                    )
+                    .with_label("while expanding this")
+                    .with_hint(format!("check `{}` for recursive calls", name));
+                    errors.push(match self.macros.defined.get(&name) {
+                        Some(m) => diag.with_note(m.span.clone(), "macro defined here"),
+                        None => diag,
+                    });
+                }
+            }
+        }
+
+        (statements, errors)
+    }
+
+    /// Parse the file included by a given `.include` line.
+    /// Returns its statements along with the errors found in it,
+    /// each noting where the file was included from.
+    fn include(&mut self, line: &Line) -> Result<(Vec<Statement>, Vec<Diagnostic>), Diagnostic> {
+        let input = line.text.trim_start();
+        let (rest, directive) = parse_ident(input).expect("line should start with .include");
+        let usage = usage_hint(".include", "\"file\"");
+
+        let rest = rest.trim_start();
+        let (after, name) = match parse_token(rest) {
+            Ok((after, Token::Str(name))) => (after, name),
+            Ok((after, token)) => {
+                return Err(Diagnostic::new(
+                    DiagnosticKind::InvalidOperand,
+                    line.span(rest, rest.len() - after.len()),
+                    format!("expected file name, found {}", token.describe()),
+                )
+                .with_label("not a string")
+                .with_hint(usage));
+            }
+            Err(_) => {
+                return Err(Diagnostic::new(
+                    DiagnosticKind::InvalidOperand,
+                    line.span(input, directive.len()),
+                    "`.include` takes 1 operands, found 0",
+                )
+                .with_hint(usage));
+            }
+        };
+        let name_span = line.span(rest, rest.len() - after.len());
+        let after = after.trim_start();
+        if !after.is_empty() && !is_comment(after) {
+            let garbage = after
+                .split([';', '#'])
+                .next()
+                .unwrap_or_default()
+                .trim_end();
+            return Err(Diagnostic::new(
+                DiagnosticKind::Syntax,
+                line.span(after, garbage.len()),
+                format!("unexpected `{}`", garbage),
+            )
+            .with_label("expected comment or end of line")
+            .with_hint(usage));
+        }
+
+        let (path, src) = match self.includes.resolve(&name, line.file) {
+            Ok(Some(found)) => found,
+            Ok(None) => {
+                let searched: Vec<String> = self
+                    .includes
+                    .candidates(&name, line.file)
+                    .iter()
+                    .map(|p| format!("`{}`", p.display()))
+                    .collect();
+                return Err(Diagnostic::new(
+                    DiagnosticKind::Include,
+                    name_span,
+                    format!("cannot find `{}`", name),
+                )
+                .with_label("no such file")
+                .with_hint(format!("searched {}", searched.join(", "))));
+            }
             Err(e) => {
-                return Err(ParserError::Error(n + 1, e.to_string()));
+                return Err(Diagnostic::new(
+                    DiagnosticKind::Include,
+                    name_span,
+                    format!("cannot read `{}`: {}", name, e),
+                ));
             }
+        };
+        if let Some(i) = self.stack.iter().position(|f| *f == path) {
+            let chain: Vec<&str> = self.stack[i..]
+                .iter()
+                .map(String::as_str)
+                .chain([path.as_str()])
+                .collect();
+            return Err(Diagnostic::new(
+                DiagnosticKind::Include,
+                name_span,
+                format!("`{}` includes itself", path),
+            )
+            .with_label("include cycle")
+            .with_hint(format!("included as {}", chain.join(" -> "))));
         }
+
+        self.stack.push(path.clone());
+        let (statements, errors) = self.parse_file(&path, &src);
+        self.stack.pop();
+        if !self.sources.iter().any(|(f, _)| *f == path) {
+            self.sources.push((path, src));
+        }
+
+        let errors = errors
+            .into_iter()
+            .map(|e| e.with_note(name_span.clone(), "included from here"))
+            .collect();
+        Ok((statements, errors))
     }
+}
+
+/// Statements parsed from a program spread across files
+#[derive(Debug, Default)]
+pub struct Parsed {
+    pub statements: Vec<Statement>,
+    pub errors: Vec<Diagnostic>,
+    /// Sources of the files included by the program, by name
+    pub included: Vec<(String, String)>,
+}
+
+/// Parse a program given as several sources, one after the other,
+/// along with the files they include. Macros defined in a source
+/// can be used in the sources after it.
+pub fn parse_sources(sources: &[(&str, &str)], includes: &Includes) -> Parsed {
+    let mut ctx = Context {
+        macros: Macros::default(),
+        includes,
+        stack: Vec::new(),
+        sources: Vec::new(),
+    };
+
+    let mut parsed = Parsed::default();
+    for (file, src) in sources {
+        ctx.stack
+            .push(normalize(Path::new(file)).to_string_lossy().into_owned());
+        let (mut statements, mut errors) = ctx.parse_file(file, src);
+        ctx.stack.pop();
+        parsed.statements.append(&mut statements);
+        parsed.errors.append(&mut errors);
+    }
+    parsed.included = ctx.sources;
+    parsed
+}
+
+/// Parse an entire program into statements, attributed to a given file,
+/// expanding the macros it defines and the files it includes.
+/// Lines with errors are skipped, with a diagnostic each.
+pub fn parse_statements(file: &str, src: &str) -> (Vec<Statement>, Vec<Diagnostic>) {
+    let parsed = parse_sources(&[(file, src)], &Includes::default());
+    (parsed.statements, parsed.errors)
+}
 
-    Ok(program)
+/// Parse an entire program, given as a string with newlines.
+pub fn parse_program(src: &str) -> Result<Program, Vec<Diagnostic>> {
+    let (statements, errors) = parse_statements("<input>", src);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(statements.into_iter().map(|s| s.instr).collect())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::compiler::expr::{BinOp, UnOp};
+    use DiagnosticKind::*;
+
+    /// Parses a single line into instructions
+    fn parse(line: &str) -> Result<Vec<Instr>, Diagnostic> {
+        let line = Line {
+            file: "a.asm",
+            n: 1,
+            text: line,
+        };
+        parse_line(&line).map(|s| s.into_iter().map(|s| s.instr).collect())
+    }
 
     #[test]
     fn test_parser() {
         // Simple Halt
         let input = "halt";
-        assert_eq!(parse_line(input).ok().unwrap(), ("", vec![Instr::Halt]));
+        assert_eq!(parse(input).unwrap(), vec![Instr::Halt]);
 
         // Basic addi r0, r0, 0
         let input = "addi r0, r0, 0";
         assert_eq!(
-            parse_line(input).ok().unwrap(),
-            (
-                "",
-                vec![Instr::Addi {
-                    rd: Op::Reg(0),
-                    rs1: Op::Reg(0),
-                    imm: Op::Imm8(0),
-                }]
-            )
+            parse(input).unwrap(),
+            vec![Instr::Addi {
+                rd: Op::Reg(0),
+                rs1: Op::Reg(0),
+                imm: Op::Imm8(0),
+            }]
         );
 
         // Malformed mv
         let input = "mv r0, 0";
-        assert!(parse_line(input).err().is_some());
+        assert!(parse(input).is_err());
+
+        // Memory ops, with and without offset
+        let input = "lb r1, 0x10(r2)";
+        assert_eq!(
+            parse(input).unwrap(),
+            vec![Instr::Lb {
+                rd: Op::Reg(1),
+                rs1: Op::Reg(2),
+                imm: Op::Imm8(0x10),
+            }]
+        );
+        let input = "sb r3, ( r4 )";
+        assert_eq!(
+            parse(input).unwrap(),
+            vec![Instr::Sb {
+                rs2: Op::Reg(3),
+                rs1: Op::Reg(4),
+                imm: Op::Imm8(0),
+            }]
+        );
 
         // Label
         let input = "loop:";
         assert_eq!(
-            parse_line(input).ok().unwrap(),
-            ("", vec![Instr::Label("loop".to_string())])
+            parse(input).unwrap(),
+            vec![Instr::Label("loop".to_string())]
         );
 
+        // Spans point at each instruction and operand
+        let (statements, _) = parse_statements("a.asm", "; comment\nloop:\n\tjmp loop");
+        assert_eq!(statements[0].span, Span::new("a.asm", 2, 1, 4));
+        assert_eq!(statements[1].span, Span::new("a.asm", 3, 2, 8));
+        assert_eq!(statements[1].operands, vec![Span::new("a.asm", 3, 6, 4)]);
+
         // Jump to label
         let input = "jmp loop";
         assert_eq!(
-            parse_line(input).ok().unwrap(),
-            (
-                "",
-                vec![Instr::Jmp {
-                    imm: Op::Label("loop".to_string())
-                }]
-            )
+            parse(input).unwrap(),
+            vec![Instr::Jmp {
+                imm: Op::Label("loop".to_string())
+            }]
         )
     }
+
+    #[test]
+    fn test_line_grammar() {
+        let halt = || Instr::Halt;
+        let label = || Instr::Label("end".to_string());
+
+        let cases = [
+            ("", vec![]),
+            ("   ", vec![]),
+            ("; comment", vec![]),
+            ("  # comment", vec![]),
+            ("end:", vec![label()]),
+            ("end: ; comment", vec![label()]),
+            ("halt", vec![halt()]),
+            ("halt;comment", vec![halt()]),
+            ("halt # comment", vec![halt()]),
+            ("end: halt", vec![label(), halt()]),
+            ("  end:halt ; comment", vec![label(), halt()]),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(parse(src).unwrap(), expected, "{}", src);
+        }
+
+        // Identifiers with digits, underscores and dots, and numeric labels
+        let jmp = |l: &str| Instr::Jmp {
+            imm: Op::Label(l.to_string()),
+        };
+        let cases = [
+            ("loop_1: jmp loop_1", "loop_1", "loop_1"),
+            ("fib2: jmp .L0", "fib2", ".L0"),
+            (".L0: jmp r1_x", ".L0", "r1_x"),
+            ("1: jmp 1b", "1", "1b"),
+            ("_x.y: jmp 12f", "_x.y", "12f"),
+        ];
+        for (src, l, target) in cases {
+            assert_eq!(
+                parse(src).unwrap(),
+                vec![Instr::Label(l.to_string()), jmp(target)],
+                "{}",
+                src
+            );
+        }
+        assert!(parse("jmp 1x").is_err());
+
+        // Comments end operand lists, too
+        assert_eq!(
+            parse("jmp end# comment").unwrap(),
+            vec![Instr::Jmp {
+                imm: Op::Label("end".to_string())
+            }]
+        );
+        assert_eq!(parse_program("end: halt\n").unwrap(), vec![label(), halt()]);
+    }
+
+    #[test]
+    fn test_directives() {
+        let directive = |src: &str| match parse(src).unwrap().as_slice() {
+            [Instr::Directive(d)] => d.clone(),
+            other => panic!("{}: {:?}", src, other),
+        };
+        assert_eq!(directive(".data"), Directive::Data);
+        assert_eq!(
+            directive(".equ LEN, 0x10"),
+            Directive::Equ("LEN".to_string(), Expr::Num(0x10))
+        );
+        assert_eq!(
+            directive(".org 0x20 ; here"),
+            Directive::Org(Expr::Num(0x20))
+        );
+        assert_eq!(
+            directive(".byte 1, 2,3"),
+            Directive::Byte(vec![Expr::Num(1), Expr::Num(2), Expr::Num(3)])
+        );
+        assert_eq!(
+            directive(".word 0x1234"),
+            Directive::Word(vec![Expr::Num(0x1234)])
+        );
+        assert_eq!(
+            directive(r#".string "a\"b\n""#),
+            Directive::String("a\"b\n".to_string())
+        );
+        assert_eq!(
+            parse("msg: .zero 4").unwrap(),
+            vec![
+                Instr::Label("msg".to_string()),
+                Instr::Directive(Directive::Zero(Expr::Num(4)))
+            ]
+        );
+
+        assert_eq!(
+            directive(".global main, putc"),
+            Directive::Global(vec!["main".to_string(), "putc".to_string()])
+        );
+        assert_eq!(
+            directive(".extern putc"),
+            Directive::Extern(vec!["putc".to_string()])
+        );
+
+        // Constants may be used as immediates
+        assert_eq!(
+            parse("addi r1, r0, LEN").unwrap(),
+            vec![Instr::Addi {
+                rd: Op::Reg(1),
+                rs1: Op::Reg(0),
+                imm: Op::Label("LEN".to_string()),
+            }]
+        );
+
+        let cases = [
+            (
+                ".bytes 1",
+                UnknownMnemonic,
+                1,
+                Some("did you mean `.byte`?"),
+            ),
+            (".text 1", InvalidOperand, 1, Some("usage: `.text`")),
+            (
+                ".byte",
+                InvalidOperand,
+                1,
+                Some("usage: `.byte value, ...`"),
+            ),
+            (".byte 1, 256", ImmediateTooLarge, 10, None),
+            (
+                ".equ 1, 2",
+                InvalidOperand,
+                6,
+                Some("usage: `.equ name, value`"),
+            ),
+            (
+                ".string 42",
+                InvalidOperand,
+                9,
+                Some("usage: `.string \"text\"`"),
+            ),
+            (
+                ".extern putc, 1",
+                InvalidOperand,
+                15,
+                Some("usage: `.extern name, ...`"),
+            ),
+            (
+                ".org \"a\"",
+                InvalidOperand,
+                6,
+                Some("usage: `.org address`"),
+            ),
+        ];
+        for (src, kind, column, hint) in cases {
+            let diag = parse(src).unwrap_err();
+            assert_eq!(diag.kind, kind, "{}", src);
+            assert_eq!(diag.span.column, column, "{}", src);
+            assert_eq!(diag.hint.as_deref(), hint, "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_expressions() {
+        let imm = |src: &str| match parse(&format!("addi r1, r0, {}", src)) {
+            Ok(instrs) => instrs[0].operands()[2].clone(),
+            Err(e) => panic!("{}: {}", src, e.message),
+        };
+        let sym = |s: &str| Box::new(Expr::Symbol(s.to_string()));
+
+        // Constants are folded
+        assert_eq!(imm("-1"), Op::Imm8(0xff));
+        assert_eq!(imm("0b1010"), Op::Imm8(10));
+        assert_eq!(imm("'A'"), Op::Imm8(b'A'));
+        assert_eq!(imm("'\\n'"), Op::Imm8(b'\n'));
+        assert_eq!(imm("1 << 3 | 1"), Op::Imm8(9));
+        assert_eq!(imm("2 + 3 * 4"), Op::Imm8(14));
+        assert_eq!(imm("(2 + 3) * 4"), Op::Imm8(20));
+        assert_eq!(imm("10 - 4 - 3"), Op::Imm8(3));
+        assert_eq!(imm("~0 & 0xf0"), Op::Imm8(0xf0));
+        assert_eq!(imm("lo(0x1234)"), Op::Imm8(0x34));
+        assert_eq!(imm("hi(0x1234)"), Op::Imm8(0x12));
+
+        // Symbolic ones are kept, local references included
+        assert_eq!(
+            imm("BUF_LEN - 1"),
+            Op::Expr(Expr::Binary(
+                BinOp::Sub,
+                sym("BUF_LEN"),
+                Box::new(Expr::Num(1))
+            ))
+        );
+        assert_eq!(
+            imm("lo(table)"),
+            Op::Expr(Expr::Unary(UnOp::Lo, sym("table")))
+        );
+        assert_eq!(imm("0b"), Op::Label("0b".to_string()));
+        assert_eq!(
+            parse("lb r1, buf + 1(r2)").unwrap(),
+            vec![Instr::Lb {
+                rd: Op::Reg(1),
+                rs1: Op::Reg(2),
+                imm: Op::Expr(Expr::Binary(BinOp::Add, sym("buf"), Box::new(Expr::Num(1)))),
+            }]
+        );
+        assert_eq!(
+            parse("sb r1, -1(r2)").unwrap(),
+            vec![Instr::Sb {
+                rs2: Op::Reg(1),
+                rs1: Op::Reg(2),
+                imm: Op::Imm8(0xff),
+            }]
+        );
+        assert_eq!(
+            parse("jmp (end)").unwrap(),
+            vec![Instr::Jmp {
+                imm: Op::Label("end".to_string())
+            }]
+        );
+
+        let cases = [
+            ("addi r1, r0, -129", ImmediateTooLarge, 14),
+            ("addi r1, r0, 1 << 8", ImmediateTooLarge, 14),
+            ("addi r1, r0, 1 / 0", InvalidOperand, 14),
+            ("addi r1, r0, r2 + 1", InvalidOperand, 14),
+            ("addi r1, r0, 1 +", Syntax, 16),
+            ("jmp -0x801", ImmediateTooLarge, 5),
+            ("lb r1, 'ab'(r2)", Syntax, 8),
+        ];
+        for (src, kind, column) in cases {
+            let diag = parse(src).unwrap_err();
+            assert_eq!(diag.kind, kind, "{}", src);
+            assert_eq!(diag.span.column, column, "{}", src);
+        }
+        assert_eq!(
+            parse("addi r1, r0, -129").unwrap_err().label,
+            "must be between -128 and 255"
+        );
+    }
+
+    #[test]
+    fn test_split_line() {
+        assert_eq!(
+            split_line("loop:  ADDI r1,r1 , 1 ; step").unwrap(),
+            LineParts {
+                label: Some("loop"),
+                name: Some("ADDI"),
+                operands: vec!["r1", "r1", "1"],
+                comment: Some("; step"),
+            }
+        );
+        assert_eq!(
+            split_line("  .string \"a;b\"").unwrap().operands,
+            ["\"a;b\""]
+        );
+        assert_eq!(split_line("   ").unwrap(), LineParts::default());
+        assert_eq!(split_line("end: # done").unwrap().comment, Some("# done"));
+        assert_eq!(split_line("end: 42"), None);
+        assert_eq!(canonical_name("ADDI"), Some("addi"));
+        assert_eq!(canonical_name(".Macro"), Some(".macro"));
+        assert_eq!(canonical_name("twice"), None);
+    }
+
+    #[test]
+    fn test_pseudo() {
+        let text = |src: &str| -> Vec<String> {
+            parse(src)
+                .unwrap_or_else(|e| panic!("{}: {}", src, e.message))
+                .iter()
+                .map(|i| i.to_string())
+                .collect()
+        };
+        assert_eq!(text("li r1, 'A'"), ["addi r1, r0, 65"]);
+        assert_eq!(text("inc r2"), ["addi r2, r2, 1"]);
+        assert_eq!(text("DEC r2"), ["addi r2, r2, 255"]);
+        assert_eq!(text("neg r1, r2"), ["sub r1, r0, r2"]);
+        assert_eq!(text("clr r3"), ["xor r3, r3, r3"]);
+        assert_eq!(text("beq r1, r2, end"), ["cmp r1, r2", "bz end"]);
+        assert_eq!(text("bne r1, r2, 0x10"), ["cmp r1, r2", "bnz 16"]);
+
+        // Expansions share the span of their line
+        let (statements, _) = parse_statements("a.asm", "loop: bne r1, r2, loop ; spin\n");
+        assert_eq!(statements.len(), 3);
+        assert_eq!(statements[1].span, Span::new("a.asm", 1, 7, 16));
+        assert_eq!(statements[2].span, statements[1].span);
+        assert_eq!(statements[2].operands, [Span::new("a.asm", 1, 19, 4)]);
+
+        // Errors are reported against the pseudo-instruction as written
+        let cases = [
+            ("li r1, 256", ImmediateTooLarge, 8, None),
+            ("li r1", InvalidOperand, 1, Some("usage: `li rd, imm8`")),
+            (
+                "beq r1, 4, end",
+                InvalidOperand,
+                9,
+                Some("usage: `beq rs1, rs2, target`"),
+            ),
+            ("inc r1 r2", Syntax, 8, Some("usage: `inc rd`")),
+        ];
+        for (src, kind, column, hint) in cases {
+            let diag = parse(src).unwrap_err();
+            assert_eq!((diag.kind, diag.span.column), (kind, column), "{}", src);
+            assert_eq!(diag.hint.as_deref(), hint, "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_include() {
+        let files = HashMap::from([
+            (
+                "lib/std.asm",
+                ".include \"util.asm\"\n.macro zero reg\n  xor \\reg, \\reg, \\reg\n.endm\n",
+            ),
+            ("lib/util.asm", "util: ret\n"),
+            ("lib/bad.asm", "halt\n  adi r1, r0, 1\n"),
+            ("a.asm", ".include \"b.asm\"\n"),
+            ("b.asm", "nop\n.include \"./a.asm\"\n"),
+        ]);
+        let includes = Includes::new(vec!["lib".into()]).with_reader(move |path| {
+            let path = path.to_str().unwrap_or_default();
+            files
+                .get(path)
+                .map(|src| src.to_string())
+                .ok_or(std::io::ErrorKind::NotFound.into())
+        });
+
+        // Included files are parsed in place, and macros carry over
+        let src = ".include \"std.asm\" ; routines\nstart: zero r1\n";
+        let parsed = parse_sources(&[("main.asm", src)], &includes);
+        assert!(parsed.errors.is_empty(), "{:?}", parsed.errors);
+        let text: Vec<String> = parsed
+            .statements
+            .iter()
+            .map(|s| s.instr.to_string())
+            .collect();
+        assert_eq!(text, ["util:", "ret", "start:", "xor r1, r1, r1"]);
+        assert_eq!(parsed.statements[1].span.file, "lib/util.asm");
+        assert_eq!(parsed.statements[3].span.file, "main.asm");
+        assert_eq!(
+            parsed.statements[3].macro_span,
+            Some(Span::new("lib/std.asm", 3, 3, 20))
+        );
+        let included: Vec<&str> = parsed.included.iter().map(|(f, _)| f.as_str()).collect();
+        assert_eq!(included, ["lib/util.asm", "lib/std.asm"]);
+
+        // Later sources see the macros of earlier ones
+        let parsed = parse_sources(&[("main.asm", src), ("more.asm", "zero r2\n")], &includes);
+        assert!(parsed.errors.is_empty(), "{:?}", parsed.errors);
+        assert_eq!(parsed.statements.len(), 5);
+
+        let error = |src: &str| {
+            let parsed = parse_sources(&[("main.asm", src)], &includes);
+            assert_eq!(parsed.errors.len(), 1, "{}: {:?}", src, parsed.errors);
+            parsed.errors.into_iter().next().unwrap()
+        };
+
+        // Errors within included files point into them, and at the include
+        let diag = error(".include \"bad.asm\"\n");
+        assert_eq!(diag.kind, UnknownMnemonic);
+        assert_eq!(diag.span, Span::new("lib/bad.asm", 2, 3, 3));
+        assert_eq!(
+            diag.notes,
+            [(
+                Span::new("main.asm", 1, 10, 9),
+                "included from here".to_string()
+            )]
+        );
+
+        let diag = error(".include \"none.asm\"\n");
+        assert_eq!(diag.kind, Include);
+        assert_eq!(diag.message, "cannot find `none.asm`");
+        assert_eq!(
+            diag.hint.as_deref(),
+            Some("searched `none.asm`, `lib/none.asm`")
+        );
+
+        let diag = error(".include \"a.asm\"\n");
+        assert_eq!(diag.message, "`a.asm` includes itself");
+        assert_eq!(diag.span, Span::new("b.asm", 2, 10, 9));
+        assert_eq!(
+            diag.hint.as_deref(),
+            Some("included as a.asm -> b.asm -> a.asm")
+        );
+        assert_eq!(diag.notes.len(), 2);
+        let parsed = parse_sources(&[("a.asm", ".include \"b.asm\"\n")], &includes);
+        assert_eq!(parsed.errors[0].message, "`a.asm` includes itself");
+
+        let cases = [
+            (".include", InvalidOperand, 1),
+            (".include std.asm", InvalidOperand, 10),
+            (".include \"std.asm\" x", Syntax, 20),
+            ("lib: .include \"std.asm\"", Include, 6),
+            (".macro m\n.include \"std.asm\"\n.endm\nm", Include, 1),
+        ];
+        for (src, kind, column) in cases {
+            let diag = error(src);
+            assert_eq!((diag.kind, diag.span.column), (kind, column), "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_macros() {
+        let src = "\
+.macro zero reg
+  xor \\reg, \\reg, \\reg
+.endm
+.macro dbnz reg, target ; decrement and branch
+  addi \\reg, \\reg, -1
+  bnz \\target
+.endm
+.macro wait n
+  addi r9, r0, \\n
+loop: dbnz r9, loop
+.endm
+start: zero r1
+  wait 3
+  wait 4 ; again
+";
+        let (statements, errors) = parse_statements("a.asm", src);
+        assert!(errors.is_empty(), "{:?}", errors);
+        let text: Vec<String> = statements.iter().map(|s| s.instr.to_string()).collect();
+        assert_eq!(
+            text,
+            [
+                "start:",
+                "xor r1, r1, r1",
+                "addi r9, r0, 3",
+                "loop@2:",
+                "addi r9, r9, 255",
+                "bnz loop@2",
+                "addi r9, r0, 4",
+                "loop@4:",
+                "addi r9, r9, 255",
+                "bnz loop@4",
+            ]
+        );
+
+        // Expanded statements point at their call, and the macro line they came from
+        assert_eq!(statements[1].span, Span::new("a.asm", 12, 8, 7));
+        assert_eq!(statements[1].operands[0], Span::new("a.asm", 12, 8, 7));
+        assert_eq!(statements[1].macro_span, Some(Span::new("a.asm", 2, 3, 20)));
+        assert_eq!(statements[9].span, Span::new("a.asm", 14, 3, 6));
+        assert_eq!(statements[9].macro_span, Some(Span::new("a.asm", 6, 3, 11)));
+
+        let error = |src: &str| {
+            let (_, errors) = parse_statements("a.asm", src);
+            assert_eq!(errors.len(), 1, "{}: {:?}", src, errors);
+            errors.into_iter().next().unwrap()
+        };
+        let defs = ".macro zero reg\n  xor \\reg, \\reg, \\reg\n.endm\n";
+
+        // Errors within expansions point at both the call and the definition
+        let diag = error(&format!("{}  zero r16\n", defs));
+        assert_eq!(diag.kind, InvalidOperand);
+        assert_eq!(diag.span, Span::new("a.asm", 4, 3, 8));
+        assert_eq!(diag.label, "in this expansion of `zero`");
+        assert_eq!(
+            diag.notes,
+            [(
+                Span::new("a.asm", 2, 3, 20),
+                "expanded to `xor r16, r16, r16`".to_string()
+            )]
+        );
+
+        let diag = error(&format!("{}  zero r1, r2\n", defs));
+        assert_eq!(diag.message, "`zero` takes 1 arguments, found 2");
+        assert_eq!(diag.notes[0].0, Span::new("a.asm", 1, 8, 4));
+        assert_eq!(diag.hint.as_deref(), Some("usage: `zero reg`"));
+
+        let diag = error(".macro rec\n  rec\n.endm\nrec\n");
+        assert_eq!(diag.span, Span::new("a.asm", 4, 1, 3));
+        assert_eq!(
+            diag.hint.as_deref(),
+            Some("check `rec` for recursive calls")
+        );
+
+        let cases = [
+            (".macro zero reg\n", "unterminated macro definition", 1),
+            (
+                ".macro a\n.macro b\n.endm\n",
+                "macros may not be defined within macros",
+                2,
+            ),
+            ("halt\n  .endm\n", "`.endm` without `.macro`", 2),
+            (
+                ".macro add x\n.endm\n",
+                "macro `add` would shadow an instruction",
+                1,
+            ),
+            (
+                ".macro m\n.endm\n.macro m\n.endm\n",
+                "macro `m` is defined multiple times",
+                3,
+            ),
+            (
+                ".macro m a, a\n.endm\n",
+                "parameter `a` is declared multiple times",
+                1,
+            ),
+            (".macro m 1\n.endm\n", "expected parameter name", 1),
+        ];
+        for (src, message, line) in cases {
+            let diag = error(src);
+            assert_eq!(diag.kind, Macro, "{}", src);
+            assert_eq!(diag.message, message, "{}", src);
+            assert_eq!(diag.span.line, line, "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_parser_errors() {
+        let cases = [
+            (
+                "adi r1, r0, 1",
+                UnknownMnemonic,
+                1,
+                Some("did you mean `addi`?"),
+            ),
+            (
+                "adi r1, r0, r2",
+                UnknownMnemonic,
+                1,
+                Some("did you mean `add`?"),
+            ),
+            ("frobnicate", UnknownMnemonic, 1, None),
+            ("mv r0, 0", InvalidOperand, 8, Some("usage: `mv rd, rs1`")),
+            (
+                "add r1, r2",
+                InvalidOperand,
+                1,
+                Some("usage: `add rd, rs1, rs2`"),
+            ),
+            ("push ; rs", InvalidOperand, 1, Some("usage: `push rs`")),
+            ("not r16, r1", InvalidOperand, 5, None),
+            ("addi r1, r0, 256", ImmediateTooLarge, 14, None),
+            ("jmp 0x1000", ImmediateTooLarge, 5, None),
+            ("lb r1, 300(r2)", ImmediateTooLarge, 8, None),
+            (
+                "add r1, r2, $",
+                Syntax,
+                13,
+                Some("usage: `add rd, rs1, rs2`"),
+            ),
+            ("  , r1", Syntax, 3, None),
+        ];
+        for (src, kind, column, hint) in cases {
+            let diag = parse(src).unwrap_err();
+            assert_eq!(diag.kind, kind, "{}", src);
+            assert_eq!(diag.span.column, column, "{}", src);
+            assert_eq!(diag.hint.as_deref(), hint, "{}", src);
+        }
+
+        // Anything but a comment after the instruction
+        let cases = [
+            ("halt extra", "extra", 6, Some("usage: `halt`")),
+            (
+                "add r1, r2, r3 r4",
+                "r4",
+                16,
+                Some("usage: `add rd, rs1, rs2`"),
+            ),
+            (
+                "add r1, r2, r3, r4 ; four",
+                ", r4",
+                15,
+                Some("usage: `add rd, rs1, rs2`"),
+            ),
+            ("jmp loop)", ")", 9, Some("usage: `jmp target`")),
+        ];
+        for (src, garbage, column, hint) in cases {
+            let diag = parse(src).unwrap_err();
+            assert_eq!(diag.kind, Syntax, "{}", src);
+            assert_eq!(diag.message, format!("unexpected `{}`", garbage));
+            assert_eq!(diag.span.column, column, "{}", src);
+            assert_eq!(diag.span.len, garbage.len(), "{}", src);
+            assert_eq!(diag.hint.as_deref(), hint, "{}", src);
+        }
+
+        // Labels may only be followed by instructions
+        let diag = parse("loop: 42 # answer").unwrap_err();
+        assert_eq!(diag.message, "expected instruction");
+        assert_eq!(diag.span.column, 7);
+
+        // Errors are collected from every line
+        let errors = parse_program("adi r1, r0, 1\nhalt\njmp 0x1000\n").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].span.line, 1);
+        assert_eq!(errors[1].span.line, 3);
+    }
 }
@@ -0,0 +1,107 @@
+use crate::compiler::{ast::*, parser::Statement, span::Span};
+
+/// An operand of an instruction a pseudo-instruction expands to
+#[derive(Debug, Clone, Copy)]
+enum Arg {
+    /// Operand of the pseudo-instruction, by index
+    Operand(usize),
+    Reg(u8),
+    Imm8(u8),
+}
+
+use Arg::{Imm8, Operand, Reg};
+
+/// Instructions a pseudo-instruction expands to, with their operands
+type Expansion = &'static [(&'static str, &'static [Arg])];
+
+/// Expansions of each pseudo-instruction, whose operands are listed
+/// alongside the instructions in the parser's signature table
+const PSEUDOS: &[(&str, Expansion)] = &[
+    ("li", &[("addi", &[Operand(0), Reg(0), Operand(1)])]),
+    ("inc", &[("addi", &[Operand(0), Operand(0), Imm8(1)])]),
+    ("dec", &[("addi", &[Operand(0), Operand(0), Imm8(0xff)])]),
+    ("neg", &[("sub", &[Operand(0), Reg(0), Operand(1)])]),
+    ("clr", &[("xor", &[Operand(0), Operand(0), Operand(0)])]),
+    (
+        "beq",
+        &[("cmp", &[Operand(0), Operand(1)]), ("bz", &[Operand(2)])],
+    ),
+    (
+        "bne",
+        &[("cmp", &[Operand(0), Operand(1)]), ("bnz", &[Operand(2)])],
+    ),
+];
+
+/// Expands a pseudo-instruction into the real instructions it stands for,
+/// given its operands and their spans. Returns `None` if no such pseudo-instruction.
+///
+/// Expanded instructions share the span of the pseudo-instruction, as do
+/// operands the expansion adds on its own.
+pub fn expand(mnemonic: &str, ops: &[Op], spans: &[Span], span: &Span) -> Option<Vec<Statement>> {
+    let (_, expansion) = PSEUDOS.iter().find(|(m, _)| *m == mnemonic)?;
+
+    let statements = expansion
+        .iter()
+        .map(|(m, args)| {
+            let (ops, operands) = args
+                .iter()
+                .map(|arg| match arg {
+                    Operand(i) => (ops[*i].clone(), spans[*i].clone()),
+                    Reg(r) => (Op::Reg(*r), span.clone()),
+                    Imm8(v) => (Op::Imm8(*v), span.clone()),
+                })
+                .unzip();
+            Statement {
+                instr: Instr::from_parts(m, ops).expect("expansion should be valid"),
+                span: span.clone(),
+                operands,
+                macro_span: None,
+            }
+        })
+        .collect();
+    Some(statements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let span = Span::new("a.asm", 1, 1, 14);
+        let spans = [
+            Span::new("a.asm", 1, 5, 2),
+            Span::new("a.asm", 1, 9, 2),
+            Span::new("a.asm", 1, 13, 2),
+        ];
+        let ops = [Op::Reg(1), Op::Reg(2), Op::Label("end".to_string())];
+
+        let statements = expand("beq", &ops, &spans, &span).unwrap();
+        assert_eq!(
+            statements.iter().map(|s| &s.instr).collect::<Vec<_>>(),
+            [
+                &Instr::Cmp {
+                    rs1: Op::Reg(1),
+                    rs2: Op::Reg(2)
+                },
+                &Instr::Bz {
+                    imm: Op::Label("end".to_string())
+                }
+            ]
+        );
+        assert_eq!(statements[1].operands, [spans[2].clone()]);
+
+        let statements = expand("dec", &ops[..1], &spans[..1], &span).unwrap();
+        assert_eq!(
+            statements[0].instr,
+            Instr::Addi {
+                rd: Op::Reg(1),
+                rs1: Op::Reg(1),
+                imm: Op::Imm8(0xff)
+            }
+        );
+        assert_eq!(statements[0].operands[2], span);
+
+        assert!(expand("add", &ops, &spans, &span).is_none());
+    }
+}
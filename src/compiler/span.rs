@@ -0,0 +1,189 @@
+use std::fmt;
+
+use console::style;
+
+/// A range of source code within a single line. Lines and columns start at 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub file: String,
+    pub line: usize,
+    /// Column of the first byte, counting bytes
+    pub column: usize,
+    /// Length in bytes
+    pub len: usize,
+}
+
+impl Span {
+    pub fn new(file: &str, line: usize, column: usize, len: usize) -> Self {
+        Self {
+            file: file.to_string(),
+            line,
+            column,
+            len,
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// Mapping from instruction addresses to their source locations,
+/// along with the source text they point into
+#[derive(Debug, Default, Clone)]
+pub struct SourceMap {
+    spans: Vec<Option<Span>>,
+    /// Source text of each file, in the order they were added
+    sources: Vec<(String, String)>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the source text of a given file.
+    pub fn add_source(&mut self, file: &str, src: &str) {
+        match self.sources.iter_mut().find(|(f, _)| f == file) {
+            Some((_, s)) => *s = src.to_string(),
+            None => self.sources.push((file.to_string(), src.to_string())),
+        }
+    }
+
+    /// Records the span of the instruction at a given address.
+    pub fn insert(&mut self, pc: u16, span: Span) {
+        let pc = pc as usize;
+        if pc >= self.spans.len() {
+            self.spans.resize(pc + 1, None);
+        }
+        self.spans[pc] = Some(span);
+    }
+
+    /// Moves every recorded instruction up by a given number of addresses,
+    /// i.e. once its program is placed past the start of program memory.
+    pub fn shift(&mut self, by: u16) {
+        self.spans
+            .splice(0..0, std::iter::repeat_n(None, by as usize));
+    }
+
+    /// Getter for the span of the instruction at a given address.
+    pub fn get(&self, pc: u16) -> Option<&Span> {
+        self.spans.get(pc as usize)?.as_ref()
+    }
+
+    /// Getter for the source text of a given file.
+    pub fn source(&self, file: &str) -> Option<&str> {
+        self.sources
+            .iter()
+            .find_map(|(f, src)| (f == file).then_some(src.as_str()))
+    }
+
+    /// Getter for the files with registered source text, in the order they were added.
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.sources.iter().map(|(f, _)| f.as_str())
+    }
+
+    /// Getter for the source line a given span points into.
+    pub fn line(&self, span: &Span) -> Option<&str> {
+        self.source(&span.file)?
+            .lines()
+            .nth(span.line.checked_sub(1)?)
+    }
+
+    /// Renders the source line a given span points into, with carets
+    /// underlining the span followed by a given label, i.e.
+    ///
+    /// ```text
+    ///  --> prog.asm:3:3
+    ///   |
+    /// 3 |   jmp 42
+    ///   |   ^^^^^^ label
+    /// ```
+    pub fn render(&self, span: &Span, label: &str) -> Option<String> {
+        let line = self.line(span)?;
+
+        let gutter = " ".repeat(span.line.to_string().len());
+        // Carets are padded by the text before them, with its tabs kept,
+        // so as to line up however tabs are shown or characters encoded
+        let start = span.column - 1;
+        let padding: String = line
+            .get(..start.min(line.len()))
+            .unwrap_or_default()
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .chain(std::iter::repeat_n(' ', start.saturating_sub(line.len())))
+            .collect();
+        let width = line
+            .get(start..start + span.len)
+            .map_or(span.len, |text| text.chars().count());
+        let underline = format!("{}{} {}", padding, "^".repeat(width.max(1)), label);
+        Some(format!(
+            "{gutter}--> {span}\n{gutter} |\n{} | {line}\n{gutter} | {}",
+            span.line,
+            style(underline.trim_end()).red().bold(),
+        ))
+    }
+
+    /// Renders the source line of the instruction at a given address,
+    /// with the instruction underlined.
+    pub fn snippet(&self, pc: u16) -> Option<String> {
+        self.render(self.get(pc)?, "")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snippet() {
+        let mut map = SourceMap::new();
+        map.add_source("prog.asm", "start:\n  addi r1, r0, 1\n  jmp 42\n");
+        map.insert(0, Span::new("prog.asm", 2, 3, 14));
+        map.insert(1, Span::new("prog.asm", 3, 3, 6));
+
+        assert_eq!(map.get(1), Some(&Span::new("prog.asm", 3, 3, 6)));
+        assert_eq!(map.line(map.get(0).unwrap()), Some("  addi r1, r0, 1"));
+        assert_eq!(
+            map.snippet(1).unwrap(),
+            " --> prog.asm:3:3\n  |\n3 |   jmp 42\n  |   ^^^^^^"
+        );
+        assert!(map.snippet(2).is_none());
+        map.add_source("lib.asm", "");
+        map.add_source("prog.asm", "start:\n  addi r1, r0, 1\n  jmp 42\n");
+        assert_eq!(map.files().collect::<Vec<_>>(), ["prog.asm", "lib.asm"]);
+
+        // Padding between instructions has no source
+        map.insert(3, Span::new("prog.asm", 3, 3, 6));
+        assert!(map.get(2).is_none());
+        assert!(map.get(3).is_some());
+
+        // Placing the program moves its instructions
+        map.shift(0x10);
+        assert!(map.get(1).is_none());
+        assert_eq!(map.get(0x11), Some(&Span::new("prog.asm", 3, 3, 6)));
+
+        let span = Span::new("prog.asm", 2, 12, 2);
+        assert_eq!(
+            map.render(&span, "here").unwrap(),
+            " --> prog.asm:2:12\n  |\n2 |   addi r1, r0, 1\n  |            ^^ here"
+        );
+
+        // Tabs and characters of several bytes before the span
+        map.add_source("tab.asm", "\tjmp 42\n\tadd é, r1\n");
+        assert_eq!(
+            map.render(&Span::new("tab.asm", 1, 2, 6), "").unwrap(),
+            " --> tab.asm:1:2\n  |\n1 | \tjmp 42\n  | \t^^^^^^"
+        );
+        assert_eq!(
+            map.render(&Span::new("tab.asm", 2, 10, 2), "").unwrap(),
+            " --> tab.asm:2:10\n  |\n2 | \tadd é, r1\n  | \t       ^^"
+        );
+        assert_eq!(
+            map.render(&Span::new("tab.asm", 2, 6, 2), "").unwrap(),
+            " --> tab.asm:2:6\n  |\n2 | \tadd é, r1\n  | \t    ^"
+        );
+    }
+}
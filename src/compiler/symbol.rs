@@ -1,6 +1,16 @@
 use std::collections::HashMap;
 
-use crate::compiler::ast::*;
+use crate::{
+    assembler::binary::MAX_WORDS,
+    compiler::{
+        ast::*,
+        diagnostic::{Diagnostic, DiagnosticKind, suggest},
+        expr::{ExprError, fits},
+        parser::Statement,
+        span::Span,
+    },
+    interpreter::state::MEMORY_SIZE,
+};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -8,52 +18,500 @@ pub enum SymbolError {
     #[error("Duplicate symbol declaration: {0}")]
     DuplicateSymbol(String),
 
-    #[error("No such symbol in table: {0}")]
-    NoSuchSymbol(String),
-
     #[error("Unstripped symbol encountered: {0}")]
     UnstrippedSymbol(String),
+
+    #[error("Attempt to move location counter backwards, from {from:#x} to {to:#x}")]
+    OrgBackwards { index: usize, from: u16, to: u16 },
+
+    #[error("Attempt to place `{what}` in the {section} section")]
+    WrongSection {
+        index: usize,
+        what: &'static str,
+        section: &'static str,
+    },
+
+    #[error("Attempt to grow the {section} section past its size of {size:#x}")]
+    SectionOverflow {
+        index: usize,
+        section: &'static str,
+        size: usize,
+    },
+
+    #[error("Value of `{expr}` ({value:#x}) does not fit in {bits} bits")]
+    OutOfRange {
+        site: Site,
+        operand: usize,
+        expr: String,
+        value: u16,
+        bits: u32,
+    },
+
+    #[error("Cannot evaluate `{expr}`: {error}")]
+    Eval {
+        site: Site,
+        operand: usize,
+        expr: String,
+        error: ExprError,
+    },
 }
 
-/// Table holding a mapping between symbols and their addresses
+/// Where an expression was evaluated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Site {
+    /// Index of a statement in the input program
+    Statement(usize),
+    /// Address of an instruction in the laid out program
+    Instr(u16),
+}
+
+/// Table holding a mapping between symbols and their addresses (or values)
 pub type SymbolTable = HashMap<String, u16>;
 
-/// Strips symbol instructions from a given program.
-pub fn strip_symbols(prg: &Program) -> Result<(Program, SymbolTable), SymbolError> {
-    // Stripped program length is <= input program
-    let mut out: Program = Vec::with_capacity(prg.len());
+/// A program laid out in memory, with its symbols stripped
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stripped {
+    /// Instructions, indexed by address
+    pub code: Program,
+    /// Initial contents of data memory, from address 0
+    pub data: Vec<u8>,
+    pub symbols: SymbolTable,
+    /// Address of each input instruction, `None` for labels and directives
+    pub addresses: Vec<Option<u16>>,
+    /// Section each label was defined in, constants excluded
+    pub labels: HashMap<String, Section>,
+    /// Values laid out by `.byte` and `.word`, in order
+    pub values: Vec<DataValue>,
+    /// Symbols declared `.extern` and not defined, in order of declaration
+    pub externs: Vec<String>,
+}
+
+/// A value laid out by `.byte` or `.word` in the data section
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataValue {
+    /// Index of the statement the value comes from
+    pub index: usize,
+    /// Position of the value among the operands of its statement
+    pub operand: usize,
+    /// Address of the value in the data section
+    pub offset: u16,
+    /// Width in bytes
+    pub width: usize,
+}
+
+/// Sections of a program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Text,
+    Data,
+}
+
+impl Section {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Data => "data",
+        }
+    }
 
-    let mut symbols: SymbolTable = HashMap::new();
-    let mut pc = 0u16;
+    /// Size of the section's address space
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Text => MAX_WORDS,
+            Self::Data => MEMORY_SIZE,
+        }
+    }
+}
+
+/// Strips symbol instructions and directives from a given program,
+/// laying out its instructions in the code section and its data in
+/// the data section.
+pub fn strip_symbols(prg: &Program) -> Result<Stripped, SymbolError> {
+    let mut out = Stripped {
+        code: Vec::with_capacity(prg.len()),
+        addresses: Vec::with_capacity(prg.len()),
+        ..Default::default()
+    };
+    let mut section = Section::Text;
+    let mut declared: Vec<&String> = Vec::new();
+
+    for (index, instr) in prg.iter().enumerate() {
+        let here = match section {
+            Section::Text => out.code.len(),
+            Section::Data => out.data.len(),
+        };
+
+        // Grows the current section to a given size, if it fits
+        let grow = |out: &mut Stripped, len: usize| {
+            if len > section.size() {
+                return Err(SymbolError::SectionOverflow {
+                    index,
+                    section: section.name(),
+                    size: section.size(),
+                });
+            }
+            // Code is padded with halts, which encode as zeros
+            match section {
+                Section::Text => out.code.resize(len.max(here), Instr::Halt),
+                Section::Data => out.data.resize(len.max(here), 0),
+            }
+            Ok(())
+        };
+        let define = |out: &mut Stripped, symbol: &str, value: u16| {
+            // If symbol already seen, error
+            if out.symbols.contains_key(symbol) {
+                return Err(SymbolError::DuplicateSymbol(symbol.to_string()));
+            }
+            out.symbols.insert(symbol.to_string(), value);
+            Ok(())
+        };
+        let emit = |out: &mut Stripped, bytes: &[u8], what: &'static str| {
+            if section != Section::Data {
+                return Err(SymbolError::WrongSection {
+                    index,
+                    what,
+                    section: section.name(),
+                });
+            }
+            grow(out, here + bytes.len())?;
+            out.data[here..].copy_from_slice(bytes);
+            Ok(())
+        };
 
-    for instr in prg {
+        let mut address = None;
+        let eval = |out: &Stripped, e: &Expr| {
+            evaluate(e, &out.symbols, Site::Statement(index), 0, 16).map(|v| v as usize)
+        };
         match instr {
             Instr::Label(label) => {
-                // If symbol already seen, error
-                if symbols.contains_key(label) {
-                    return Err(SymbolError::DuplicateSymbol(label.to_string()));
+                define(&mut out, label, here as u16)?;
+                out.labels.insert(label.clone(), section);
+            }
+            Instr::Directive(d) => match d {
+                Directive::Text => section = Section::Text,
+                Directive::Data => section = Section::Data,
+                Directive::Equ(name, value) => {
+                    let value = eval(&out, value)? as u16;
+                    define(&mut out, name, value)?
+                }
+                Directive::Org(to) => {
+                    let to = eval(&out, to)?;
+                    if to < here {
+                        return Err(SymbolError::OrgBackwards {
+                            index,
+                            from: here as u16,
+                            to: to as u16,
+                        });
+                    }
+                    grow(&mut out, to)?;
+                }
+                Directive::Align(n) => {
+                    let n = eval(&out, n)?;
+                    grow(&mut out, here.next_multiple_of(n.max(1)))?
+                }
+                // Values may refer to symbols defined later on,
+                // so they are only filled in once all are known
+                Directive::Byte(values) | Directive::Word(values) => {
+                    let width = match d {
+                        Directive::Byte(_) => 1,
+                        _ => 2,
+                    };
+                    emit(&mut out, &vec![0; values.len() * width], d.name())?;
+                    out.values
+                        .extend((0..values.len()).map(|operand| DataValue {
+                            index,
+                            operand,
+                            offset: (here + operand * width) as u16,
+                            width,
+                        }));
+                }
+                Directive::String(text) => {
+                    let mut bytes = text.as_bytes().to_vec();
+                    bytes.push(0);
+                    emit(&mut out, &bytes, d.name())?
+                }
+                Directive::Zero(n) => {
+                    let n = eval(&out, n)?;
+                    emit(&mut out, &vec![0; n], d.name())?
                 }
+                Directive::Global(_) => {}
+                Directive::Extern(names) => declared.extend(names),
+            },
+            _ => {
+                if section != Section::Text {
+                    return Err(SymbolError::WrongSection {
+                        index,
+                        what: instr.mnemonic(),
+                        section: section.name(),
+                    });
+                }
+                grow(&mut out, here + 1)?;
+                out.code[here] = instr.clone();
+                address = Some(here as u16);
+            }
+        }
+        out.addresses.push(address);
+    }
 
-                // Record symbol
-                symbols.insert(label.clone(), pc);
+    for name in declared {
+        if !out.symbols.contains_key(name) && !out.externs.contains(name) {
+            out.externs.push(name.clone());
+        }
+    }
+
+    // Symbols defined elsewhere are left for the linker to fill in
+    let table = with_externs(&out.symbols, &out.externs);
+    for value in &out.values {
+        let Instr::Directive(d) = &prg[value.index] else {
+            unreachable!("data values should come from directives");
+        };
+        let v = evaluate(
+            d.exprs()[value.operand],
+            &table,
+            Site::Statement(value.index),
+            value.operand,
+            value.width as u32 * 8,
+        )?;
+        let at = value.offset as usize;
+        out.data[at..at + value.width].copy_from_slice(&v.to_le_bytes()[..value.width]);
+    }
+
+    Ok(out)
+}
+
+/// A symbol table with given external symbols added, at address 0
+pub fn with_externs(symbols: &SymbolTable, externs: &[String]) -> SymbolTable {
+    let mut table = symbols.clone();
+    table.extend(externs.iter().map(|name| (name.clone(), 0)));
+    table
+}
+
+/// Whether a label is numeric, i.e. "1"
+pub fn is_numeric(label: &str) -> bool {
+    !label.is_empty() && label.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Gives local labels names of their own, so that they resolve like any other:
+///
+/// - `.name` is scoped to the previous global label, becoming `global.name`
+/// - numeric labels `N:` are referenced as `Nb` (nearest definition before)
+///   or `Nf` (nearest definition after), and become `N#k` for their k-th definition
+///
+/// References to numeric labels without a matching definition are left as is,
+/// and labels made unique to macro expansions do not change the scope.
+pub fn scope_local_labels(statements: &mut [Statement]) {
+    // Positions of every numeric label definition
+    let mut numeric: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, stmt) in statements.iter().enumerate() {
+        if let Instr::Label(label) = &stmt.instr
+            && is_numeric(label)
+        {
+            numeric.entry(label.clone()).or_default().push(i);
+        }
+    }
+
+    let mut global = String::new();
+    for (i, stmt) in statements.iter_mut().enumerate() {
+        let scoped = |label: &str| -> Option<String> {
+            if label.starts_with('.') {
+                return Some(format!("{}{}", global, label));
             }
-            _ => {
-                out.push(instr.clone());
-                pc += 1;
+
+            // Numeric reference, resolved to the k-th definition
+            let (name, dir) = label.split_at(label.len().checked_sub(1)?);
+            let defs = numeric.get(name)?;
+            let k = match dir {
+                "b" => defs.iter().rposition(|d| *d < i)?,
+                "f" => defs.iter().position(|d| *d > i)?,
+                _ => return None,
+            };
+            Some(format!("{}#{}", name, k))
+        };
+
+        match &stmt.instr {
+            Instr::Label(label) if is_numeric(label) => {
+                let k = numeric[label].iter().position(|d| *d == i).unwrap();
+                stmt.instr = Instr::Label(format!("{}#{}", label, k));
+            }
+            Instr::Label(label) if label.starts_with('.') => {
+                stmt.instr = Instr::Label(scoped(label).unwrap());
+            }
+            // Labels local to macro expansions do not open a scope
+            Instr::Label(label) if label.contains('@') => {}
+            Instr::Label(label) => global = label.clone(),
+            Instr::Directive(d) => {
+                let mut d = d.clone();
+                d.exprs_mut().into_iter().for_each(|e| e.rename(&scoped));
+                stmt.instr = Instr::Directive(d);
+            }
+            instr => {
+                let ops = instr
+                    .operands()
+                    .into_iter()
+                    .map(|op| match op {
+                        Op::Label(label) => Op::Label(scoped(label).unwrap_or(label.clone())),
+                        Op::Expr(e) => {
+                            let mut e = e.clone();
+                            e.rename(&scoped);
+                            Op::Expr(e)
+                        }
+                        _ => op.clone(),
+                    })
+                    .collect();
+                stmt.instr = Instr::from_parts(instr.mnemonic(), ops)
+                    .expect("operands should be valid for their instruction");
+            }
+        }
+    }
+}
+
+/// Symbols referenced by a statement, along with the spans of
+/// the operands referencing them
+fn references(stmt: &Statement) -> Vec<(&String, &Span)> {
+    let symbols: Vec<Vec<&String>> = match &stmt.instr {
+        Instr::Directive(Directive::Global(names)) => names.iter().map(|n| vec![n]).collect(),
+        Instr::Directive(d) => d.exprs().into_iter().map(Expr::symbols).collect(),
+        instr => instr
+            .operands()
+            .into_iter()
+            .map(|op| match op {
+                Op::Label(label) => vec![label],
+                Op::Expr(e) => e.symbols(),
+                _ => vec![],
+            })
+            .collect(),
+    };
+    symbols
+        .into_iter()
+        .zip(&stmt.operands)
+        .flat_map(|(symbols, span)| symbols.into_iter().map(move |s| (s, span)))
+        .collect()
+}
+
+/// Checks parsed statements for duplicate and undefined labels,
+/// returning a diagnostic for each offending label.
+/// Symbols declared `.extern` count as defined, so may not also be defined.
+pub fn check_symbols(statements: &[Statement]) -> Vec<Diagnostic> {
+    let mut errors = Vec::new();
+    let mut defined: HashMap<&str, &Span> = HashMap::new();
+    let mut declared: HashMap<&str, &Span> = HashMap::new();
+    let both = |stmt: &Statement, symbol: &str, span: &Span, label: &str, other: &Span, note| {
+        stmt.note_expansion(
+            Diagnostic::new(
+                DiagnosticKind::DuplicateLabel,
+                span.clone(),
+                format!("symbol `{}` is both declared `.extern` and defined", symbol),
+            )
+            .with_label(label)
+            .with_note(other.clone(), note)
+            .with_hint("`.extern` declares symbols defined by other objects"),
+        )
+    };
+
+    for stmt in statements {
+        // Labels and constants share a namespace
+        let symbol = match &stmt.instr {
+            Instr::Label(label) => label,
+            Instr::Directive(Directive::Equ(name, _)) => name,
+            Instr::Directive(Directive::Extern(names)) => {
+                for (name, span) in names.iter().zip(&stmt.operands) {
+                    if let Some(definition) = defined.get(name.as_str()) {
+                        errors.push(both(
+                            stmt,
+                            name,
+                            span,
+                            "declared here",
+                            definition,
+                            "defined here",
+                        ));
+                    }
+                    declared.entry(name).or_insert(span);
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        if let Some(declaration) = declared.get(symbol.as_str()) {
+            errors.push(both(
+                stmt,
+                symbol,
+                &stmt.span,
+                "defined here",
+                declaration,
+                "declared `.extern` here",
+            ));
+        }
+        match defined.get(symbol.as_str()) {
+            Some(first) => errors.push(
+                stmt.note_expansion(
+                    Diagnostic::new(
+                        DiagnosticKind::DuplicateLabel,
+                        stmt.span.clone(),
+                        format!("symbol `{}` is defined multiple times", symbol),
+                    )
+                    .with_label("redefined here")
+                    .with_note((*first).clone(), "first defined here"),
+                ),
+            ),
+            None => {
+                defined.insert(symbol, &stmt.span);
             }
         }
     }
 
-    Ok((out, symbols))
+    for stmt in statements {
+        for (label, span) in references(stmt) {
+            if defined.contains_key(label.as_str()) || declared.contains_key(label.as_str()) {
+                continue;
+            }
+
+            let diag = stmt.note_expansion(
+                Diagnostic::new(
+                    DiagnosticKind::UndefinedLabel,
+                    span.clone(),
+                    format!("undefined label `{}`", label),
+                )
+                .with_label("not defined"),
+            );
+            // Labels local to macro expansions are not visible outside of them
+            let visible = defined.keys().copied().filter(|k| !k.contains('@'));
+            errors.push(match suggest(label, visible) {
+                Some(s) => diag.with_hint(format!("did you mean `{}`?", s)),
+                None => diag,
+            });
+        }
+    }
+
+    errors
 }
 
-/// Getter for an address in symbol table.
-#[inline]
-fn lookup_address(symbol: &str, table: &SymbolTable) -> Result<u16, SymbolError> {
-    match table.get(symbol) {
-        Some(a) => Ok(*a),
-        None => Err(SymbolError::NoSuchSymbol(symbol.to_string())),
+/// Evaluates an operand expression with symbols from table,
+/// checking that it fits in a given number of bits.
+fn evaluate(
+    expr: &Expr,
+    table: &SymbolTable,
+    site: Site,
+    operand: usize,
+    bits: u32,
+) -> Result<u16, SymbolError> {
+    let value = expr
+        .eval(&|s| table.get(s).copied())
+        .map_err(|error| SymbolError::Eval {
+            site,
+            operand,
+            expr: expr.to_string(),
+            error,
+        })?;
+    if !fits(value, bits) {
+        return Err(SymbolError::OutOfRange {
+            site,
+            operand,
+            expr: expr.to_string(),
+            value,
+            bits,
+        });
     }
+    Ok(value)
 }
 
 /// Replaces symbols in operands with addresses from table.
@@ -61,37 +519,39 @@ pub fn replace_symbols(prg: &Program, symbols: &SymbolTable) -> Result<Program,
     // Resulting program length will be == input program length
     let mut out: Program = Vec::with_capacity(prg.len());
 
-    for instr in prg {
+    for (pc, instr) in prg.iter().enumerate() {
         match instr {
             Instr::Label(s) => {
                 // Input program not fully stripped
                 return Err(SymbolError::UnstrippedSymbol(s.to_string()));
             }
-            Instr::Jmp {
-                imm: Op::Label(symbol),
-            } => {
-                let addr = lookup_address(symbol, symbols)?;
-                out.push(Instr::Jmp {
-                    imm: Op::Imm12(addr),
-                })
-            }
-            Instr::Bnz {
-                imm: Op::Label(symbol),
-            } => {
-                let addr = lookup_address(symbol, symbols)?;
-                out.push(Instr::Bnz {
-                    imm: Op::Imm12(addr),
-                })
-            }
-            Instr::Bz {
-                imm: Op::Label(symbol),
-            } => {
-                let addr = lookup_address(symbol, symbols)?;
-                out.push(Instr::Bz {
-                    imm: Op::Imm12(addr),
-                })
-            }
-            _ => out.push(instr.clone()),
+            Instr::Directive(d) => return Err(SymbolError::UnstrippedSymbol(d.to_string())),
+            _ => {
+                // Symbol operands resolve to immediates of the width the instruction takes
+                let bits = if instr.has_imm8() { 8 } else { 12 };
+                let ops = instr
+                    .operands()
+                    .into_iter()
+                    .enumerate()
+                    .map(|(operand, op)| {
+                        let value = match op {
+                            Op::Label(symbol) => Expr::Symbol(symbol.clone()),
+                            Op::Expr(e) => e.clone(),
+                            _ => return Ok(op.clone()),
+                        };
+                        let site = Site::Instr(pc as u16);
+                        let value = evaluate(&value, symbols, site, operand, bits)?;
+                        Ok(match bits {
+                            8 => Op::Imm8(value as u8),
+                            _ => Op::Imm12(value & 0xfff),
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                out.push(
+                    Instr::from_parts(instr.mnemonic(), ops)
+                        .expect("operands should be valid for their instruction"),
+                );
+            }
         }
     }
 
@@ -101,14 +561,16 @@ pub fn replace_symbols(prg: &Program, symbols: &SymbolTable) -> Result<Program,
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::compiler::parser::parse_statements;
 
     #[test]
     fn test_strip_symbols() {
         let prg = vec![Instr::Label("start".to_string()), Instr::Halt];
 
-        let (stripped, symbols) = strip_symbols(&prg).unwrap();
-        assert_eq!(stripped.len(), 1);
-        assert_eq!(symbols.get("start").unwrap(), &0u16);
+        let stripped = strip_symbols(&prg).unwrap();
+        assert_eq!(stripped.code.len(), 1);
+        assert_eq!(stripped.symbols.get("start").unwrap(), &0u16);
+        assert_eq!(stripped.addresses, vec![None, Some(0)]);
 
         // Program with duplicate labels
         let prg = vec![
@@ -119,6 +581,110 @@ mod tests {
         assert!(res.is_err());
     }
 
+    #[test]
+    fn test_layout() {
+        let src = "\
+.equ LEN, 4
+.data
+buf: .zero 4
+msg: .string \"hi\"
+.align 4
+word: .word 0x1234
+.text
+  lb r1, buf(r0)
+  addi r2, r0, LEN
+.org 4
+end: jmp end
+";
+        let (statements, errors) = parse_statements("a.asm", src);
+        assert!(errors.is_empty());
+        let prg: Program = statements.into_iter().map(|s| s.instr).collect();
+        let stripped = strip_symbols(&prg).unwrap();
+
+        assert_eq!(stripped.code.len(), 5);
+        assert_eq!(stripped.code[2], Instr::Halt);
+        assert_eq!(stripped.data, [0, 0, 0, 0, b'h', b'i', 0, 0, 0x34, 0x12]);
+        assert_eq!(stripped.symbols["LEN"], 4);
+        assert_eq!(stripped.symbols["msg"], 4);
+        assert_eq!(stripped.symbols["word"], 8);
+        assert_eq!(stripped.symbols["end"], 4);
+        assert_eq!(stripped.addresses[10], Some(0));
+        assert_eq!(stripped.addresses[14], Some(4));
+        assert_eq!(stripped.labels["word"], Section::Data);
+        assert!(!stripped.labels.contains_key("LEN"));
+        assert_eq!(
+            stripped.values,
+            [DataValue {
+                index: 8,
+                operand: 0,
+                offset: 8,
+                width: 2
+            }]
+        );
+
+        let replaced = replace_symbols(&stripped.code, &stripped.symbols).unwrap();
+        assert_eq!(
+            replaced[1],
+            Instr::Addi {
+                rd: Op::Reg(2),
+                rs1: Op::Reg(0),
+                imm: Op::Imm8(4)
+            }
+        );
+
+        // Layout errors
+        let layout = |src: &str| {
+            let (statements, _) = parse_statements("a.asm", src);
+            let prg: Program = statements.into_iter().map(|s| s.instr).collect();
+            strip_symbols(&prg).unwrap_err()
+        };
+        assert!(matches!(
+            layout("nop\nnop\n.org 1\n"),
+            SymbolError::OrgBackwards {
+                index: 2,
+                from: 2,
+                to: 1
+            }
+        ));
+        assert!(matches!(
+            layout(".byte 1\n"),
+            SymbolError::WrongSection {
+                index: 0,
+                what: ".byte",
+                section: "text"
+            }
+        ));
+        assert!(matches!(
+            layout(".data\nnop\n"),
+            SymbolError::WrongSection { index: 1, .. }
+        ));
+        assert!(matches!(
+            layout(".data\n.zero 0x200\n.byte 1\n"),
+            SymbolError::SectionOverflow {
+                index: 2,
+                section: "data",
+                ..
+            }
+        ));
+
+        // Symbols must fit the immediates they are used as
+        let prg = vec![Instr::Addi {
+            rd: Op::Reg(1),
+            rs1: Op::Reg(0),
+            imm: Op::Label("far".to_string()),
+        }];
+        let symbols = SymbolTable::from([("far".to_string(), 0x100)]);
+        assert!(matches!(
+            replace_symbols(&prg, &symbols),
+            Err(SymbolError::OutOfRange {
+                site: Site::Instr(0),
+                operand: 2,
+                bits: 8,
+                ..
+            })
+        ));
+    }
+
     #[test]
     fn test_replace_symbols() {
         let prg = vec![
@@ -134,8 +700,112 @@ mod tests {
 
         assert_eq!(replaced[0], Instr::Jmp { imm: Op::Imm12(0) });
 
+        // Subroutine call
+        let prg = vec![Instr::Call {
+            imm: Op::Label("start".to_string()),
+        }];
+        let replaced = replace_symbols(&prg, &symbols).ok().unwrap();
+        assert_eq!(replaced[0], Instr::Call { imm: Op::Imm12(0) });
+
         // Unstripped program
         let prg = vec![Instr::Label("start".to_string()), Instr::Halt];
         assert!(replace_symbols(&prg, &symbols).err().is_some());
     }
+
+    #[test]
+    fn test_scope_local_labels() {
+        let src = "\
+.top: jmp .loop
+outer:
+.loop: jmp 1f
+1: bz 1b
+1: bnz .loop
+inner: jmp .loop
+.loop: jmp 1b
+  jmp 2f
+";
+        let (mut statements, errors) = parse_statements("a.asm", src);
+        assert!(errors.is_empty());
+        scope_local_labels(&mut statements);
+
+        let labels: Vec<_> = statements
+            .iter()
+            .flat_map(|s| match &s.instr {
+                Instr::Label(l) => vec![l.as_str()],
+                i => i
+                    .operands()
+                    .into_iter()
+                    .filter_map(|op| match op {
+                        Op::Label(l) => Some(l.as_str()),
+                        _ => None,
+                    })
+                    .collect(),
+            })
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                ".top",
+                ".loop",
+                "outer",
+                "outer.loop",
+                "1#0",
+                "1#0",
+                "1#0",
+                "1#1",
+                "outer.loop",
+                "inner",
+                "inner.loop",
+                "inner.loop",
+                "1#1",
+                "2f"
+            ]
+        );
+
+        // Unresolved references are reported as undefined
+        let errors = check_symbols(&statements);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].message, "undefined label `.loop`");
+        assert_eq!(errors[1].message, "undefined label `2f`");
+    }
+
+    #[test]
+    fn test_check_symbols() {
+        let src = "start:\n  jmp strat\nstart:\n  bz end\n";
+        let (statements, _) = parse_statements("a.asm", src);
+        let errors = check_symbols(&statements);
+
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0].kind, DiagnosticKind::DuplicateLabel);
+        assert_eq!(errors[0].span.line, 3);
+        assert_eq!(errors[0].notes[0].0.line, 1);
+        assert_eq!(errors[1].kind, DiagnosticKind::UndefinedLabel);
+        assert_eq!(errors[1].span, Span::new("a.asm", 2, 7, 5));
+        assert_eq!(errors[1].hint.as_deref(), Some("did you mean `start`?"));
+        assert_eq!(errors[2].hint, None);
+        // External symbols count as defined, and exported ones must be
+        let src = ".extern putc\n.global main, putc, nope\nmain: call putc\n";
+        let (statements, _) = parse_statements("a.asm", src);
+        let errors = check_symbols(&statements);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].span, Span::new("a.asm", 2, 21, 4));
+
+        let prg: Program = statements.into_iter().map(|s| s.instr).collect();
+        let stripped = strip_symbols(&prg).unwrap();
+        assert_eq!(stripped.externs, ["putc"]);
+
+        // External symbols may not also be defined, in either order
+        let src = ".extern X, Y\nX: halt\n.equ Z, 1\n.extern Z\n";
+        let (statements, _) = parse_statements("a.asm", src);
+        let errors = check_symbols(&statements);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0].message,
+            "symbol `X` is both declared `.extern` and defined"
+        );
+        assert_eq!(errors[0].span.line, 2);
+        assert_eq!(errors[0].notes[0].0, Span::new("a.asm", 1, 9, 1));
+        assert_eq!(errors[1].span, Span::new("a.asm", 4, 9, 1));
+        assert_eq!(errors[1].notes[0].0.line, 3);
+    }
 }
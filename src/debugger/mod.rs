@@ -0,0 +1,673 @@
+use std::{
+    collections::BTreeSet,
+    fmt,
+    io::{self, BufRead, Write},
+    time::Instant,
+};
+
+use console::style;
+
+use crate::{
+    assembler::layout::Layout,
+    compiler::{
+        Compiled,
+        ast::*,
+        compile,
+        diagnostic::Diagnostics,
+        span::SourceMap,
+        symbol::{Section, SymbolTable},
+    },
+    interpreter::{
+        Limits, TIMEOUT_CHECK_INTERVAL,
+        state::State,
+        vm::{InterpreterError, interpret},
+    },
+};
+
+/// Number of source lines shown around the current line
+const CONTEXT_LINES: usize = 2;
+
+/// Number of instructions `continue` executes at most, unless changed
+pub const DEFAULT_MAX_STEPS: u64 = 1_000_000;
+
+/// A breakpoint location, given as address or label
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    Address(u16),
+    Label(String),
+}
+
+/// A watched register or flag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watch {
+    Reg(u8),
+    Zero,
+    Negative,
+    Carry,
+    Overflow,
+}
+
+impl Watch {
+    /// Reads the watched value from a state
+    fn read(&self, state: &State) -> u8 {
+        match self {
+            Self::Reg(r) => state.regs.r(*r).unwrap_or_default(),
+            Self::Zero => state.flags.zero as u8,
+            Self::Negative => state.flags.negative as u8,
+            Self::Carry => state.flags.carry as u8,
+            Self::Overflow => state.flags.overflow as u8,
+        }
+    }
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Reg(r) => write!(f, "r{}", r),
+            Self::Zero => write!(f, "zero"),
+            Self::Negative => write!(f, "negative"),
+            Self::Carry => write!(f, "carry"),
+            Self::Overflow => write!(f, "overflow"),
+        }
+    }
+}
+
+/// Debugger commands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Execute a number of instructions
+    Step(usize),
+    /// Execute until a breakpoint, watchpoint, halt, error or the step limit
+    Continue,
+    /// Set the number of instructions `continue` executes at most, if any
+    Limit(Option<u64>),
+    /// Set a breakpoint
+    Break(Location),
+    /// Remove a breakpoint
+    Delete(Location),
+    /// Watch a register or flag for changes
+    Watch(Watch),
+    /// Stop watching a register or flag
+    Unwatch(Watch),
+    /// Print machine state
+    Print,
+    /// List source around the current line
+    List,
+    /// List breakpoints and watchpoints
+    Info,
+    /// Restart the program from its initial state
+    Reset,
+    Help,
+    Quit,
+}
+
+const HELP: &str = "\
+Commands:
+  s, step [n]          execute n instructions (default 1)
+  c, continue          run until breakpoint, watchpoint, halt, error or step limit
+  limit <n|off>        set the step limit of continue (default 1000000)
+  b, break <loc>       set breakpoint at address or label
+  d, delete <loc>      remove breakpoint at address or label
+  w, watch <target>    stop when register (r0-r15) or flag (zero, negative, carry, overflow) changes
+  u, unwatch <target>  remove watchpoint
+  p, print             print machine state
+  l, list              list source around current line
+  i, info              list breakpoints and watchpoints
+  r, reset             restart program
+  h, help              show this help
+  q, quit              exit debugger";
+
+/// Parses a number, hex or decimal
+fn parse_number(s: &str) -> Option<u16> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+impl Command {
+    /// Parses a command line. Empty lines step a single instruction
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let cmd = words.next().unwrap_or("step");
+        let arg = words.next();
+        if let Some(extra) = words.next() {
+            return Err(format!("unexpected argument: {}", extra));
+        }
+
+        let location = || -> Result<Location, String> {
+            let arg = arg.ok_or("missing address or label")?;
+            Ok(match parse_number(arg) {
+                Some(a) => Location::Address(a),
+                None => Location::Label(arg.to_string()),
+            })
+        };
+        let watch = || -> Result<Watch, String> {
+            let arg = arg.ok_or("missing register or flag")?;
+            match arg {
+                "z" | "zero" => Ok(Watch::Zero),
+                "n" | "negative" => Ok(Watch::Negative),
+                "c" | "carry" => Ok(Watch::Carry),
+                "v" | "overflow" => Ok(Watch::Overflow),
+                _ => match arg.strip_prefix('r').and_then(|r| r.parse().ok()) {
+                    Some(r @ 0..=15) => Ok(Watch::Reg(r)),
+                    _ => Err(format!("no such register or flag: {}", arg)),
+                },
+            }
+        };
+
+        match cmd {
+            "s" | "step" => match arg {
+                Some(n) => n
+                    .parse()
+                    .map(Command::Step)
+                    .map_err(|_| format!("invalid step count: {}", n)),
+                None => Ok(Command::Step(1)),
+            },
+            "c" | "continue" => Ok(Command::Continue),
+            "limit" => match arg {
+                Some("off") => Ok(Command::Limit(None)),
+                Some(n) => n
+                    .parse()
+                    .map(|n| Command::Limit(Some(n)))
+                    .map_err(|_| format!("invalid step limit: {}", n)),
+                None => Err("missing step limit".to_string()),
+            },
+            "b" | "break" => location().map(Command::Break),
+            "d" | "delete" => location().map(Command::Delete),
+            "w" | "watch" => watch().map(Command::Watch),
+            "u" | "unwatch" => watch().map(Command::Unwatch),
+            "p" | "print" => Ok(Command::Print),
+            "l" | "list" => Ok(Command::List),
+            "i" | "info" => Ok(Command::Info),
+            "r" | "reset" => Ok(Command::Reset),
+            "h" | "help" => Ok(Command::Help),
+            "q" | "quit" => Ok(Command::Quit),
+            _ => Err(format!("unknown command: {} (try 'help')", cmd)),
+        }
+    }
+}
+
+/// Reason execution stopped
+enum Stop {
+    Breakpoint,
+    Watchpoint(Watch, u8, u8),
+    Halted,
+    Error(String),
+}
+
+/// State of the machine mapped by a given layout, with a program's data
+/// loaded and the PC at the start of its text region
+fn initial_state(layout: &Layout, data: &[u8]) -> State {
+    let mut state = State::with_layout(layout);
+    state
+        .mem
+        .load(0, data)
+        .expect("compiled data should fit in memory");
+    state.pc = layout.region(Section::Text).start;
+    state
+}
+
+/// Interactive step debugger for a compiled program
+pub struct Debugger {
+    prg: Program,
+    /// Initial contents of data memory
+    data: Vec<u8>,
+    layout: Layout,
+    symbols: SymbolTable,
+    source_map: SourceMap,
+    state: State,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watch>,
+    /// Limits of each `continue`
+    limits: Limits,
+    /// Set once the program halted or failed
+    finished: bool,
+}
+
+impl Debugger {
+    /// Compiles a given program for debugging.
+    pub fn new(file: &str, src: &str) -> Result<Self, Diagnostics> {
+        compile(file, src).map(Self::from_compiled)
+    }
+
+    /// Debugs an already compiled program.
+    pub fn from_compiled(compiled: Compiled) -> Self {
+        Self::with_layout(compiled, &Layout::default())
+    }
+
+    /// Debugs a program placed by a given layout (i.e. once linked),
+    /// with memory mapped by the same layout.
+    pub fn with_layout(compiled: Compiled, layout: &Layout) -> Self {
+        Self {
+            state: initial_state(layout, &compiled.data),
+            prg: compiled.program,
+            data: compiled.data,
+            layout: layout.clone(),
+            symbols: compiled.symbols,
+            source_map: compiled.source_map,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            limits: Limits {
+                max_steps: Some(DEFAULT_MAX_STEPS),
+                timeout: None,
+            },
+            finished: false,
+        }
+    }
+
+    /// Sets the limits of each `continue`, which then stops with an error
+    /// once any is exceeded (but may be continued again)
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Current machine state
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Resolves a location into an address
+    fn resolve(&self, loc: &Location) -> Result<u16, String> {
+        match loc {
+            Location::Address(a) => Ok(*a),
+            Location::Label(l) => self
+                .symbols
+                .get(l)
+                .copied()
+                .ok_or_else(|| format!("no such label: {}", l)),
+        }
+    }
+
+    /// Executes a single instruction, reporting why execution stopped (if it did)
+    fn step(&mut self) -> Option<Stop> {
+        if self.finished {
+            return Some(Stop::Halted);
+        }
+
+        let pc = self.state.pc;
+        let Some(instr) = self.prg.get(pc as usize).filter(|_| self.state.is_code(pc)) else {
+            self.finished = true;
+            return Some(Stop::Error(
+                InterpreterError::PCOutOfBounds(self.state.pc).to_string(),
+            ));
+        };
+
+        let before: Vec<u8> = self
+            .watchpoints
+            .iter()
+            .map(|w| w.read(&self.state))
+            .collect();
+
+        match interpret(instr, &mut self.state) {
+            // Stay on the instruction leaving the program, to point at it
+            Ok(Some(pc)) if pc as usize >= self.prg.len() || !self.state.is_code(pc) => {
+                self.finished = true;
+                return Some(Stop::Error(InterpreterError::PCOutOfBounds(pc).to_string()));
+            }
+            Ok(Some(pc)) => self.state.pc = pc,
+            Ok(None) => {
+                self.finished = true;
+                return Some(Stop::Halted);
+            }
+            Err(e) => {
+                self.finished = true;
+                return Some(Stop::Error(e.to_string()));
+            }
+        }
+
+        for (w, old) in self.watchpoints.iter().zip(before) {
+            let new = w.read(&self.state);
+            if new != old {
+                return Some(Stop::Watchpoint(*w, old, new));
+            }
+        }
+        if self.breakpoints.contains(&self.state.pc) {
+            return Some(Stop::Breakpoint);
+        }
+        None
+    }
+
+    /// Executes instructions until execution stops, or any limit is exceeded
+    fn resume(&mut self) -> Stop {
+        let start = Instant::now();
+        let mut steps = 0u64;
+        loop {
+            let pc = self.state.pc;
+            if self.limits.max_steps.is_some_and(|max| steps >= max) {
+                return Stop::Error(InterpreterError::StepLimitExceeded(pc, steps).to_string());
+            }
+            if steps.is_multiple_of(TIMEOUT_CHECK_INTERVAL)
+                && self.limits.timeout.is_some_and(|t| start.elapsed() >= t)
+            {
+                return Stop::Error(InterpreterError::TimeLimitExceeded(pc, steps).to_string());
+            }
+            steps += 1;
+
+            if let Some(stop) = self.step() {
+                return stop;
+            }
+        }
+    }
+
+    /// Writes the source lines around the current instruction,
+    /// with the current line highlighted
+    fn list(&self, out: &mut impl Write) -> io::Result<()> {
+        let Some((current, source)) = self
+            .source_map
+            .get(self.state.pc)
+            .and_then(|span| Some((span.line - 1, self.source_map.source(&span.file)?)))
+        else {
+            return writeln!(out, "No source for address {}", self.state.pc);
+        };
+
+        let first = current.saturating_sub(CONTEXT_LINES);
+        for (n, line) in source
+            .lines()
+            .enumerate()
+            .skip(first)
+            .take(current + CONTEXT_LINES + 1 - first)
+        {
+            let text = format!("{:>4} | {}", n + 1, line);
+            if n == current {
+                writeln!(out, "{} {}", style("=>").green().bold(), style(text).bold())?;
+            } else {
+                writeln!(out, "   {}", style(text).dim())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the machine state and current source line
+    fn print(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "{}", self.state)?;
+        self.list(out)
+    }
+
+    /// Executes a single command, writing its output.
+    /// Returns `false` once the debugger should exit
+    pub fn execute(&mut self, cmd: Command, out: &mut impl Write) -> io::Result<bool> {
+        match cmd {
+            Command::Step(n) => {
+                let stop = (0..n).find_map(|_| self.step());
+                self.report(stop, out)?;
+            }
+            Command::Continue => {
+                let stop = self.resume();
+                self.report(Some(stop), out)?;
+            }
+            Command::Limit(max_steps) => {
+                self.limits.max_steps = max_steps;
+                match max_steps {
+                    Some(n) => writeln!(out, "Continue stops after {} steps", n)?,
+                    None => writeln!(out, "Continue has no step limit")?,
+                }
+            }
+            Command::Break(loc) => match self.resolve(&loc) {
+                Ok(a) => {
+                    self.breakpoints.insert(a);
+                    writeln!(out, "Breakpoint set at {:#05x}", a)?;
+                }
+                Err(e) => writeln!(out, "{} {}", style("Error").red().bold(), e)?,
+            },
+            Command::Delete(loc) => match self.resolve(&loc) {
+                Ok(a) if self.breakpoints.remove(&a) => {
+                    writeln!(out, "Breakpoint removed at {:#05x}", a)?
+                }
+                Ok(a) => writeln!(out, "No breakpoint at {:#05x}", a)?,
+                Err(e) => writeln!(out, "{} {}", style("Error").red().bold(), e)?,
+            },
+            Command::Watch(w) => {
+                if !self.watchpoints.contains(&w) {
+                    self.watchpoints.push(w);
+                }
+                writeln!(out, "Watching {}", w)?;
+            }
+            Command::Unwatch(w) => {
+                self.watchpoints.retain(|x| *x != w);
+                writeln!(out, "No longer watching {}", w)?;
+            }
+            Command::Print => self.print(out)?,
+            Command::List => self.list(out)?,
+            Command::Info => {
+                writeln!(out, "Breakpoints:")?;
+                for a in &self.breakpoints {
+                    // Show labels pointing at the address, if any
+                    let mut labels: Vec<_> = self
+                        .symbols
+                        .iter()
+                        .filter(|(_, addr)| *addr == a)
+                        .map(|(l, _)| l.as_str())
+                        .collect();
+                    labels.sort();
+                    writeln!(out, "  {:#05x} {}", a, labels.join(", "))?;
+                }
+                writeln!(out, "Watchpoints:")?;
+                for w in &self.watchpoints {
+                    writeln!(out, "  {}", w)?;
+                }
+            }
+            Command::Reset => {
+                self.state = initial_state(&self.layout, &self.data);
+                self.finished = false;
+                writeln!(out, "Program reset")?;
+                self.list(out)?;
+            }
+            Command::Help => writeln!(out, "{}", HELP)?,
+            Command::Quit => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Writes why execution stopped, followed by the machine state
+    fn report(&self, stop: Option<Stop>, out: &mut impl Write) -> io::Result<()> {
+        match stop {
+            None => {}
+            Some(Stop::Breakpoint) => writeln!(
+                out,
+                "{} at {:#05x}",
+                style("Breakpoint").yellow().bold(),
+                self.state.pc
+            )?,
+            Some(Stop::Watchpoint(w, old, new)) => writeln!(
+                out,
+                "{} {}: {:#04x} -> {:#04x}",
+                style("Watchpoint").yellow().bold(),
+                w,
+                old,
+                new
+            )?,
+            Some(Stop::Halted) => writeln!(out, "{}", style("Halted").green().bold())?,
+            Some(Stop::Error(e)) => {
+                writeln!(out, "{} {}", style("Error").red().bold(), e)?;
+                if let Some(snippet) = self.source_map.snippet(self.state.pc) {
+                    writeln!(out, "{}", snippet)?;
+                }
+            }
+        }
+        self.print(out)
+    }
+
+    /// Runs the read-eval-print loop until quit or end of input.
+    pub fn repl(&mut self, input: impl BufRead, mut out: impl Write) -> io::Result<()> {
+        writeln!(out, "Type 'help' for a list of commands")?;
+        self.list(&mut out)?;
+
+        let mut lines = input.lines();
+        loop {
+            write!(out, "{} ", style("(cobble)").bold())?;
+            out.flush()?;
+
+            let Some(line) = lines.next().transpose()? else {
+                return Ok(());
+            };
+            match Command::parse(&line) {
+                Ok(cmd) => {
+                    if !self.execute(cmd, &mut out)? {
+                        return Ok(());
+                    }
+                }
+                Err(e) => writeln!(out, "{} {}", style("Error").red().bold(), e)?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRC: &str = "\
+start:
+  addi r1, r0, 3
+loop:
+  addi r2, r2, 2
+  addi r1, r1, 0xff ; r1 -= 1
+  bnz loop
+  halt
+";
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(Command::parse(""), Ok(Command::Step(1)));
+        assert_eq!(Command::parse("s 5"), Ok(Command::Step(5)));
+        assert_eq!(
+            Command::parse("b loop"),
+            Ok(Command::Break(Location::Label("loop".to_string())))
+        );
+        assert_eq!(
+            Command::parse("break 0x10"),
+            Ok(Command::Break(Location::Address(0x10)))
+        );
+        assert_eq!(Command::parse("w r3"), Ok(Command::Watch(Watch::Reg(3))));
+        assert_eq!(
+            Command::parse("watch carry"),
+            Ok(Command::Watch(Watch::Carry))
+        );
+        assert_eq!(Command::parse("limit 10"), Ok(Command::Limit(Some(10))));
+        assert_eq!(Command::parse("limit off"), Ok(Command::Limit(None)));
+        assert!(Command::parse("limit").is_err());
+        assert!(Command::parse("w r16").is_err());
+        assert!(Command::parse("b").is_err());
+        assert!(Command::parse("step 1 2").is_err());
+        assert!(Command::parse("jump").is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut dbg = Debugger::new("prog.asm", "  addi r1, r0, 1\n  jmp 42\n").unwrap();
+        let mut out = Vec::new();
+        dbg.execute(Command::Continue, &mut out).unwrap();
+
+        // Stops on the offending instruction, and points at it
+        assert_eq!(dbg.state().pc, 1);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("out-of-bounds address 42"));
+        assert!(out.contains(" --> prog.asm:2:3\n  |\n2 |   jmp 42\n  |   ^^^^^^"));
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let mut dbg = Debugger::new("prog.asm", "loop: jmp loop\n").unwrap();
+        let mut out = Vec::new();
+        dbg.execute(Command::Limit(Some(100)), &mut out).unwrap();
+        dbg.execute(Command::Continue, &mut out).unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("Step limit exceeded at address 0 after 100 steps"));
+
+        // The program may still be continued
+        let mut out = Vec::new();
+        dbg.execute(Command::Continue, &mut out).unwrap();
+        assert!(
+            String::from_utf8(out)
+                .unwrap()
+                .contains("Step limit exceeded")
+        );
+
+        // The default limit stops it too
+        let mut dbg = Debugger::new("prog.asm", "loop: jmp loop\n").unwrap();
+        let mut out = Vec::new();
+        dbg.execute(Command::Continue, &mut out).unwrap();
+        assert!(
+            String::from_utf8(out)
+                .unwrap()
+                .contains(&format!("after {} steps", DEFAULT_MAX_STEPS))
+        );
+    }
+
+    #[test]
+    fn test_layout() {
+        // Only ROM regions hold code
+        let layout = Layout::parse("rom rom 0 2\nram ram 0 0x100\n").unwrap();
+        let compiled = compile("prog.asm", "  nop\n  jmp 2\n  halt\n").unwrap();
+        let mut dbg = Debugger::with_layout(compiled, &layout);
+        let mut out = Vec::new();
+        dbg.execute(Command::Continue, &mut out).unwrap();
+
+        assert_eq!(dbg.state().pc, 1);
+        assert!(
+            String::from_utf8(out)
+                .unwrap()
+                .contains("out-of-bounds address 2")
+        );
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut dbg = Debugger::new("prog.asm", SRC).unwrap();
+        let mut out = Vec::new();
+
+        dbg.execute(
+            Command::Break(Location::Label("loop".to_string())),
+            &mut out,
+        )
+        .unwrap();
+        dbg.execute(Command::Continue, &mut out).unwrap();
+        assert_eq!(dbg.state().pc, 1);
+
+        // Continuing from a breakpoint runs one loop iteration
+        dbg.execute(Command::Continue, &mut out).unwrap();
+        assert_eq!(dbg.state().pc, 1);
+        assert_eq!(dbg.state().regs.r(1).unwrap(), 2);
+
+        // Without breakpoints, run to completion
+        dbg.execute(Command::Delete(Location::Address(1)), &mut out)
+            .unwrap();
+        dbg.execute(Command::Continue, &mut out).unwrap();
+        assert_eq!(dbg.state().regs.r(2).unwrap(), 6);
+        assert!(String::from_utf8(out).unwrap().contains("Halted"));
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut dbg = Debugger::new("prog.asm", SRC).unwrap();
+        let mut out = Vec::new();
+
+        dbg.execute(Command::Watch(Watch::Reg(2)), &mut out)
+            .unwrap();
+        dbg.execute(Command::Continue, &mut out).unwrap();
+        assert_eq!(dbg.state().pc, 2);
+        assert_eq!(dbg.state().regs.r(2).unwrap(), 2);
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("r2: 0x00 -> 0x02"));
+        // Current source line is listed
+        assert!(out.contains("=>    5 |   addi r1, r1, 0xff"));
+    }
+
+    #[test]
+    fn test_repl() {
+        let mut dbg = Debugger::new("prog.asm", SRC).unwrap();
+        let mut out = Vec::new();
+        let input = "step 2\nbogus\nreset\nb 9\ninfo\nquit\nstep\n";
+        dbg.repl(input.as_bytes(), &mut out).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("unknown command: bogus"));
+        assert!(out.contains("Program reset"));
+        assert!(out.contains("Breakpoint set at 0x009"));
+        // Quit stops reading further commands
+        assert_eq!(dbg.state().pc, 0);
+    }
+}
@@ -1,31 +1,73 @@
 pub mod state;
 pub mod vm;
 
-use crate::{compiler::ast::*, interpreter::state::State};
+use std::{
+    borrow::Cow,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    assembler::{binary::Binary, decoder::decode},
+    compiler::ast::*,
+    interpreter::state::{MemoryError, State},
+};
 use vm::*;
 
-/// Interprets a given program, then returns a tuple of
-/// the encountered error (if any) + the final state of
-/// the machine.
-pub fn interpret_program(
-    prg: Program,
-    initial_state: Option<State>,
+/// Number of steps between wall-clock budget checks
+pub(crate) const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
+
+/// Execution limits for the interpreter (unlimited by default)
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Limits {
+    /// Maximum number of instructions to execute
+    pub max_steps: Option<u64>,
+    /// Wall-clock budget for the whole run
+    pub timeout: Option<Duration>,
+}
+
+/// Runs the fetch-execute loop until halted or an error occurs,
+/// fetching instructions through the given closure.
+fn execute<'a>(
+    mut fetch: impl FnMut(u16) -> Result<Cow<'a, Instr>, InterpreterError>,
+    mut state: State,
+    limits: Limits,
 ) -> (Result<(), InterpreterError>, State) {
-    // // Use given initial state, or default
-    let mut state = initial_state.unwrap_or_default();
+    let start = Instant::now();
+    let mut steps = 0u64;
+    let mut last_pc = None;
 
     let status = loop {
-        // Get instruction at given PC
-        let instr = match prg.get(state.pc as usize) {
-            Some(i) => i,
-            None => {
-                // PC points to out-of-bounds instruction
-                break Some(InterpreterError::PCOutOfBounds(state.pc));
+        // Enforce limits before executing another instruction
+        if limits.max_steps.is_some_and(|max| steps >= max) {
+            break Some(InterpreterError::StepLimitExceeded(state.pc, steps));
+        }
+        if steps.is_multiple_of(TIMEOUT_CHECK_INTERVAL)
+            && limits.timeout.is_some_and(|t| start.elapsed() >= t)
+        {
+            break Some(InterpreterError::TimeLimitExceeded(state.pc, steps));
+        }
+        steps += 1;
+
+        // Get instruction at given PC, if it lies in code memory
+        let fetched = match state.is_code(state.pc) {
+            true => fetch(state.pc),
+            false => Err(InterpreterError::PCOutOfBounds(state.pc)),
+        };
+        let instr = match fetched {
+            Ok(i) => i,
+            Err(err) => {
+                // Running off the program is the fault of the
+                // instruction that got us there
+                if let (InterpreterError::PCOutOfBounds(_), Some(pc)) = (&err, last_pc) {
+                    state.pc = pc;
+                }
+                break Some(err);
             }
         };
+        last_pc = Some(state.pc);
 
         // Interpret instruction
-        match interpret(instr, &mut state) {
+        match interpret(&instr, &mut state) {
             Ok(new_pc) => {
                 // Set new PC
                 match new_pc {
@@ -49,6 +91,80 @@ pub fn interpret_program(
     )
 }
 
+/// Interprets a given program, then returns a tuple of
+/// the encountered error (if any) + the final state of
+/// the machine.
+///
+/// On error, the PC of the returned state points at the
+/// offending instruction.
+pub fn interpret_program(
+    prg: Program,
+    initial_state: Option<State>,
+) -> (Result<(), InterpreterError>, State) {
+    interpret_program_with_limits(prg, initial_state, Limits::default())
+}
+
+/// Like [`interpret_program`], but stops with an error once
+/// any of the given execution limits is exceeded.
+pub fn interpret_program_with_limits(
+    prg: Program,
+    initial_state: Option<State>,
+    limits: Limits,
+) -> (Result<(), InterpreterError>, State) {
+    // Use given initial state, or default
+    let state = initial_state.unwrap_or_default();
+
+    let fetch = |pc: u16| match prg.get(pc as usize) {
+        Some(i) => Ok(Cow::Borrowed(i)),
+        // PC points to out-of-bounds instruction
+        None => Err(InterpreterError::PCOutOfBounds(pc)),
+    };
+    execute(fetch, state, limits)
+}
+
+/// Interprets a given binary by fetching and decoding its machine code
+/// words, then returns a tuple of the encountered error (if any) + the
+/// final state of the machine.
+///
+/// Without an initial state, execution starts at the binary's entry point,
+/// with the binary's data loaded into memory.
+pub fn interpret_binary(
+    bin: &Binary,
+    initial_state: Option<State>,
+) -> (Result<(), InterpreterError>, State) {
+    interpret_binary_with_limits(bin, initial_state, Limits::default())
+}
+
+/// Like [`interpret_binary`], but stops with an error once
+/// any of the given execution limits is exceeded.
+pub fn interpret_binary_with_limits(
+    bin: &Binary,
+    initial_state: Option<State>,
+    limits: Limits,
+) -> (Result<(), InterpreterError>, State) {
+    let state = match initial_state {
+        Some(state) => state,
+        None => match State::with_data(&bin.data) {
+            Ok(state) => State {
+                pc: bin.entry,
+                ..state
+            },
+            Err(MemoryError::OutOfBounds(addr)) => {
+                return (Err(InterpreterError::StoreOutOfBounds(addr)), State::new());
+            }
+        },
+    };
+
+    let fetch = |pc: u16| match bin.code.get(pc as usize) {
+        Some(word) => decode(*word)
+            .map(Cow::Owned)
+            .map_err(|e| InterpreterError::IllegalInstruction(pc, e)),
+        // PC points to out-of-bounds instruction
+        None => Err(InterpreterError::PCOutOfBounds(pc)),
+    };
+    execute(fetch, state, limits)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -85,4 +201,98 @@ mod tests {
         let (status, _state) = interpret_program(prg, None);
         assert!(status.is_err());
     }
+
+    #[test]
+    fn test_limits() {
+        // loop: jmp loop
+        let prg = vec![Instr::Jmp { imm: Op::Imm12(0) }];
+
+        let limits = Limits {
+            max_steps: Some(100),
+            ..Default::default()
+        };
+        let (status, state) = interpret_program_with_limits(prg.clone(), None, limits);
+        assert!(matches!(
+            status,
+            Err(InterpreterError::StepLimitExceeded(0, 100))
+        ));
+        assert_eq!(state.pc, 0);
+
+        let limits = Limits {
+            timeout: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+        let (status, _state) = interpret_program_with_limits(prg, None, limits);
+        assert!(matches!(
+            status,
+            Err(InterpreterError::TimeLimitExceeded(0, _))
+        ));
+
+        // Halting within the limit is fine
+        let limits = Limits {
+            max_steps: Some(1),
+            ..Default::default()
+        };
+        let bin = Binary::new(vec![0x000000]);
+        let (status, _state) = interpret_binary_with_limits(&bin, None, limits);
+        assert!(status.is_ok());
+    }
+
+    #[test]
+    fn test_code_map() {
+        use crate::assembler::layout::Layout;
+
+        // Jumping into the padding before a ROM region faults, rather than halting
+        let layout = Layout::parse("rom flash 0x10 0x10\nram ram 0 0x100\n").unwrap();
+        let mut prg = vec![Instr::Halt; 0x10];
+        prg.push(Instr::Jmp { imm: Op::Imm12(4) });
+        let state = State {
+            pc: 0x10,
+            ..State::with_layout(&layout)
+        };
+        let (status, state) = interpret_program(prg.clone(), Some(state));
+        assert!(matches!(status, Err(InterpreterError::PCOutOfBounds(4))));
+        assert_eq!(state.pc, 0x10);
+
+        // Without a layout, all of program memory holds code
+        let (status, _state) = interpret_program(prg, None);
+        assert!(status.is_ok());
+    }
+
+    #[test]
+    fn test_interpret_binary() {
+        // 2 + 2, entering past a leading halt
+        let bin = Binary {
+            entry: 1,
+            ..Binary::new(vec![0x000000, 0x020101, 0x020201, 0x021302, 0x000000])
+        };
+        let (status, state) = interpret_binary(&bin, None);
+        assert!(status.is_ok());
+        assert_eq!(state.regs.r(3).unwrap(), 4);
+        assert_eq!(state.pc, 4);
+
+        // Illegal opcode
+        let bin = Binary::new(vec![0x00003F]);
+        let (status, _state) = interpret_binary(&bin, None);
+        assert!(matches!(
+            status,
+            Err(InterpreterError::IllegalInstruction(0, _))
+        ));
+
+        // Running off the end of the code section
+        let bin = Binary::new(vec![0x000001]);
+        let (status, state) = interpret_binary(&bin, None);
+        assert!(matches!(status, Err(InterpreterError::PCOutOfBounds(1))));
+        assert_eq!(state.pc, 0);
+
+        // Data is loaded before execution
+        let bin = Binary {
+            data: vec![0, 7],
+            ..Binary::new(vec![0x010106, 0x000000])
+        };
+        let (status, state) = interpret_binary(&bin, None);
+        assert!(status.is_ok());
+        assert_eq!(state.regs.r(1).unwrap(), 7);
+        assert_eq!(state.mem.r(1).unwrap(), 7);
+    }
 }
@@ -1,8 +1,16 @@
-use std::fmt::{self, Error};
+use std::{
+    fmt::{self, Error},
+    ops::Range,
+};
 
 use console::style;
 use thiserror::Error;
 
+use crate::assembler::{
+    binary::MAX_WORDS,
+    layout::{Layout, RegionKind},
+};
+
 #[derive(Debug, Error)]
 pub enum RegisterError {
     #[error("No such register: {0}")]
@@ -57,51 +65,174 @@ impl fmt::Display for Registers {
     }
 }
 
-/// Collection of ALU flags
-pub struct Flags {
-    pub zero: bool,
-    pub overflow: bool,
+#[derive(Debug, Error)]
+pub enum MemoryError {
+    #[error("Address out of bounds: {0}")]
+    OutOfBounds(u16),
 }
 
-impl Default for Flags {
+/// Size of the data memory, in bytes.
+///
+/// Addresses are formed as `rs1 + imm` from 8-bit values, reaching up to
+/// `0xff + 0xff`, so memory covers every address a load or store can form.
+pub const MEMORY_SIZE: usize = 0x200;
+
+/// Initial stack pointer; the stack grows down from the top of memory
+pub const STACK_TOP: u16 = MEMORY_SIZE as u16;
+
+/// Lowest address the stack may grow into
+pub const STACK_LIMIT: u16 = 0x1C0;
+
+/// Byte-addressable data memory
+pub struct Memory([u8; MEMORY_SIZE]);
+
+impl Default for Memory {
     fn default() -> Self {
-        (true, false).into()
+        Self([0; MEMORY_SIZE])
     }
 }
 
-impl fmt::Display for Flags {
+impl Memory {
+    /// Read a byte from memory.
+    /// Returns `None` if address out of bounds
+    #[inline]
+    pub fn r(&self, addr: u16) -> Option<u8> {
+        self.0.get(addr as usize).copied()
+    }
+
+    /// Write a byte to memory.
+    /// Returns `Err` if address out of bounds
+    #[inline]
+    pub fn w(&mut self, addr: u16, v: u8) -> Result<(), MemoryError> {
+        match self.0.get_mut(addr as usize) {
+            Some(b) => {
+                *b = v;
+                Ok(())
+            }
+            None => Err(MemoryError::OutOfBounds(addr)),
+        }
+    }
+
+    /// Write a run of bytes to memory, starting at a given address.
+    /// Returns `Err` with the first address out of bounds, if any
+    pub fn load(&mut self, addr: u16, bytes: &[u8]) -> Result<(), MemoryError> {
+        let start = addr as usize;
+        match self.0.get_mut(start..start + bytes.len()) {
+            Some(dst) => {
+                dst.copy_from_slice(bytes);
+                Ok(())
+            }
+            None => Err(MemoryError::OutOfBounds(addr.max(MEMORY_SIZE as u16))),
+        }
+    }
+}
+
+impl fmt::Display for Memory {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "zero: {}, overflow: {}", self.zero, self.overflow)
+        // Only rows holding non-zero bytes are shown
+        for (i, row) in self.0.chunks(16).enumerate() {
+            if row.iter().all(|b| *b == 0) {
+                continue;
+            }
+            write!(f, "{}:", style(format!("0x{:03x}", i * 16)).bold())?;
+            for b in row {
+                write!(f, " {:02x}", b)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
     }
 }
 
-impl From<(bool, bool)> for Flags {
+/// Collection of ALU flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flags {
+    /// Result is zero
+    pub zero: bool,
+    /// Result has its sign bit (bit 7) set
+    pub negative: bool,
+    /// Unsigned carry out of an addition, or borrow out of a subtraction
+    pub carry: bool,
+    /// Signed (two's complement) overflow
+    pub overflow: bool,
+}
+
+impl Flags {
+    /// Flags for a given result, with carry and overflow cleared
     #[inline]
-    fn from(value: (bool, bool)) -> Self {
+    pub fn from_result(res: u8) -> Self {
         Self {
-            zero: value.0,
-            overflow: value.1,
+            zero: res == 0,
+            negative: res & 0x80 != 0,
+            carry: false,
+            overflow: false,
         }
     }
 }
 
+impl Default for Flags {
+    fn default() -> Self {
+        Self::from_result(0)
+    }
+}
+
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "zero: {}, negative: {}, carry: {}, overflow: {}",
+            self.zero, self.negative, self.carry, self.overflow
+        )
+    }
+}
+
 /// Virtual machine state
-#[derive(Default)]
 pub struct State {
     /// Program counter
     pub pc: u16,
+    /// Stack pointer (address of the top stack entry)
+    pub sp: u16,
     /// Registers
     pub regs: Registers,
     /// ALU Flags
     pub flags: Flags,
+    /// Data memory
+    pub mem: Memory,
+    /// Addresses the stack may grow down through, from the end
+    pub stack: Range<u16>,
+    /// Ranges of data memory that may be loaded from and stored to
+    pub mapped: Vec<Range<u16>>,
+    /// Ranges of program memory instructions may be fetched from
+    pub code: Vec<Range<u16>>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            pc: 0,
+            sp: STACK_TOP,
+            regs: Registers::default(),
+            flags: Flags::default(),
+            mem: Memory::default(),
+            stack: STACK_LIMIT..STACK_TOP,
+            mapped: vec![0..STACK_LIMIT, STACK_LIMIT..STACK_TOP],
+            code: vec![Range {
+                start: 0,
+                end: MAX_WORDS as u16,
+            }],
+        }
+    }
 }
 
 impl fmt::Display for State {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "PC:    0x{:04x}", self.pc)?;
+        writeln!(f, "SP:    0x{:04x}", self.sp)?;
         writeln!(f, "Flags: {}", self.flags)?;
         writeln!(f, "Registers:")?;
-        write!(f, "{}", self.regs)
+        write!(f, "{}", self.regs)?;
+        writeln!(f, "Memory:")?;
+        write!(f, "{}", self.mem)
     }
 }
 
@@ -109,4 +240,49 @@ impl State {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Creates a state with the given initial contents of data memory.
+    pub fn with_data(data: &[u8]) -> Result<Self, MemoryError> {
+        let mut state = Self::default();
+        state.mem.load(0, data)?;
+        Ok(state)
+    }
+
+    /// Creates a state with the memory map of a given layout, where only
+    /// ROM regions hold code and only the regions of the data space are
+    /// mapped, and the stack starts at the top of the stack region (if any).
+    pub fn with_layout(layout: &Layout) -> Self {
+        let range = |start: u16, end: usize| start..end as u16;
+        let stack = match layout.stack() {
+            Some(r) => range(r.start, r.end()),
+            None => 0..0,
+        };
+        Self {
+            sp: stack.end,
+            stack,
+            mapped: layout
+                .data_regions()
+                .map(|r| range(r.start, r.end()))
+                .collect(),
+            code: layout
+                .regions
+                .iter()
+                .filter(|r| r.kind == RegionKind::Rom)
+                .map(|r| range(r.start, r.end()))
+                .collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Whether a given address of data memory is mapped
+    #[inline]
+    pub fn is_mapped(&self, addr: u16) -> bool {
+        self.mapped.iter().any(|r| r.contains(&addr))
+    }
+
+    /// Whether a given address of program memory holds code
+    #[inline]
+    pub fn is_code(&self, pc: u16) -> bool {
+        self.code.iter().any(|r| r.contains(&pc))
+    }
 }
@@ -1,8 +1,9 @@
 use thiserror::Error;
 
 use crate::{
+    assembler::decoder::DecodeError,
     compiler::ast::*,
-    interpreter::state::{Registers, State},
+    interpreter::state::{Flags, Registers, State},
 };
 
 #[derive(Debug, Error)]
@@ -18,26 +19,157 @@ pub enum InterpreterError {
 
     #[error("Attempt to interpret out-of-bounds address {0}")]
     PCOutOfBounds(u16),
+
+    #[error("Attempt to load from out-of-bounds address {0}")]
+    LoadOutOfBounds(u16),
+
+    #[error("Attempt to store to out-of-bounds address {0}")]
+    StoreOutOfBounds(u16),
+
+    #[error("Stack overflow at stack pointer {0}")]
+    StackOverflow(u16),
+
+    #[error("Stack underflow at stack pointer {0}")]
+    StackUnderflow(u16),
+
+    #[error("Step limit exceeded at address {0} after {1} steps")]
+    StepLimitExceeded(u16, u64),
+
+    #[error("Time limit exceeded at address {0} after {1} steps")]
+    TimeLimitExceeded(u16, u64),
+
+    #[error("Illegal instruction at address {0}: {1}")]
+    IllegalInstruction(u16, DecodeError),
+}
+
+/// Does a wrapping add, with flags for the result
+#[inline]
+fn inbounds_add(a: u8, b: u8) -> (u8, Flags) {
+    let (res, carry) = a.overflowing_add(b);
+    let overflow = (a as i8).checked_add(b as i8).is_none();
+    (
+        res,
+        Flags {
+            carry,
+            overflow,
+            ..Flags::from_result(res)
+        },
+    )
+}
+
+/// Does a wrapping sub, with flags for the result (carry set on borrow)
+#[inline]
+fn inbounds_sub(a: u8, b: u8) -> (u8, Flags) {
+    let (res, carry) = a.overflowing_sub(b);
+    let overflow = (a as i8).checked_sub(b as i8).is_none();
+    (
+        res,
+        Flags {
+            carry,
+            overflow,
+            ..Flags::from_result(res)
+        },
+    )
+}
+
+/// Shift and rotate operations
+#[derive(Clone, Copy)]
+enum Shift {
+    Left,
+    Right,
+    Arithmetic,
+    RotateLeft,
+    RotateRight,
+}
+
+/// Does a shift or rotate by `n` bits, with flags for the result.
+/// Carry holds the last bit shifted (or rotated) out, and is cleared if
+/// nothing was shifted; overflow is always cleared.
+#[inline]
+fn shift(kind: Shift, a: u8, n: u8) -> (u8, Flags) {
+    // Bit `i` of `a`, or the fill bit if past the edge
+    let bit = |i: u8, fill: bool| if i < 8 { a & (1 << i) != 0 } else { fill };
+    let sign = a & 0x80 != 0;
+
+    let (res, carry) = match (kind, n) {
+        (_, 0) => (a, false),
+        (Shift::Left, n) => (
+            a.checked_shl(n as u32).unwrap_or(0),
+            n <= 8 && bit(8 - n, false),
+        ),
+        (Shift::Right, n) => (a.checked_shr(n as u32).unwrap_or(0), bit(n - 1, false)),
+        (Shift::Arithmetic, n) => {
+            let res = (a as i8)
+                .checked_shr(n as u32)
+                .unwrap_or(if sign { -1 } else { 0 });
+            (res as u8, bit(n - 1, sign))
+        }
+        (Shift::RotateLeft, n) => {
+            let res = a.rotate_left(n as u32);
+            (res, res & 0x01 != 0)
+        }
+        (Shift::RotateRight, n) => {
+            let res = a.rotate_right(n as u32);
+            (res, res & 0x80 != 0)
+        }
+    };
+
+    (
+        res,
+        Flags {
+            carry,
+            ..Flags::from_result(res)
+        },
+    )
 }
 
-/// Does a wrapping add, with bool set if overflowed
+/// Pushes a byte onto the stack
 #[inline]
-fn inbounds_add(a: u8, b: u8) -> (u8, bool) {
-    match a.checked_add(b) {
-        Some(s) => (s, false),
-        None => (a.wrapping_add(b), true),
+fn push(state: &mut State, v: u8) -> Result<(), InterpreterError> {
+    if state.sp <= state.stack.start {
+        return Err(InterpreterError::StackOverflow(state.sp));
     }
+    state.sp -= 1;
+    state
+        .mem
+        .w(state.sp, v)
+        .map_err(|_| InterpreterError::StackOverflow(state.sp))
 }
 
-/// Does a wrapping sub, with bool set if overflowed
+/// Fails unless a given number of bytes may be pushed onto the stack,
+/// so as to push several at once or none at all
 #[inline]
-fn inbounds_sub(a: u8, b: u8) -> (u8, bool) {
-    match a.checked_sub(b) {
-        Some(s) => (s, false),
-        None => (a.wrapping_sub(b), true),
+fn reserve(state: &State, n: u16) -> Result<(), InterpreterError> {
+    match state.sp.saturating_sub(state.stack.start) < n {
+        true => Err(InterpreterError::StackOverflow(state.sp)),
+        false => Ok(()),
     }
 }
 
+/// Fails unless a given number of bytes may be popped off the stack,
+/// so as to pop several at once or none at all
+#[inline]
+fn holds(state: &State, n: u16) -> Result<(), InterpreterError> {
+    match state.stack.end.saturating_sub(state.sp) < n {
+        true => Err(InterpreterError::StackUnderflow(state.sp)),
+        false => Ok(()),
+    }
+}
+
+/// Pops a byte off the stack
+#[inline]
+fn pop(state: &mut State) -> Result<u8, InterpreterError> {
+    if state.sp >= state.stack.end {
+        return Err(InterpreterError::StackUnderflow(state.sp));
+    }
+    let v = state
+        .mem
+        .r(state.sp)
+        .ok_or(InterpreterError::StackUnderflow(state.sp))?;
+    state.sp += 1;
+    Ok(v)
+}
+
 /// Extension trait for error-mapped register interaction
 pub trait RegisterAccess {
     fn read_err(&self, reg: u8) -> Result<u8, InterpreterError>;
@@ -62,7 +194,7 @@ impl RegisterAccess for Registers {
 pub fn interpret(instr: &Instr, state: &mut State) -> Result<Option<u16>, InterpreterError> {
     match instr {
         Instr::Halt => {
-            state.flags = (true, false).into();
+            state.flags = Flags::from_result(0);
             Ok(None)
         }
         Instr::Addi {
@@ -71,9 +203,9 @@ pub fn interpret(instr: &Instr, state: &mut State) -> Result<Option<u16>, Interp
             imm: Op::Imm8(imm),
         } => {
             let a = state.regs.read_err(*rs1)?;
-            let (res, overflow) = inbounds_add(a, *imm);
+            let (res, flags) = inbounds_add(a, *imm);
             state.regs.write_err(*rd, res)?;
-            state.flags = (res.eq(&0u8), overflow).into();
+            state.flags = flags;
             Ok(Some(state.pc + 1))
         }
         Instr::Mv {
@@ -82,11 +214,11 @@ pub fn interpret(instr: &Instr, state: &mut State) -> Result<Option<u16>, Interp
         } => {
             let a = state.regs.read_err(*rs1)?;
             state.regs.write_err(*rd, a)?;
-            state.flags = (a.eq(&0u8), false).into();
+            state.flags = Flags::from_result(a);
             Ok(Some(state.pc + 1))
         }
         Instr::Nop => {
-            state.flags = (true, false).into();
+            state.flags = Flags::from_result(0);
             Ok(Some(state.pc + 1))
         }
         Instr::Add {
@@ -96,9 +228,9 @@ pub fn interpret(instr: &Instr, state: &mut State) -> Result<Option<u16>, Interp
         } => {
             let a = state.regs.read_err(*rs1)?;
             let b = state.regs.read_err(*rs2)?;
-            let (res, overflow) = inbounds_add(a, b);
+            let (res, flags) = inbounds_add(a, b);
             state.regs.write_err(*rd, res)?;
-            state.flags = (res.eq(&0u8), overflow).into();
+            state.flags = flags;
             Ok(Some(state.pc + 1))
         }
         Instr::Sub {
@@ -108,9 +240,9 @@ pub fn interpret(instr: &Instr, state: &mut State) -> Result<Option<u16>, Interp
         } => {
             let a = state.regs.read_err(*rs1)?;
             let b = state.regs.read_err(*rs2)?;
-            let (res, overflow) = inbounds_sub(a, b);
+            let (res, flags) = inbounds_sub(a, b);
             state.regs.write_err(*rd, res)?;
-            state.flags = (res.eq(&0u8), overflow).into();
+            state.flags = flags;
             Ok(Some(state.pc + 1))
         }
         Instr::Not {
@@ -120,33 +252,266 @@ pub fn interpret(instr: &Instr, state: &mut State) -> Result<Option<u16>, Interp
             let a = state.regs.read_err(*rs1)?;
             let res = !a;
             state.regs.write_err(*rd, res)?;
-            state.flags = (res.eq(&0u8), false).into();
+            state.flags = Flags::from_result(res);
+            Ok(Some(state.pc + 1))
+        }
+        Instr::And {
+            rd: Op::Reg(rd),
+            rs1: Op::Reg(rs1),
+            rs2: Op::Reg(rs2),
+        }
+        | Instr::Or {
+            rd: Op::Reg(rd),
+            rs1: Op::Reg(rs1),
+            rs2: Op::Reg(rs2),
+        }
+        | Instr::Xor {
+            rd: Op::Reg(rd),
+            rs1: Op::Reg(rs1),
+            rs2: Op::Reg(rs2),
+        } => {
+            let a = state.regs.read_err(*rs1)?;
+            let b = state.regs.read_err(*rs2)?;
+            let res = match instr {
+                Instr::And { .. } => a & b,
+                Instr::Or { .. } => a | b,
+                _ => a ^ b,
+            };
+            state.regs.write_err(*rd, res)?;
+            state.flags = Flags::from_result(res);
+            Ok(Some(state.pc + 1))
+        }
+        Instr::Andi {
+            rd: Op::Reg(rd),
+            rs1: Op::Reg(rs1),
+            imm: Op::Imm8(imm),
+        }
+        | Instr::Ori {
+            rd: Op::Reg(rd),
+            rs1: Op::Reg(rs1),
+            imm: Op::Imm8(imm),
+        }
+        | Instr::Xori {
+            rd: Op::Reg(rd),
+            rs1: Op::Reg(rs1),
+            imm: Op::Imm8(imm),
+        } => {
+            let a = state.regs.read_err(*rs1)?;
+            let res = match instr {
+                Instr::Andi { .. } => a & imm,
+                Instr::Ori { .. } => a | imm,
+                _ => a ^ imm,
+            };
+            state.regs.write_err(*rd, res)?;
+            state.flags = Flags::from_result(res);
+            Ok(Some(state.pc + 1))
+        }
+        Instr::Shl {
+            rd: Op::Reg(rd),
+            rs1: Op::Reg(rs1),
+            rs2: Op::Reg(rs2),
+        }
+        | Instr::Shr {
+            rd: Op::Reg(rd),
+            rs1: Op::Reg(rs1),
+            rs2: Op::Reg(rs2),
+        }
+        | Instr::Sar {
+            rd: Op::Reg(rd),
+            rs1: Op::Reg(rs1),
+            rs2: Op::Reg(rs2),
+        }
+        | Instr::Rol {
+            rd: Op::Reg(rd),
+            rs1: Op::Reg(rs1),
+            rs2: Op::Reg(rs2),
+        }
+        | Instr::Ror {
+            rd: Op::Reg(rd),
+            rs1: Op::Reg(rs1),
+            rs2: Op::Reg(rs2),
+        } => {
+            let a = state.regs.read_err(*rs1)?;
+            let n = state.regs.read_err(*rs2)?;
+            let kind = match instr {
+                Instr::Shl { .. } => Shift::Left,
+                Instr::Shr { .. } => Shift::Right,
+                Instr::Sar { .. } => Shift::Arithmetic,
+                Instr::Rol { .. } => Shift::RotateLeft,
+                _ => Shift::RotateRight,
+            };
+            let (res, flags) = shift(kind, a, n);
+            state.regs.write_err(*rd, res)?;
+            state.flags = flags;
             Ok(Some(state.pc + 1))
         }
+        Instr::Shli {
+            rd: Op::Reg(rd),
+            rs1: Op::Reg(rs1),
+            imm: Op::Imm8(imm),
+        }
+        | Instr::Shri {
+            rd: Op::Reg(rd),
+            rs1: Op::Reg(rs1),
+            imm: Op::Imm8(imm),
+        }
+        | Instr::Sari {
+            rd: Op::Reg(rd),
+            rs1: Op::Reg(rs1),
+            imm: Op::Imm8(imm),
+        }
+        | Instr::Roli {
+            rd: Op::Reg(rd),
+            rs1: Op::Reg(rs1),
+            imm: Op::Imm8(imm),
+        }
+        | Instr::Rori {
+            rd: Op::Reg(rd),
+            rs1: Op::Reg(rs1),
+            imm: Op::Imm8(imm),
+        } => {
+            let a = state.regs.read_err(*rs1)?;
+            let kind = match instr {
+                Instr::Shli { .. } => Shift::Left,
+                Instr::Shri { .. } => Shift::Right,
+                Instr::Sari { .. } => Shift::Arithmetic,
+                Instr::Roli { .. } => Shift::RotateLeft,
+                _ => Shift::RotateRight,
+            };
+            let (res, flags) = shift(kind, a, *imm);
+            state.regs.write_err(*rd, res)?;
+            state.flags = flags;
+            Ok(Some(state.pc + 1))
+        }
+        Instr::Cmp {
+            rs1: Op::Reg(rs1),
+            rs2: Op::Reg(rs2),
+        } => {
+            // Flags as for sub, with the result discarded
+            let a = state.regs.read_err(*rs1)?;
+            let b = state.regs.read_err(*rs2)?;
+            let (_, flags) = inbounds_sub(a, b);
+            state.flags = flags;
+            Ok(Some(state.pc + 1))
+        }
+        Instr::Lb {
+            rd: Op::Reg(rd),
+            rs1: Op::Reg(rs1),
+            imm: Op::Imm8(imm),
+        } => {
+            let addr = state.regs.read_err(*rs1)? as u16 + *imm as u16;
+            let v = state
+                .mem
+                .r(addr)
+                .filter(|_| state.is_mapped(addr))
+                .ok_or(InterpreterError::LoadOutOfBounds(addr))?;
+            state.regs.write_err(*rd, v)?;
+            state.flags = Flags::from_result(v);
+            Ok(Some(state.pc + 1))
+        }
+        Instr::Sb {
+            rs2: Op::Reg(rs2),
+            rs1: Op::Reg(rs1),
+            imm: Op::Imm8(imm),
+        } => {
+            // Stores leave flags untouched
+            let addr = state.regs.read_err(*rs1)? as u16 + *imm as u16;
+            let v = state.regs.read_err(*rs2)?;
+            if !state.is_mapped(addr) {
+                return Err(InterpreterError::StoreOutOfBounds(addr));
+            }
+            state
+                .mem
+                .w(addr, v)
+                .map_err(|_| InterpreterError::StoreOutOfBounds(addr))?;
+            Ok(Some(state.pc + 1))
+        }
+        Instr::Push { rs: Op::Reg(rs) } => {
+            // Pushes leave flags untouched
+            let v = state.regs.read_err(*rs)?;
+            push(state, v)?;
+            Ok(Some(state.pc + 1))
+        }
+        Instr::Pop { rd: Op::Reg(rd) } => {
+            let v = pop(state)?;
+            state.regs.write_err(*rd, v)?;
+            state.flags = Flags::from_result(v);
+            Ok(Some(state.pc + 1))
+        }
+        Instr::Call {
+            imm: Op::Imm12(imm),
+        } => {
+            // Return address is pushed high byte first
+            let [lo, hi] = (state.pc + 1).to_le_bytes();
+            reserve(state, 2)?;
+            push(state, hi)?;
+            push(state, lo)?;
+            // Flags are the same as for jmp
+            state.flags = Flags::from_result(0);
+            Ok(Some(*imm))
+        }
+        Instr::Ret => {
+            holds(state, 2)?;
+            let lo = pop(state)?;
+            let hi = pop(state)?;
+            // Flags are the same as for jmp
+            state.flags = Flags::from_result(0);
+            Ok(Some(u16::from_le_bytes([lo, hi])))
+        }
         Instr::Jmp { imm: target } => match target {
             Op::Imm12(imm) => {
                 // Flags are the same as res = 0
-                state.flags = (true, false).into();
+                state.flags = Flags::from_result(0);
                 Ok(Some(*imm))
             }
             _ => Err(InterpreterError::InvalidOperands(instr.clone())),
         },
         Instr::Bz {
             imm: Op::Imm12(imm),
-        } => {
-            if state.flags.zero {
-                Ok(Some(*imm))
-            } else {
-                Ok(Some(state.pc + 1))
-            }
         }
-        Instr::Bnz {
+        | Instr::Bnz {
+            imm: Op::Imm12(imm),
+        }
+        | Instr::Bc {
+            imm: Op::Imm12(imm),
+        }
+        | Instr::Bnc {
+            imm: Op::Imm12(imm),
+        }
+        | Instr::Bn {
+            imm: Op::Imm12(imm),
+        }
+        | Instr::Bv {
+            imm: Op::Imm12(imm),
+        }
+        | Instr::Blt {
+            imm: Op::Imm12(imm),
+        }
+        | Instr::Bge {
+            imm: Op::Imm12(imm),
+        }
+        | Instr::Bltu {
+            imm: Op::Imm12(imm),
+        }
+        | Instr::Bgeu {
             imm: Op::Imm12(imm),
         } => {
-            if state.flags.zero {
-                Ok(Some(state.pc + 1))
-            } else {
+            // Branches leave flags untouched
+            let flags = &state.flags;
+            let taken = match instr {
+                Instr::Bz { .. } => flags.zero,
+                Instr::Bnz { .. } => !flags.zero,
+                Instr::Bc { .. } | Instr::Bltu { .. } => flags.carry,
+                Instr::Bnc { .. } | Instr::Bgeu { .. } => !flags.carry,
+                Instr::Bn { .. } => flags.negative,
+                Instr::Bv { .. } => flags.overflow,
+                Instr::Blt { .. } => flags.negative != flags.overflow,
+                _ => flags.negative == flags.overflow,
+            };
+            if taken {
                 Ok(Some(*imm))
+            } else {
+                Ok(Some(state.pc + 1))
             }
         }
         _ => Err(InterpreterError::InvalidInstruction(instr.clone())),
@@ -156,6 +521,11 @@ pub fn interpret(instr: &Instr, state: &mut State) -> Result<Option<u16>, Interp
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        assembler::layout::Layout,
+        compiler::parser::parse_program,
+        interpreter::state::{STACK_LIMIT, STACK_TOP},
+    };
 
     #[test]
     fn test_interpreter() {
@@ -180,6 +550,251 @@ mod tests {
         assert_eq!(interpret(&instr, &mut state).ok().unwrap(), Some(0xf));
     }
 
+    #[test]
+    fn test_conformance() {
+        // Initially r1 = 0b1100_1010, r2 = 0b0101_0110, r3 = 0xee, all flags set
+        // (instruction, expected r3, expected flags set of "ZNCV", expected next PC)
+        let cases = [
+            ("halt", None, "Z", None),
+            ("nop", None, "Z", Some(1)),
+            ("mv r3, r1", Some(0xCA), "N", Some(1)),
+            ("mv r3, r0", Some(0x00), "Z", Some(1)),
+            ("not r3, r1", Some(0x35), "", Some(1)),
+            ("add r3, r1, r2", Some(0x20), "C", Some(1)),
+            ("add r3, r2, r2", Some(0xAC), "NV", Some(1)),
+            ("sub r3, r1, r2", Some(0x74), "V", Some(1)),
+            ("sub r3, r2, r1", Some(0x8C), "NCV", Some(1)),
+            ("sub r3, r1, r1", Some(0x00), "Z", Some(1)),
+            ("and r3, r1, r2", Some(0x42), "", Some(1)),
+            ("and r3, r1, r0", Some(0x00), "Z", Some(1)),
+            ("or r3, r1, r2", Some(0xDE), "N", Some(1)),
+            ("or r3, r0, r0", Some(0x00), "Z", Some(1)),
+            ("xor r3, r1, r2", Some(0x9C), "N", Some(1)),
+            ("xor r3, r1, r1", Some(0x00), "Z", Some(1)),
+            ("addi r3, r1, 0x36", Some(0x00), "ZC", Some(1)),
+            ("addi r3, r1, 1", Some(0xCB), "N", Some(1)),
+            ("cmp r1, r2", None, "V", Some(1)),
+            ("cmp r2, r1", None, "NCV", Some(1)),
+            ("cmp r1, r1", None, "Z", Some(1)),
+            // r4 = 3, r5 = 0
+            ("shl r3, r1, r4", Some(0x50), "", Some(1)),
+            ("shl r3, r1, r5", Some(0xCA), "N", Some(1)),
+            ("shr r3, r1, r4", Some(0x19), "", Some(1)),
+            ("sar r3, r1, r4", Some(0xF9), "N", Some(1)),
+            ("rol r3, r1, r4", Some(0x56), "", Some(1)),
+            ("ror r3, r1, r4", Some(0x59), "", Some(1)),
+            ("shli r3, r1, 1", Some(0x94), "NC", Some(1)),
+            ("shli r3, r1, 8", Some(0x00), "Z", Some(1)),
+            ("shli r3, r1, 9", Some(0x00), "Z", Some(1)),
+            ("shri r3, r1, 2", Some(0x32), "C", Some(1)),
+            ("shri r3, r1, 8", Some(0x00), "ZC", Some(1)),
+            ("shri r3, r1, 9", Some(0x00), "Z", Some(1)),
+            ("sari r3, r1, 2", Some(0xF2), "NC", Some(1)),
+            ("sari r3, r1, 9", Some(0xFF), "NC", Some(1)),
+            ("sari r3, r2, 9", Some(0x00), "Z", Some(1)),
+            ("roli r3, r1, 1", Some(0x95), "NC", Some(1)),
+            ("roli r3, r1, 8", Some(0xCA), "N", Some(1)),
+            ("rori r3, r1, 1", Some(0x65), "", Some(1)),
+            ("rori r3, r2, 1", Some(0x2B), "", Some(1)),
+            ("andi r3, r1, 0x0f", Some(0x0A), "", Some(1)),
+            ("andi r3, r1, 0x30", Some(0x00), "Z", Some(1)),
+            ("ori r3, r1, 0x0f", Some(0xCF), "N", Some(1)),
+            ("ori r3, r0, 0", Some(0x00), "Z", Some(1)),
+            ("xori r3, r1, 0xff", Some(0x35), "", Some(1)),
+            ("xori r3, r1, 0xca", Some(0x00), "Z", Some(1)),
+            // Memory holds 0x5a at 0x20, 0x00 at 0x21
+            ("lb r3, 0x20(r0)", Some(0x5A), "", Some(1)),
+            ("lb r3, 0x21(r0)", Some(0x00), "Z", Some(1)),
+            ("sb r1, 0x22(r0)", None, "ZNCV", Some(1)),
+            // Stack holds 0x00 on top
+            ("push r1", None, "ZNCV", Some(1)),
+            ("pop r3", Some(0x00), "Z", Some(1)),
+            ("call 0x123", None, "Z", Some(0x123)),
+            ("ret", None, "Z", Some(0x200)),
+            ("jmp 0x123", None, "Z", Some(0x123)),
+            // Branches leave flags untouched
+            ("bz 0x123", None, "ZNCV", Some(0x123)),
+            ("bnz 0x123", None, "ZNCV", Some(1)),
+            ("bc 0x123", None, "ZNCV", Some(0x123)),
+            ("bnc 0x123", None, "ZNCV", Some(1)),
+            ("bn 0x123", None, "ZNCV", Some(0x123)),
+            ("bv 0x123", None, "ZNCV", Some(0x123)),
+            ("blt 0x123", None, "ZNCV", Some(1)),
+            ("bge 0x123", None, "ZNCV", Some(0x123)),
+            ("bltu 0x123", None, "ZNCV", Some(0x123)),
+            ("bgeu 0x123", None, "ZNCV", Some(1)),
+        ];
+
+        for (src, value, flags, next) in cases {
+            let instr = &parse_program(src).unwrap()[0];
+            let mut state = State::new();
+            state.regs.w(1, 0b1100_1010).unwrap();
+            state.regs.w(2, 0b0101_0110).unwrap();
+            state.regs.w(3, 0xEE).unwrap();
+            state.regs.w(4, 3).unwrap();
+            state.flags = Flags {
+                zero: true,
+                negative: true,
+                carry: true,
+                overflow: true,
+            };
+            state.mem.w(0x20, 0x5A).unwrap();
+            // Return address 0x0200 on top of stack
+            state.sp = STACK_TOP - 2;
+            state.mem.w(STACK_TOP - 1, 0x02).unwrap();
+
+            let res = interpret(instr, &mut state).unwrap();
+            assert_eq!(res, next, "next PC of {}", instr);
+            assert_eq!(state.regs.r(3).unwrap(), value.unwrap_or(0xEE), "{}", instr);
+            let expected = Flags {
+                zero: flags.contains('Z'),
+                negative: flags.contains('N'),
+                carry: flags.contains('C'),
+                overflow: flags.contains('V'),
+            };
+            assert_eq!(state.flags, expected, "flags of {}", instr);
+        }
+
+        // Labels must be stripped before interpretation
+        let instr = Instr::Label("start".to_string());
+        assert!(matches!(
+            interpret(&instr, &mut State::new()),
+            Err(InterpreterError::InvalidInstruction(_))
+        ));
+    }
+
+    #[test]
+    fn test_comparisons() {
+        // (a, b, a < b signed, a < b unsigned)
+        let cases = [
+            (1u8, 2u8, true, true),
+            (2, 1, false, false),
+            (5, 5, false, false),
+            (0xFF, 1, true, false),
+            (1, 0xFF, false, true),
+            (0x80, 0x7F, true, false),
+            (0x7F, 0x80, false, true),
+        ];
+        let sub = Instr::Sub {
+            rd: Op::Reg(0),
+            rs1: Op::Reg(1),
+            rs2: Op::Reg(2),
+        };
+        let target = || Op::Imm12(0x100);
+
+        for (a, b, lt, ltu) in cases {
+            let mut state = State::new();
+            state.regs.w(1, a).unwrap();
+            state.regs.w(2, b).unwrap();
+            interpret(&sub, &mut state).unwrap();
+
+            let taken =
+                |instr: Instr, state: &mut State| interpret(&instr, state).unwrap() == Some(0x100);
+            assert_eq!(taken(Instr::Blt { imm: target() }, &mut state), lt);
+            assert_eq!(taken(Instr::Bge { imm: target() }, &mut state), !lt);
+            assert_eq!(taken(Instr::Bltu { imm: target() }, &mut state), ltu);
+            assert_eq!(taken(Instr::Bgeu { imm: target() }, &mut state), !ltu);
+        }
+    }
+
+    #[test]
+    fn test_stack() {
+        let mut state = State::new();
+        state.pc = 0x123;
+        state.regs.w(1, 0xAB).unwrap();
+
+        // Call pushes return address
+        let call = Instr::Call {
+            imm: Op::Imm12(0x456),
+        };
+        assert_eq!(interpret(&call, &mut state).unwrap(), Some(0x456));
+        assert_eq!(state.sp, STACK_TOP - 2);
+        state.pc = 0x456;
+
+        // Push/pop round trip
+        let instr = Instr::Push { rs: Op::Reg(1) };
+        interpret(&instr, &mut state).unwrap();
+        let instr = Instr::Pop { rd: Op::Reg(2) };
+        interpret(&instr, &mut state).unwrap();
+        assert_eq!(state.regs.r(2).unwrap(), 0xAB);
+
+        // Ret returns past the call
+        assert_eq!(interpret(&Instr::Ret, &mut state).unwrap(), Some(0x124));
+        assert_eq!(state.sp, STACK_TOP);
+
+        // Popping empty stack
+        assert!(matches!(
+            interpret(&Instr::Ret, &mut state),
+            Err(InterpreterError::StackUnderflow(_))
+        ));
+
+        // Calls and returns fault without moving half an address
+        state.sp = STACK_LIMIT + 1;
+        assert!(matches!(
+            interpret(&call, &mut state),
+            Err(InterpreterError::StackOverflow(sp)) if sp == STACK_LIMIT + 1
+        ));
+        assert_eq!(state.sp, STACK_LIMIT + 1);
+        assert_eq!(state.mem.r(STACK_LIMIT), Some(0));
+        state.sp = STACK_TOP - 1;
+        assert!(matches!(
+            interpret(&Instr::Ret, &mut state),
+            Err(InterpreterError::StackUnderflow(sp)) if sp == STACK_TOP - 1
+        ));
+        assert_eq!(state.sp, STACK_TOP - 1);
+        state.sp = STACK_TOP;
+
+        // Pushing full stack
+        let instr = Instr::Push { rs: Op::Reg(1) };
+        let res = (0..=STACK_TOP - STACK_LIMIT)
+            .try_for_each(|_| interpret(&instr, &mut state).map(|_| ()));
+        assert!(matches!(
+            res,
+            Err(InterpreterError::StackOverflow(STACK_LIMIT))
+        ));
+    }
+
+    #[test]
+    fn test_memory_map() {
+        let layout = Layout::parse(
+            "rom rom 0 0x1000\nram ram 0 0x40\nmmio io 0x80 0x10\nstack stack 0xf0 2\n",
+        )
+        .unwrap();
+        let mut state = State::with_layout(&layout);
+        assert_eq!(state.sp, 0xf2);
+
+        // Only regions of the layout are mapped
+        state.regs.w(1, 0x80).unwrap();
+        let store = |imm| Instr::Sb {
+            rs2: Op::Reg(1),
+            rs1: Op::Reg(1),
+            imm: Op::Imm8(imm),
+        };
+        assert!(interpret(&store(0x0f), &mut state).is_ok());
+        assert!(matches!(
+            interpret(&store(0x10), &mut state),
+            Err(InterpreterError::StoreOutOfBounds(0x90))
+        ));
+        let load = Instr::Lb {
+            rd: Op::Reg(2),
+            rs1: Op::Reg(0),
+            imm: Op::Imm8(0x40),
+        };
+        assert!(matches!(
+            interpret(&load, &mut state),
+            Err(InterpreterError::LoadOutOfBounds(0x40))
+        ));
+
+        // The stack is bounded by its region
+        let push = Instr::Push { rs: Op::Reg(1) };
+        assert!(interpret(&push, &mut state).is_ok());
+        assert!(interpret(&push, &mut state).is_ok());
+        assert!(matches!(
+            interpret(&push, &mut state),
+            Err(InterpreterError::StackOverflow(0xf0))
+        ));
+    }
+
     #[test]
     fn test_interpreter_errors() {
         // Invalid operand
@@ -189,6 +804,29 @@ mod tests {
         };
         assert!(interpret(&instr, &mut State::new()).err().is_some());
 
+        // Every address a load or store can form lies in memory,
+        // but not all of it is mapped
+        let mut state = State::new();
+        state.regs.w(1, 0xFF).unwrap();
+        let instr = Instr::Lb {
+            rd: Op::Reg(2),
+            rs1: Op::Reg(1),
+            imm: Op::Imm8(1),
+        };
+        assert!(interpret(&instr, &mut state).is_ok());
+        let instr = Instr::Sb {
+            rs2: Op::Reg(2),
+            rs1: Op::Reg(1),
+            imm: Op::Imm8(0xFF),
+        };
+        assert!(interpret(&instr, &mut state).is_ok());
+        assert!(state.is_mapped(0x1FE));
+        state.mapped.retain(|r| r.end <= STACK_LIMIT);
+        assert!(matches!(
+            interpret(&instr, &mut state),
+            Err(InterpreterError::StoreOutOfBounds(0x1FE))
+        ));
+
         // Invalid register
         let instr = Instr::Addi {
             rd: Op::Reg(0),
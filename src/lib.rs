@@ -1,5 +1,6 @@
 pub mod assembler;
 pub mod compiler;
+pub mod debugger;
 pub mod interpreter;
 
 #[cfg(test)]
@@ -16,7 +17,7 @@ mod tests {
 
         // Load example file
         let path = "examples/fib.asm";
-        let src = load_from_file(&path).expect("examples/fib.asm should load correctly");
+        let src = load_from_file(path).expect("examples/fib.asm should load correctly");
 
         // Compile program
         let prg =
@@ -29,4 +30,64 @@ mod tests {
         // Check final state
         assert_eq!(state.regs.r(3).unwrap(), 8);
     }
+
+    #[test]
+    fn test_integration_binary() {
+        let src = std::fs::read_to_string("examples/fib.asm")
+            .expect("examples/fib.asm should load correctly");
+        let prg =
+            compiler::compile_program(&src).expect("examples/fib.asm should compile correctly");
+
+        // Assemble, serialize and reload program
+        let code = assembler::encoder::encode_program(&prg)
+            .expect("examples/fib.asm should encode correctly");
+        let bytes = assembler::binary::Binary::new(code)
+            .to_bytes()
+            .expect("examples/fib.asm should serialize correctly");
+        let bin = assembler::binary::Binary::from_bytes(&bytes)
+            .expect("examples/fib.asm binary should load correctly");
+
+        // Interpret binary
+        let (res, state) = interpreter::interpret_binary(&bin, None);
+        res.expect("examples/fib.asm binary should interpret correctly");
+
+        // Check final state
+        assert_eq!(state.regs.r(3).unwrap(), 8);
+    }
+
+    #[test]
+    fn test_integration_link() {
+        let main = ".extern double, x\nli r1, 21\ncall double\nlb r2, x(r0)\nhalt\n";
+        let lib =
+            ".global double, x\n.data\n.zero 3\nx: .byte 7\n.text\ndouble: add r1, r1, r1\nret\n";
+
+        // Compile and encode objects separately
+        let object = |file: &str, src: &str| {
+            let obj = compiler::compile_object(&[(file, src)], &Default::default())
+                .expect("sources should compile into objects");
+            let code = assembler::encoder::encode_program(&obj.compiled.program)
+                .expect("objects should encode correctly");
+            let obj = assembler::object::Object {
+                code,
+                data: obj.compiled.data,
+                exports: obj.exports,
+                externs: obj.externs,
+                relocations: obj.relocations,
+            };
+            let bytes = obj.to_bytes().expect("objects should serialize correctly");
+            assembler::object::Object::from_bytes(&bytes).expect("objects should load correctly")
+        };
+
+        // Link and interpret
+        let linked = assembler::linker::link(&[
+            ("main.o", object("main.asm", main)),
+            ("lib.o", object("lib.asm", lib)),
+        ])
+        .expect("objects should link correctly");
+        let (res, state) = interpreter::interpret_binary(&linked.binary, None);
+        res.expect("linked binary should interpret correctly");
+
+        assert_eq!(state.regs.r(1).unwrap(), 42);
+        assert_eq!(state.regs.r(2).unwrap(), 7);
+    }
 }
@@ -1,7 +1,7 @@
 use console::style;
-use std::{thread, time::Duration};
+use std::{path::Path, thread, time::Duration};
 
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use indicatif::ProgressBar;
 
 #[derive(Parser)]
@@ -16,13 +16,27 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Compile a given assembly file into bytecode
+    /// Compile given assembly files into a binary, or an object to link
     #[command(alias = "b")]
-    Build(FilePaths),
+    Build(BuildArgs),
 
-    /// Run a given program through the interpreter
+    /// Link given objects into a binary
+    Link(LinkArgs),
+
+    /// Run a given program (source or binary) through the interpreter
     #[command(alias = "r")]
-    Run(FilePaths),
+    Run(RunArgs),
+
+    /// Disassemble a given binary into assembly listing
+    #[command(alias = "dis")]
+    Disasm(FilePaths),
+
+    /// Step through given assembly files in an interactive debugger
+    #[command(alias = "dbg")]
+    Debug(DebugArgs),
+
+    /// Rewrite given assembly files in the canonical format
+    Fmt(FmtArgs),
 }
 
 #[derive(Args)]
@@ -35,30 +49,357 @@ struct FilePaths {
     output: Option<String>,
 }
 
+#[derive(Args)]
+struct SourcePaths {
+    /// Input file paths, assembled one after the other
+    #[arg(required = true)]
+    in_paths: Vec<String>,
+
+    /// Directory to search for included files, after that of the including file
+    #[arg(short = 'I', long = "include", value_name = "DIR")]
+    include_paths: Vec<String>,
+
+    /// Output file path
+    #[arg(short, long)]
+    output: Option<String>,
+}
+
+impl SourcePaths {
+    fn includes(&self) -> cobble::compiler::include::Includes {
+        cobble::compiler::include::Includes::new(
+            self.include_paths.iter().map(|p| p.into()).collect(),
+        )
+    }
+}
+
+#[derive(Args)]
+struct FmtArgs {
+    /// Input file paths
+    #[arg(required = true)]
+    in_paths: Vec<String>,
+
+    /// Directory to search for included files, after that of the including file
+    #[arg(short = 'I', long = "include", value_name = "DIR")]
+    include_paths: Vec<String>,
+
+    /// Only check that files are formatted, exiting with an error if not
+    #[arg(long)]
+    check: bool,
+}
+
+#[derive(Args)]
+struct LinkArgs {
+    /// Input object paths, placed one after the other
+    #[arg(required = true)]
+    in_paths: Vec<String>,
+
+    /// Output file path
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// Memory layout to place sections by
+    #[arg(long, value_name = "FILE")]
+    layout: Option<String>,
+}
+
+#[derive(Args)]
+struct BuildArgs {
+    #[command(flatten)]
+    paths: SourcePaths,
+
+    /// Compile into an object, to be linked with others
+    #[arg(short = 'c')]
+    object: bool,
+
+    /// Format to write the program in
+    #[arg(long, value_enum, default_value_t, conflicts_with = "object")]
+    format: Format,
+
+    /// Write a listing of each source line with the words assembled from it
+    #[arg(long, value_name = "FILE")]
+    listing: Option<String>,
+
+    /// Write a map of every symbol with its address and section
+    #[arg(long, value_name = "FILE")]
+    map: Option<String>,
+}
+
+/// Formats programs can be written in
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// Cobble binary, holding code, data and entry point
+    #[default]
+    Cbl,
+    /// Raw code image, with 3 little-endian bytes per word
+    Raw,
+    /// Intel HEX of the raw code image
+    Ihex,
+    /// Code image for Verilog's `$readmemh`, with one word per line
+    Verilog,
+    /// Logisim "v2.0 raw" code image
+    Logisim,
+}
+
+impl Format {
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Cbl => "cbl",
+            Self::Raw => "bin",
+            Self::Ihex => "hex",
+            Self::Verilog => "mem",
+            Self::Logisim => "img",
+        }
+    }
+}
+
+#[derive(Args)]
+struct DebugArgs {
+    #[command(flatten)]
+    paths: SourcePaths,
+
+    /// Memory layout to map memory by, and to link sources with
+    #[arg(long, value_name = "FILE")]
+    layout: Option<String>,
+}
+
+#[derive(Args)]
+struct RunArgs {
+    #[command(flatten)]
+    paths: SourcePaths,
+
+    /// Stop with an error after executing this many instructions
+    #[arg(long, value_name = "N")]
+    max_steps: Option<u64>,
+
+    /// Stop with an error after running for this many milliseconds
+    #[arg(long, value_name = "MS")]
+    timeout: Option<u64>,
+
+    /// Memory layout to map memory by, and to link sources with
+    #[arg(long, value_name = "FILE")]
+    layout: Option<String>,
+}
+
 fn main() {
     let cli = Cli::parse();
-    match cli.command {
-        Some(Commands::Run(file_paths)) => run_program(&file_paths.in_path),
-        _ => unimplemented!(),
+    let ok = match cli.command {
+        Some(Commands::Build(args)) => {
+            let paths = &args.paths;
+            let out_path = paths.output.clone().unwrap_or_else(|| {
+                Path::new(&paths.in_paths[0])
+                    .with_extension(match args.object {
+                        true => "o",
+                        false => args.format.extension(),
+                    })
+                    .to_string_lossy()
+                    .into_owned()
+            });
+            build_program(&args, &out_path)
+        }
+        Some(Commands::Link(args)) => {
+            let out_path = args.output.clone().unwrap_or_else(|| {
+                Path::new(&args.in_paths[0])
+                    .with_extension("cbl")
+                    .to_string_lossy()
+                    .into_owned()
+            });
+            link_program(&args.in_paths, &out_path, args.layout.as_deref())
+        }
+        Some(Commands::Run(args)) => {
+            let limits = cobble::interpreter::Limits {
+                max_steps: args.max_steps,
+                timeout: args.timeout.map(Duration::from_millis),
+            };
+            run_program(&args.paths, limits, args.layout.as_deref())
+        }
+        Some(Commands::Disasm(file_paths)) => {
+            disasm_program(&file_paths.in_path, file_paths.output.as_deref())
+        }
+        Some(Commands::Debug(args)) => debug_program(&args.paths, args.layout.as_deref()),
+        // Fails for CI when files are not formatted
+        Some(Commands::Fmt(args)) => fmt_programs(&args),
+        None => true,
+    };
+    // Any failure, including running past a limit, exits with an error
+    if !ok {
+        std::process::exit(1);
     }
 }
 
-fn run_program(path: &str) {
+/// Reads the source of each given file, in order
+fn read_sources(paths: &[String]) -> Result<Vec<(&str, String)>, String> {
+    paths
+        .iter()
+        .map(|p| {
+            std::fs::read_to_string(p)
+                .map(|src| (p.as_str(), src))
+                .map_err(|e| format!("{}: {}", p, e))
+        })
+        .collect()
+}
+
+/// Reads the memory layout at a given path, or the default one
+fn read_layout(path: Option<&str>) -> Result<cobble::assembler::layout::Layout, String> {
+    use cobble::assembler::layout::Layout;
+
+    match path {
+        Some(p) => std::fs::read_to_string(p)
+            .map_err(|e| e.to_string())
+            .and_then(|src| Layout::parse(&src).map_err(|e| e.to_string()))
+            .map_err(|e| format!("{}: {}", p, e)),
+        None => Ok(Layout::default()),
+    }
+}
+
+/// Compiles given sources, in order, into a single program
+fn compile_sources(
+    sources: &[(&str, String)],
+    paths: &SourcePaths,
+) -> Result<cobble::compiler::Compiled, cobble::compiler::diagnostic::Diagnostics> {
+    let sources: Vec<(&str, &str)> = sources.iter().map(|(p, s)| (*p, s.as_str())).collect();
+    cobble::compiler::compile_sources(&sources, &paths.includes())
+}
+
+fn debug_program(paths: &SourcePaths, layout_path: Option<&str>) -> bool {
+    use cobble::debugger::Debugger;
+
+    let read =
+        read_sources(&paths.in_paths).and_then(|sources| Ok((sources, read_layout(layout_path)?)));
+    let (sources, layout) = match read {
+        Ok(r) => r,
+        Err(e) => {
+            println!("{} while reading: {}", style("Error").red().bold(), e);
+            return false;
+        }
+    };
+
+    let dbg = match layout_path {
+        // Sources are only placed by a layout once linked
+        Some(_) => link_sources(&sources, paths, &layout)
+            .map(|(compiled, _)| Debugger::with_layout(compiled, &layout)),
+        None => compile_sources(&sources, paths)
+            .map(Debugger::from_compiled)
+            .map_err(|e| e.to_string()),
+    };
+    let mut dbg = match dbg {
+        Ok(d) => d,
+        Err(e) => {
+            println!("{} while compiling\n{}", style("Error").red().bold(), e);
+            return false;
+        }
+    };
+
+    if let Err(e) = dbg.repl(std::io::stdin().lock(), std::io::stdout()) {
+        println!("{} {}", style("Error").red().bold(), e);
+        return false;
+    }
+    true
+}
+
+/// A compiled program, ready to be encoded
+enum Output {
+    Binary(cobble::compiler::Compiled),
+    Object(cobble::compiler::CompiledObject),
+}
+
+impl Output {
+    fn compiled(&self) -> &cobble::compiler::Compiled {
+        match self {
+            Self::Binary(c) => c,
+            Self::Object(o) => &o.compiled,
+        }
+    }
+}
+
+/// Renders the listing and symbol map of a compiled program,
+/// for those given a path to be written to
+fn render_reports<'a>(
+    compiled: &cobble::compiler::Compiled,
+    args: &'a BuildArgs,
+) -> Result<Vec<(&'a str, String)>, String> {
+    use cobble::assembler::{
+        encoder::encode_program,
+        listing::{listing, symbol_map},
+    };
+
+    let mut reports = Vec::new();
+    if let Some(p) = &args.listing {
+        let code = encode_program(&compiled.program).map_err(|e| e.to_string())?;
+        reports.push((p.as_str(), listing(&code, &compiled.source_map)));
+    }
+    if let Some(p) = &args.map {
+        reports.push((p.as_str(), symbol_map(&compiled.symbols, &compiled.labels)));
+    }
+    Ok(reports)
+}
+
+/// Encodes a compiled object
+fn encode_object(
+    o: cobble::compiler::CompiledObject,
+) -> Result<cobble::assembler::object::Object, String> {
+    let code = cobble::assembler::encoder::encode_program(&o.compiled.program)
+        .map_err(|e| e.to_string())?;
+    Ok(cobble::assembler::object::Object {
+        code,
+        data: o.compiled.data,
+        exports: o.exports,
+        externs: o.externs,
+        relocations: o.relocations,
+    })
+}
+
+/// Encodes a compiled program into the bytes of an object,
+/// or of a binary in a given format
+fn encode_output(output: Output, format: Format) -> Result<Vec<u8>, String> {
+    use cobble::assembler::{binary::Binary, encoder::encode_program, image};
+
+    match output {
+        Output::Binary(c) => {
+            let code = encode_program(&c.program).map_err(|e| e.to_string())?;
+            if format != Format::Cbl && !c.data.is_empty() {
+                return Err(format!(
+                    "the {} format only holds code, so cannot hold the data section",
+                    format
+                        .to_possible_value()
+                        .expect("formats are not skipped")
+                        .get_name()
+                ));
+            }
+            match format {
+                Format::Cbl => Binary {
+                    data: c.data,
+                    ..Binary::new(code)
+                }
+                .to_bytes()
+                .map_err(|e| e.to_string()),
+                Format::Raw => Ok(image::raw(&code)),
+                Format::Ihex => Ok(image::intel_hex(&code).into_bytes()),
+                Format::Verilog => Ok(image::readmemh(&code).into_bytes()),
+                Format::Logisim => Ok(image::logisim(&code).into_bytes()),
+            }
+        }
+        Output::Object(o) => encode_object(o)?.to_bytes().map_err(|e| e.to_string()),
+    }
+}
+
+fn build_program(args: &BuildArgs, out_path: &str) -> bool {
+    let paths = &args.paths;
+    let path = paths.in_paths.join(", ");
     let pb = ProgressBar::new_spinner();
     pb.enable_steady_tick(Duration::from_millis(100));
 
-    // Load source file
-    pb.set_message(format!("{} Reading {}", style("[1/3]").bold().dim(), path));
-    let src = match std::fs::read_to_string(path) {
+    // Load source files
+    pb.set_message(format!("{} Reading {}", style("[1/4]").bold().dim(), path));
+    let sources = match read_sources(&paths.in_paths) {
         Ok(s) => s,
         Err(e) => {
             pb.finish_with_message(format!(
                 "{} {} while reading",
-                style("[1/3]").bold().dim(),
+                style("[1/4]").bold().dim(),
                 style("Error").red().bold(),
             ));
             println!("{}", e);
-            return;
+            return false;
         }
     };
     thread::sleep(Duration::from_millis(250));
@@ -66,19 +407,339 @@ fn run_program(path: &str) {
     // Compile program
     pb.set_message(format!(
         "{} Compiling {}",
-        style("[2/3]").bold().dim(),
+        style("[2/4]").bold().dim(),
         path
     ));
-    let prg = match cobble::compiler::compile_program(&src) {
-        Ok(p) => p,
+    let compiled = match args.object {
+        true => {
+            let sources: Vec<(&str, &str)> =
+                sources.iter().map(|(p, s)| (*p, s.as_str())).collect();
+            cobble::compiler::compile_object(&sources, &paths.includes()).map(Output::Object)
+        }
+        false => compile_sources(&sources, paths).map(Output::Binary),
+    };
+    let compiled = match compiled {
+        Ok(c) => c,
         Err(e) => {
             pb.finish_with_message(format!(
                 "{} {} while compiling",
+                style("[2/4]").bold().dim(),
+                style("Error").red().bold(),
+            ));
+            println!("{}", e);
+            return false;
+        }
+    };
+    thread::sleep(Duration::from_millis(250));
+
+    // Encode program
+    pb.set_message(format!("{} Encoding {}", style("[3/4]").bold().dim(), path));
+    let encoded = render_reports(compiled.compiled(), args)
+        .and_then(|reports| Ok((encode_output(compiled, args.format)?, reports)));
+    let (bytes, reports) = match encoded {
+        Ok(b) => b,
+        Err(e) => {
+            pb.finish_with_message(format!(
+                "{} {} while encoding",
+                style("[3/4]").bold().dim(),
+                style("Error").red().bold(),
+            ));
+            println!("{}", e);
+            return false;
+        }
+    };
+    thread::sleep(Duration::from_millis(250));
+
+    // Write binary, along with any listing or map
+    pb.set_message(format!(
+        "{} Writing {}",
+        style("[4/4]").bold().dim(),
+        out_path
+    ));
+    let written = std::fs::write(out_path, bytes).and_then(|_| {
+        reports
+            .iter()
+            .try_for_each(|(p, report)| std::fs::write(p, report))
+    });
+    if let Err(e) = written {
+        pb.finish_with_message(format!(
+            "{} {} while writing",
+            style("[4/4]").bold().dim(),
+            style("Error").red().bold(),
+        ));
+        println!("{}", e);
+        return false;
+    }
+
+    pb.finish_with_message(format!(
+        "{} {}",
+        style("[4/4]").bold().dim(),
+        style("Done").green().bold()
+    ));
+    true
+}
+
+fn link_program(paths: &[String], out_path: &str, layout_path: Option<&str>) -> bool {
+    use cobble::assembler::{linker::link_with, object::Object};
+
+    let path = paths.join(", ");
+    let pb = ProgressBar::new_spinner();
+    pb.enable_steady_tick(Duration::from_millis(100));
+
+    // Load objects and layout
+    pb.set_message(format!("{} Reading {}", style("[1/3]").bold().dim(), path));
+    let objects = paths
+        .iter()
+        .map(|p| {
+            std::fs::read(p)
+                .map_err(|e| e.to_string())
+                .and_then(|b| Object::from_bytes(&b).map_err(|e| e.to_string()))
+                .map(|obj| (p.as_str(), obj))
+                .map_err(|e| format!("{}: {}", p, e))
+        })
+        .collect::<Result<Vec<_>, _>>();
+    let (objects, layout) = match objects.and_then(|o| Ok((o, read_layout(layout_path)?))) {
+        Ok(o) => o,
+        Err(e) => {
+            pb.finish_with_message(format!(
+                "{} {} while reading",
+                style("[1/3]").bold().dim(),
+                style("Error").red().bold(),
+            ));
+            println!("{}", e);
+            return false;
+        }
+    };
+    thread::sleep(Duration::from_millis(250));
+
+    // Link objects into a binary
+    pb.set_message(format!("{} Linking {}", style("[2/3]").bold().dim(), path));
+    let bytes = match link_with(&objects, &layout) {
+        Ok(linked) => linked.binary.to_bytes().map_err(|e| vec![e.to_string()]),
+        Err(errors) => Err(errors.iter().map(|e| e.to_string()).collect()),
+    };
+    let bytes = match bytes {
+        Ok(b) => b,
+        Err(errors) => {
+            pb.finish_with_message(format!(
+                "{} {} while linking",
                 style("[2/3]").bold().dim(),
                 style("Error").red().bold(),
             ));
+            for e in errors {
+                println!("{}: {}", style("error").red().bold(), e);
+            }
+            return false;
+        }
+    };
+    thread::sleep(Duration::from_millis(250));
+
+    // Write binary
+    pb.set_message(format!(
+        "{} Writing {}",
+        style("[3/3]").bold().dim(),
+        out_path
+    ));
+    if let Err(e) = std::fs::write(out_path, bytes) {
+        pb.finish_with_message(format!(
+            "{} {} while writing",
+            style("[3/3]").bold().dim(),
+            style("Error").red().bold(),
+        ));
+        println!("{}", e);
+        return false;
+    }
+
+    pb.finish_with_message(format!(
+        "{} {}",
+        style("[3/3]").bold().dim(),
+        style("Done").green().bold()
+    ));
+    true
+}
+
+fn disasm_program(path: &str, out_path: Option<&str>) -> bool {
+    use cobble::assembler::{binary::Binary, decoder::decode};
+
+    let bin = match std::fs::read(path)
+        .map_err(|e| e.to_string())
+        .and_then(|b| Binary::from_bytes(&b).map_err(|e| e.to_string()))
+    {
+        Ok(b) => b,
+        Err(e) => {
+            println!("{} {}", style("Error").red().bold(), e);
+            return false;
+        }
+    };
+
+    // Format one line per word: address, raw word, instruction
+    let mut listing = String::new();
+    for (addr, word) in bin.code.iter().enumerate() {
+        let text = match decode(*word) {
+            Ok(instr) => instr.to_string(),
+            Err(e) => format!("; {}", e),
+        };
+        let marker = if addr == bin.entry as usize { ">" } else { " " };
+        listing += &format!("{}{:03x}:  {:06x}  {}\n", marker, addr, word, text);
+    }
+
+    // Data follows as rows of bytes, by memory address
+    if !bin.data.is_empty() {
+        listing += "\n.data\n";
+    }
+    for (i, row) in bin.data.chunks(8).enumerate() {
+        let bytes: Vec<String> = row.iter().map(|b| format!("{:#04x}", b)).collect();
+        listing += &format!(" {:03x}:  .byte {}\n", i * 8, bytes.join(", "));
+    }
+
+    match out_path {
+        Some(p) => {
+            if let Err(e) = std::fs::write(p, listing) {
+                println!("{} {}", style("Error").red().bold(), e);
+                return false;
+            }
+        }
+        None => print!("{}", listing),
+    }
+    true
+}
+
+/// A program loaded for interpretation
+enum Executable {
+    Source(cobble::compiler::Compiled),
+    Binary(cobble::assembler::binary::Binary),
+}
+
+/// Compiles given sources into an object, then links it by a given layout.
+/// Returns the binary, along with the program as placed in it.
+fn link_sources(
+    sources: &[(&str, String)],
+    paths: &SourcePaths,
+    layout: &cobble::assembler::layout::Layout,
+) -> Result<
+    (
+        cobble::compiler::Compiled,
+        cobble::assembler::binary::Binary,
+    ),
+    String,
+> {
+    use cobble::{assembler::decoder::decode_program, compiler::symbol::Section};
+
+    let sources: Vec<(&str, &str)> = sources.iter().map(|(p, s)| (*p, s.as_str())).collect();
+    let object =
+        cobble::compiler::compile_object(&sources, &paths.includes()).map_err(|e| e.to_string())?;
+    let mut compiled = object.compiled.clone();
+    let object = encode_object(object)?;
+    let binary =
+        cobble::assembler::linker::link_with(&[(paths.in_paths[0].as_str(), object)], layout)
+            .map(|linked| linked.binary)
+            .map_err(|errors| {
+                let errors: Vec<String> = errors.iter().map(|e| format!("error: {}", e)).collect();
+                errors.join("\n")
+            })?;
+
+    // Labels and source lines move along with their section
+    compiled.program = decode_program(&binary.code).map_err(|e| e.to_string())?;
+    compiled.data = binary.data.clone();
+    for (name, address) in compiled.symbols.iter_mut() {
+        if let Some(section) = compiled.labels.get(name) {
+            *address = address.wrapping_add(layout.region(*section).start);
+        }
+    }
+    compiled
+        .source_map
+        .shift(layout.region(Section::Text).start);
+    Ok((compiled, binary))
+}
+
+fn run_program(
+    paths: &SourcePaths,
+    limits: cobble::interpreter::Limits,
+    layout_path: Option<&str>,
+) -> bool {
+    use cobble::{
+        assembler::binary::{Binary, is_binary},
+        interpreter::state::State,
+    };
+
+    let path = paths.in_paths.join(", ");
+    let pb = ProgressBar::new_spinner();
+    pb.enable_steady_tick(Duration::from_millis(100));
+
+    // Load input files and layout
+    pb.set_message(format!("{} Reading {}", style("[1/3]").bold().dim(), path));
+    let inputs = paths
+        .in_paths
+        .iter()
+        .map(|p| {
+            std::fs::read(p)
+                .map(|b| (p.as_str(), b))
+                .map_err(|e| format!("{}: {}", p, e))
+        })
+        .collect::<Result<Vec<_>, _>>();
+    let (inputs, layout) = match inputs.and_then(|i| Ok((i, read_layout(layout_path)?))) {
+        Ok(i) => i,
+        Err(e) => {
+            pb.finish_with_message(format!(
+                "{} {} while reading",
+                style("[1/3]").bold().dim(),
+                style("Error").red().bold(),
+            ));
+            println!("{}", e);
+            return false;
+        }
+    };
+    thread::sleep(Duration::from_millis(250));
+
+    // Load binary, or compile program from sources
+    let (exe, verb) = if inputs.iter().any(|(_, b)| is_binary(b)) {
+        pb.set_message(format!("{} Loading {}", style("[2/3]").bold().dim(), path));
+        (
+            match inputs.as_slice() {
+                [(_, bytes)] => Binary::from_bytes(bytes)
+                    .map(Executable::Binary)
+                    .map_err(|e| e.to_string()),
+                _ => Err("a binary cannot be run along with other files".to_string()),
+            },
+            "loading",
+        )
+    } else {
+        pb.set_message(format!(
+            "{} Compiling {}",
+            style("[2/3]").bold().dim(),
+            path
+        ));
+        (
+            inputs
+                .into_iter()
+                .map(|(p, b)| {
+                    String::from_utf8(b)
+                        .map(|src| (p, src))
+                        .map_err(|e| format!("{}: {}", p, e))
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .and_then(|sources| match layout_path {
+                    // Sources are only placed by a layout once linked
+                    Some(_) => link_sources(&sources, paths, &layout)
+                        .map(|(_, binary)| Executable::Binary(binary)),
+                    None => compile_sources(&sources, paths)
+                        .map(Executable::Source)
+                        .map_err(|e| e.to_string()),
+                }),
+            "compiling",
+        )
+    };
+    let exe = match exe {
+        Ok(x) => x,
+        Err(e) => {
+            pb.finish_with_message(format!(
+                "{} {} while {}",
+                style("[2/3]").bold().dim(),
+                style("Error").red().bold(),
+                verb,
+            ));
             println!("{}", e);
-            return;
+            return false;
         }
     };
     thread::sleep(Duration::from_millis(250));
@@ -89,7 +750,28 @@ fn run_program(path: &str) {
         style("[3/3]").bold().dim(),
         path
     ));
-    let (res, state) = cobble::interpreter::interpret_program(prg, None);
+    let mut state = State::with_layout(&layout);
+    let (res, state) = match exe {
+        Executable::Source(ref c) => {
+            state
+                .mem
+                .load(0, &c.data)
+                .expect("compiled data should fit in memory");
+            cobble::interpreter::interpret_program_with_limits(
+                c.program.clone(),
+                Some(state),
+                limits,
+            )
+        }
+        Executable::Binary(ref bin) => {
+            state
+                .mem
+                .load(0, &bin.data)
+                .expect("binary data should fit in memory");
+            state.pc = bin.entry;
+            cobble::interpreter::interpret_binary_with_limits(bin, Some(state), limits)
+        }
+    };
     if let Err(e) = res {
         pb.finish_with_message(format!(
             "{} {} while interpreting",
@@ -97,7 +779,13 @@ fn run_program(path: &str) {
             style("Error").red().bold(),
         ));
         println!("{}", e);
-        return;
+        // Point at the offending source line, if known
+        if let Executable::Source(c) = exe
+            && let Some(snippet) = c.source_map.snippet(state.pc)
+        {
+            println!("{}", snippet);
+        }
+        return false;
     }
     thread::sleep(Duration::from_millis(250));
 
@@ -108,6 +796,53 @@ fn run_program(path: &str) {
     ));
 
     println!("{}", state);
+    true
+}
+
+/// Formats given files in place, or checks that they are formatted.
+/// Returns whether all of them could be formatted, and were already when checking.
+fn fmt_programs(args: &FmtArgs) -> bool {
+    use cobble::compiler::{format::format_source, include::Includes};
+
+    let includes = Includes::new(args.include_paths.iter().map(|p| p.into()).collect());
+    let mut ok = true;
+    for path in &args.in_paths {
+        let src = match std::fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) => {
+                println!("{} {}: {}", style("Error").red().bold(), path, e);
+                ok = false;
+                continue;
+            }
+        };
+        let formatted = match format_source(path, &src, &includes) {
+            Ok(f) => f,
+            Err(e) => {
+                println!(
+                    "{} while parsing {}\n{}",
+                    style("Error").red().bold(),
+                    path,
+                    e
+                );
+                ok = false;
+                continue;
+            }
+        };
+        if formatted == src {
+            continue;
+        }
+
+        if args.check {
+            println!("{} {}", style("Would reformat").yellow().bold(), path);
+            ok = false;
+        } else if let Err(e) = std::fs::write(path, formatted) {
+            println!("{} {}: {}", style("Error").red().bold(), path, e);
+            ok = false;
+        } else {
+            println!("{} {}", style("Formatted").green().bold(), path);
+        }
+    }
+    ok
 }
 
 #[cfg(test)]